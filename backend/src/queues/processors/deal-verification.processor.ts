@@ -1,7 +1,11 @@
 import { Processor, WorkerHost, OnWorkerEvent } from '@nestjs/bullmq';
 import { Job } from 'bullmq';
 import { Logger, Inject, forwardRef } from '@nestjs/common';
-import { RustService, ConsensusResponse } from '../../rust/rust.service';
+import {
+  RustService,
+  ConsensusResponse,
+  DealPendingError,
+} from '../../rust/rust.service';
 import { PrismaClient } from '@prisma/client';
 import { WebsocketGateway } from '../../websocket/websocket.gateway';
 import { RedisService } from '../../redis/redis.service';
@@ -42,7 +46,7 @@ export class DealVerificationProcessor extends WorkerHost {
   }
 
   async process(job: Job<DealVerificationJob>): Promise<DealVerificationResult> {
-    const { dealId, nftId, buyerAddress, sellerAddress, price } = job.data;
+    const { dealId, buyerAddress, sellerAddress, price } = job.data;
 
     this.logger.log(`Processing deal verification for deal ${dealId}`);
 
@@ -151,14 +155,25 @@ export class DealVerificationProcessor extends WorkerHost {
         message: 'Running BFT consensus...',
       });
 
-      // Step 4: Run BFT consensus via Rust service
+      // Spending policies of the buyer agent and its owner apply to the deal
+      const spender = {
+        agentId: deal.buyerAgentId,
+        ownerId: deal.buyerAgent.userId,
+      };
+
+      // Step 4: Run BFT consensus via Rust service. The verifiers check the seller's
+      // holdings on chain themselves.
       this.logger.log(`Running BFT consensus for deal ${dealId}`);
-      const consensusResult = await this.rustService.runConsensus(
+      const consensusResult = await this.rustService.runConsensus({
         dealId,
-        nftOwnership,
+        nftId: `${deal.nft.collection}#${deal.nft.tokenId}`,
+        sellerAddress,
+        buyerAddress,
         buyerBalance,
+        price,
         signatures,
-      );
+        spender,
+      });
 
       // Step 5: Update deal status in database
       await this.prisma.deal.update({
@@ -191,16 +206,37 @@ export class DealVerificationProcessor extends WorkerHost {
         message: 'Executing blockchain transaction...',
       });
 
-      // Step 6: Execute escrow if consensus approved
+      // Step 6: Execute escrow if consensus approved. A transaction not confirmed in
+      // time is awaited through the deal's timeline by the Rust service client.
       this.logger.log(`Consensus approved, executing escrow for deal ${dealId}`);
       const escrowResult = await this.rustService.executeEscrow(
         dealId,
         buyerAddress,
         sellerAddress,
-        nftId,
+        `${deal.nft.collection}#${deal.nft.tokenId}`,
         price,
+        spender,
       );
 
+      if (!escrowResult.success) {
+        this.logger.warn(
+          `Escrow transaction reverted for deal ${dealId}: tx_hash=${escrowResult.txHash}`,
+        );
+        await this.prisma.deal.update({
+          where: { id: dealId },
+          data: { status: 'failed', txHash: escrowResult.txHash },
+        });
+        return {
+          success: false,
+          dealId,
+          verified: true,
+          consensusResult,
+          txHash: escrowResult.txHash,
+          blockNumber: escrowResult.blockNumber,
+          error: 'Escrow transaction reverted',
+        };
+      }
+
       // Step 7: Update deal with transaction details and set agents to completed
       await this.prisma.deal.update({
         where: { id: dealId },
@@ -277,6 +313,12 @@ export class DealVerificationProcessor extends WorkerHost {
         blockNumber: escrowResult.blockNumber,
       };
     } catch (error) {
+      if (error instanceof DealPendingError) {
+        // The transaction may still be mined and the deal settled; leave it verifying
+        this.logger.warn(error.message);
+        throw error;
+      }
+
       this.logger.error(
         `Failed to verify deal ${dealId}: ${error.message}`,
         error.stack,
@@ -14,11 +14,22 @@ export interface VerifySignatureResponse {
   error?: string;
 }
 
+/** Buyer agent of a deal and the user who spawned it, checked against spending policies */
+export interface Spender {
+  agentId: string;
+  ownerId: string;
+}
+
 export interface ConsensusRequest {
   dealId: string;
-  nftOwnership: boolean;
+  /** `collection#tokenId` of the NFT; the verifiers check the seller holds it */
+  nftId: string;
+  sellerAddress: string;
+  buyerAddress: string;
   buyerBalance: number;
+  price: number;
   signatures: string[];
+  spender: Spender;
 }
 
 export interface VerifierChecks {
@@ -56,6 +67,44 @@ export interface EscrowResponse {
   blockNumber: number;
 }
 
+/**
+ * Escrow whose transaction was submitted but not confirmed in time (HTTP 202).
+ * The Rust service settles it once the transaction resolves.
+ */
+interface PendingDealResponse {
+  deal_id: string;
+  chain: string;
+  status: 'pending';
+  tx_hash: string;
+}
+
+/** Escrow record of a deal, as returned by `/deals/{id}/timeline` */
+interface EscrowRecord {
+  tx_hash: string | null;
+  block_number: number;
+  status: 'pending' | 'success' | 'reverted' | 'dropped' | 'replaced';
+  failure?: string;
+}
+
+interface DealTimelineResponse {
+  deal_id: string;
+  record: EscrowRecord | null;
+}
+
+/**
+ * Thrown when an escrow's transaction is still pending after the polling deadline.
+ * The deal may yet settle, so it must not be treated as failed.
+ */
+export class DealPendingError extends Error {
+  constructor(
+    readonly dealId: string,
+    readonly txHash: string,
+  ) {
+    super(`Escrow for deal ${dealId} still pending (tx_hash=${txHash})`);
+    this.name = 'DealPendingError';
+  }
+}
+
 export interface NftOwnershipRequest {
   collection: string;
   tokenId: string;
@@ -84,10 +133,14 @@ export class RustService {
   private readonly appLogger: AppLoggerService;
   private readonly client: AxiosInstance;
   private readonly serviceUrl: string;
+  private readonly dealPollIntervalMs: number;
+  private readonly dealPollTimeoutMs: number;
 
   constructor() {
     this.appLogger = new AppLoggerService(RustService.name);
     this.serviceUrl = process.env.RUST_SERVICE_URL || 'http://localhost:8080';
+    this.dealPollIntervalMs = Number(process.env.RUST_DEAL_POLL_INTERVAL_MS) || 5000;
+    this.dealPollTimeoutMs = Number(process.env.RUST_DEAL_POLL_TIMEOUT_MS) || 600000;
     this.client = axios.create({
       baseURL: this.serviceUrl,
       timeout: 10000, // 10 second timeout
@@ -141,25 +194,35 @@ export class RustService {
   /**
    * Run BFT consensus for deal verification
    */
-  async runConsensus(
-    dealId: string,
-    nftOwnership: boolean,
-    buyerBalance: number,
-    signatures: string[],
-  ): Promise<ConsensusResponse> {
+  async runConsensus(request: ConsensusRequest): Promise<ConsensusResponse> {
+    const {
+      dealId,
+      nftId,
+      sellerAddress,
+      buyerAddress,
+      buyerBalance,
+      price,
+      signatures,
+      spender,
+    } = request;
     const endpoint = `${this.serviceUrl}/run-consensus`;
     try {
       this.logger.log(
-        `Running BFT consensus for deal ${dealId} (NFT: ${nftOwnership}, Balance: ${buyerBalance}, Sigs: ${signatures.length})`,
+        `Running BFT consensus for deal ${dealId} (NFT: ${nftId}, Balance: ${buyerBalance}, Sigs: ${signatures.length})`,
       );
 
       const response = await this.client.post<ConsensusResponse>(
         '/run-consensus',
         {
           deal_id: dealId,
-          nft_ownership: nftOwnership,
+          nft_id: nftId,
+          seller_address: sellerAddress,
+          buyer_address: buyerAddress,
           buyer_balance: buyerBalance,
+          price,
           signatures,
+          agent_id: spender.agentId,
+          owner_id: spender.ownerId,
         },
       );
 
@@ -203,7 +266,11 @@ export class RustService {
   }
 
   /**
-   * Execute escrow transaction on blockchain
+   * Execute escrow transaction on blockchain.
+   *
+   * If the transaction is not confirmed in time the Rust service answers 202 and
+   * settles the deal in the background; the deal's timeline is then polled until
+   * its escrow record leaves the pending state.
    */
   async executeEscrow(
     dealId: string,
@@ -211,6 +278,7 @@ export class RustService {
     sellerAddress: string,
     nftId: string,
     price: number,
+    spender: Spender,
   ): Promise<EscrowResponse> {
     const endpoint = `${this.serviceUrl}/execute-escrow`;
     try {
@@ -218,7 +286,7 @@ export class RustService {
         `Executing escrow for deal ${dealId}: ${nftId} from ${sellerAddress} to ${buyerAddress} for ${price} USDC`,
       );
 
-      const response = await this.client.post<EscrowResponse>(
+      const response = await this.client.post(
         '/execute-escrow',
         {
           deal_id: dealId,
@@ -226,15 +294,30 @@ export class RustService {
           seller_address: sellerAddress,
           nft_id: nftId,
           price,
+          agent_id: spender.agentId,
+          owner_id: spender.ownerId,
         },
+        { validateStatus: (status) => status === 200 || status === 202 },
       );
 
+      const result =
+        response.status === 202
+          ? await this.awaitPendingDeal(response.data as PendingDealResponse)
+          : {
+              success: response.data.success,
+              txHash: response.data.tx_hash,
+              blockNumber: response.data.block_number,
+            };
+
       this.logger.log(
-        `Escrow executed: tx_hash=${response.data.txHash}, block=${response.data.blockNumber}`,
+        `Escrow executed: success=${result.success}, tx_hash=${result.txHash}, block=${result.blockNumber}`,
       );
 
-      return response.data;
+      return result;
     } catch (error) {
+      if (error instanceof DealPendingError) {
+        throw error;
+      }
       if (error instanceof Error) {
         const logContext: LogContext = {
           dealId,
@@ -255,6 +338,42 @@ export class RustService {
     }
   }
 
+  /**
+   * Poll the timeline of a deal whose escrow is pending until its record resolves
+   */
+  private async awaitPendingDeal(
+    pending: PendingDealResponse,
+  ): Promise<EscrowResponse> {
+    const { deal_id: dealId, tx_hash: txHash } = pending;
+    this.logger.warn(
+      `Escrow for deal ${dealId} not confirmed yet (tx_hash=${txHash}), polling its timeline`,
+    );
+
+    const deadline = Date.now() + this.dealPollTimeoutMs;
+    while (Date.now() < deadline) {
+      await new Promise((resolve) => setTimeout(resolve, this.dealPollIntervalMs));
+
+      const response = await this.client.get<DealTimelineResponse>(
+        `/deals/${encodeURIComponent(dealId)}/timeline`,
+      );
+      const record = response.data.record;
+      if (!record || record.status === 'pending') {
+        continue;
+      }
+      if (record.status !== 'success' && record.status !== 'reverted') {
+        throw new Error(
+          `Escrow transaction ${record.tx_hash ?? txHash} ${record.status}: ${record.failure ?? 'no receipt'}`,
+        );
+      }
+      return {
+        success: record.status === 'success',
+        txHash: record.tx_hash ?? txHash,
+        blockNumber: record.block_number,
+      };
+    }
+    throw new DealPendingError(dealId, txHash);
+  }
+
   /**
    * Query NFT ownership on ARK Network
    */
@@ -1,109 +1,344 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+use crate::chains::ChainConfig;
+use crate::config::Config;
+use crate::fees::{fee_in_native, FeePolicy};
+use crate::nft::NftRef;
+use crate::settlement::Payout;
+use crate::simulator::SimulatedChain;
+use crate::tokens::{TokenAmount, TokenInfo, TokenRegistry};
+use crate::transactions::{PendingTransaction, StuckTxPolicy};
+use crate::wallet::{
+    ChainAccount, ContractCall, HotWallet, SignedTransaction, UnsignedTransaction,
+};
+
 #[derive(Error, Debug)]
 pub enum ArkError {
-    #[error("HTTP request failed: {0}")]
-    HttpError(#[from] reqwest::Error),
-    #[error("NFT not found or not owned by address")]
-    NftNotOwned,
-    #[error("Insufficient balance: has {has} USDC, needs {needs} USDC")]
-    InsufficientBalance { has: f64, needs: f64 },
+    #[error("Insufficient balance: has {has} {token}, needs {needs} {token}")]
+    InsufficientBalance { token: String, has: f64, needs: f64 },
     #[error("Transaction failed: {0}")]
     TransactionFailed(String),
-    #[error("Confirmation timeout")]
-    ConfirmationTimeout,
+    #[error("Transaction {tx_hash} was not confirmed in time and may still be mined")]
+    ConfirmationTimeout { tx_hash: String },
+    #[error("Transaction not found: {0}")]
+    TransactionNotFound(String),
+    #[error(
+        "Transaction {tx_hash} was dropped by a chain reorganization (was in block {block_number})"
+    )]
+    TransactionReorged { tx_hash: String, block_number: u64 },
+    #[error("Transaction {0} was cancelled before it was mined")]
+    TransactionCancelled(String),
     #[error("Configuration error: {0}")]
     ConfigError(String),
+    #[error("Invalid NFT reference: {0}")]
+    InvalidNft(String),
+    #[error("Invalid token amount: {0}")]
+    InvalidToken(String),
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("Unknown chain: {0}")]
+    UnknownChain(String),
+    #[error("Invalid spending policy: {0}")]
+    InvalidPolicy(String),
+    #[error("Mandate rejected: {0}")]
+    InvalidMandate(String),
+    #[error("Hold conflict: {0}")]
+    HoldConflict(String),
+    #[error("Deal conflict: {0}")]
+    DealConflict(String),
+    #[error("{nft} is locked by deal {deal_id}")]
+    NftLocked { nft: String, deal_id: String },
+    #[error("Ledger error: {0}")]
+    LedgerError(String),
+    #[error("Storage error: {0}")]
+    StorageError(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct NftOwnershipQuery {
-    pub collection: String,
-    pub token_id: String,
-    pub owner_address: String,
+impl ArkError {
+    /// Hash of the transaction the error is about, for errors raised after it was
+    /// broadcast
+    pub fn tx_hash(&self) -> Option<&str> {
+        match self {
+            Self::ConfirmationTimeout { tx_hash }
+            | Self::TransactionReorged { tx_hash, .. }
+            | Self::TransactionCancelled(tx_hash) => Some(tx_hash),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct NftOwnershipResponse {
-    pub owned: bool,
-    pub collection: String,
-    pub token_id: String,
-    pub owner: String,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EscrowTransaction {
+    pub buyer_address: String,
+    pub seller_address: String,
+    /// Every NFT moves to the buyer, or none does
+    pub nfts: Vec<NftRef>,
+    /// Paid by the buyer in any registered token
+    pub price: TokenAmount,
+    /// Recipients of the price; the contract reverts unless they add up to it
+    pub payouts: Vec<Payout>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct BalanceQuery {
+/// Assets one party of a swap hands over
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SwapSide {
     pub address: String,
-    pub token: String, // "USDC"
+    #[serde(default)]
+    pub nfts: Vec<NftRef>,
+    #[serde(default)]
+    pub tokens: Vec<TokenAmount>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct BalanceResponse {
-    pub address: String,
-    pub token: String,
-    pub balance: f64,
+/// Exchange of arbitrary assets between two parties: both sides move in one
+/// transaction or neither does
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SwapTransaction {
+    /// Party that proposed the trade
+    pub maker: SwapSide,
+    /// Party that accepted it
+    pub taker: SwapSide,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct EscrowTransaction {
-    pub buyer_address: String,
-    pub seller_address: String,
-    pub nft_collection: String,
-    pub nft_token_id: String,
-    pub price_usdc: f64,
+/// Lifecycle status of a transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStatus {
+    /// Known to the network but not mined yet
+    Pending,
+    /// Mined and executed
+    Success,
+    /// Mined but execution failed; the fee is still paid
+    Reverted,
+    /// Orphaned by a reorg and not included again
+    Dropped,
+    /// Superseded by another transaction with the same nonce
+    Replaced,
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TxStatus::Pending => "pending",
+            TxStatus::Success => "success",
+            TxStatus::Reverted => "reverted",
+            TxStatus::Dropped => "dropped",
+            TxStatus::Replaced => "replaced",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Decoded event emitted by a mined transaction
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TransactionLog {
+    /// ERC-721 `Transfer` or ERC-1155 `TransferSingle`
+    NftTransfer {
+        nft: NftRef,
+        from: String,
+        to: String,
+    },
+    /// ERC-20 `Transfer(from, to, value)`, with the value in whole tokens
+    TokenTransfer {
+        token: String,
+        from: String,
+        to: String,
+        amount: f64,
+    },
+}
+
+/// An event log with the transaction and block that emitted it
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockLog {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub log: TransactionLog,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionReceipt {
     pub tx_hash: String,
     pub block_number: u64,
-    pub status: String,
+    pub block_hash: String,
+    /// Unix timestamp of the block
+    pub timestamp: i64,
+    pub status: TxStatus,
     pub confirmations: u32,
     pub gas_used: u64,
+    pub effective_gas_price_gwei: u64,
+    /// Fee charged for the transaction, in native tokens
+    pub fee_paid: f64,
+    /// Fee charged for the transaction, in USDC at the fee policy's token price
+    pub fee_paid_usdc: f64,
+    /// Tokens moved between the parties, per token
+    pub value: Vec<TokenAmount>,
+    pub logs: Vec<TransactionLog>,
+    pub revert_reason: Option<String>,
 }
 
-/// ARK Network testnet client
+/// Client for one chain's RPC backend (ARK testnet unless routed elsewhere)
 pub struct ArkClient {
-    client: Client,
-    rpc_url: String,
+    config: ChainConfig,
+    chain: Arc<SimulatedChain>,
+    fee_policy: FeePolicy,
+    stuck_tx_policy: StuckTxPolicy,
+    confirmation_timeout: Duration,
 }
 
 impl ArkClient {
-    /// Create a new ARK client with testnet configuration
-    pub fn new() -> Result<Self, ArkError> {
-        let rpc_url = env::var("ARK_TESTNET_URL")
-            .unwrap_or_else(|_| "https://testnet-rpc.ark.network".to_string());
+    /// Create the client of one chain, backed by the process-wide simulation of it
+    pub fn connect(config: ChainConfig, settings: &Config) -> Result<Self, ArkError> {
+        let chain = SimulatedChain::shared(&config);
+        Self::with_simulator(config, chain, settings)
+    }
 
-        log::info!("Initializing ARK testnet client with RPC URL: {}", rpc_url);
+    /// Create the client of one chain, backed by a given simulation of it
+    pub fn with_simulator(
+        config: ChainConfig,
+        chain: Arc<SimulatedChain>,
+        settings: &Config,
+    ) -> Result<Self, ArkError> {
+        log::info!(
+            "Initializing {} client (chain id {}) with RPC URL: {}",
+            config.name,
+            config.chain_id,
+            config.rpc_url
+        );
 
         Ok(Self {
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(30))
-                .build()?,
-            rpc_url,
+            chain,
+            config,
+            fee_policy: settings.fees.clone(),
+            stuck_tx_policy: settings.transactions.stuck_tx_policy(),
+            confirmation_timeout: settings.rpc.confirmation_timeout(),
         })
     }
 
-    /// Query NFT ownership on ARK testnet
+    /// Configuration of the chain this client talks to
+    pub fn chain_config(&self) -> &ChainConfig {
+        &self.config
+    }
+
+    /// Simulated backend of the chain, for the testnet control endpoints
+    pub fn simulator(&self) -> &SimulatedChain {
+        &self.chain
+    }
+
+    /// The operator wallet's account on this client's chain
+    pub fn account(&self, wallet: &HotWallet) -> Arc<ChainAccount> {
+        wallet.account(&self.config.name)
+    }
+
+    pub fn stuck_tx_policy(&self) -> &StuckTxPolicy {
+        &self.stuck_tx_policy
+    }
+
+    /// Tokens deals can be settled in on this chain
+    pub fn tokens(&self) -> &TokenRegistry {
+        &self.config.tokens
+    }
+
+    /// Estimate the gas a contract call will consume
+    pub async fn estimate_gas(&self, from: &str, call: &ContractCall) -> Result<u64, ArkError> {
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/transaction/estimate
+        // Body: { from, to, call }
+        let estimated_gas = self.chain.estimate_gas(call);
+        log::debug!("Gas estimate for call from {}: {} units", from, estimated_gas);
+        Ok(estimated_gas)
+    }
+
+    /// Current base fee per gas, in gwei
+    pub async fn get_base_fee(&self) -> Result<u64, ArkError> {
+        // In production, this would read the base fee of the latest block header
+        Ok(self.chain.base_fee())
+    }
+
+    /// Next nonce the chain expects from `address`
+    pub async fn get_transaction_count(&self, address: &str) -> Result<u64, ArkError> {
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/account/nonce
+        // Body: { address }
+        Ok(self.chain.transaction_count(address))
+    }
+
+    /// Broadcast a signed transaction and return its hash
+    pub async fn send_raw_transaction(&self, signed: &SignedTransaction) -> Result<String, ArkError> {
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/transaction/broadcast
+        // Body: { tx, signature, public_key }
+        self.chain.submit(signed)?;
+        log::debug!(
+            "Transaction {} accepted (nonce {}, max fee {} gwei)",
+            signed.tx_hash,
+            signed.tx.nonce,
+            signed.tx.max_fee_per_gas
+        );
+        Ok(signed.tx_hash.clone())
+    }
+
+    /// Query the current head block height
+    pub async fn get_block_number(&self) -> Result<u64, ArkError> {
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/block/latest
+        // Response: { height: 1234567 }
+        Ok(self.chain.head_height())
+    }
+
+    /// Query the hash of the canonical block at `height`
+    pub async fn get_block_hash(&self, height: u64) -> Result<String, ArkError> {
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/block/{height}
+        // Response: { hash: "0x..." }
+        Ok(self.chain.block_hash(height))
+    }
+
+    /// Query the event logs of blocks `from..=to`, in block order
+    pub async fn get_logs(&self, from: u64, to: u64) -> Result<Vec<BlockLog>, ArkError> {
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/logs
+        // Body: { from_block, to_block, topics: [Transfer, TransferSingle] }
+        // Response: { logs: [{ block_number, tx_hash, address, topics, data }] }
+        Ok(self.chain.logs(from, to))
+    }
+
+    /// Query how many units of an NFT token id an address holds on ARK testnet
     ///
     /// In production, this would query the blockchain via RPC.
     /// For testnet/development, we simulate the blockchain query with realistic behavior.
-    pub async fn query_nft_ownership(
+    pub async fn query_nft_balance(&self, nft: &NftRef, owner: &str) -> Result<u64, ArkError> {
+        log::info!("Querying NFT balance: nft={}, owner={}", nft, owner);
+
+        // Simulate network delay (50-150ms)
+        tokio::time::sleep(tokio::time::Duration::from_millis(
+            rand::random::<u64>() % 100 + 50
+        ))
+        .await;
+
+        // In production, this would make an RPC call like:
+        // POST {rpc_url}/nft/balance
+        // Body: { chain, collection, token_id, owner }
+        // Response: { balance: 1 }
+        // which is `ownerOf(tokenId) == owner` for ERC-721 and `balanceOf(owner, id)`
+        // for ERC-1155
+        let balance = self.chain.nft_balance(nft, owner);
+
+        log::info!("NFT balance query result: {} (nft={}, owner={})", balance, nft, owner);
+
+        Ok(balance)
+    }
+
+    /// Tokens of a collection `owner` holds on the chain, with the units of each
+    pub async fn query_nft_holdings(
         &self,
         collection: &str,
-        token_id: &str,
-        expected_owner: &str,
-    ) -> Result<bool, ArkError> {
-        log::info!(
-            "Querying NFT ownership: collection={}, token_id={}, owner={}",
-            collection,
-            token_id,
-            expected_owner
-        );
+        owner: &str,
+    ) -> Result<Vec<(NftRef, u64)>, ArkError> {
+        log::info!("Querying {} holdings of {}", collection, owner);
 
         // Simulate network delay (50-150ms)
         tokio::time::sleep(tokio::time::Duration::from_millis(
@@ -112,30 +347,33 @@ impl ArkClient {
         .await;
 
         // In production, this would make an RPC call like:
-        // POST {rpc_url}/nft/owner
-        // Body: { collection, token_id }
-        // Response: { owner: "0x..." }
-
-        // For testnet/development: simulate successful ownership check
-        // In real implementation, this would query the actual blockchain
-        let owned = true;
+        // POST {rpc_url}/nft/holdings
+        // Body: { chain, collection, owner }
+        // Response: { tokens: [{ token_id, standard, balance }] }
+        // backed by an indexer, since ERC-721 only enumerates with the optional
+        // `tokenOfOwnerByIndex` and ERC-1155 not at all
+        let holdings = self.chain.nft_holdings(collection, owner);
 
         log::info!(
-            "NFT ownership query result: {} (collection={}, token_id={})",
-            owned,
+            "NFT holdings query result: {} tokens of {} for {}",
+            holdings.len(),
             collection,
-            token_id
+            owner
         );
 
-        Ok(owned)
+        Ok(holdings)
     }
 
-    /// Query USDC balance on ARK testnet
+    /// Query the balance of a registered token on ARK testnet
     ///
-    /// In production, this would query the USDC token contract on ARK Network.
+    /// In production, this would query the token contract on ARK Network.
     /// For testnet/development, we simulate the balance query with realistic behavior.
-    pub async fn query_usdc_balance(&self, address: &str) -> Result<f64, ArkError> {
-        log::info!("Querying USDC balance for address: {}", address);
+    pub async fn query_token_balance(
+        &self,
+        token: &TokenInfo,
+        address: &str,
+    ) -> Result<f64, ArkError> {
+        log::info!("Querying {} balance for address: {}", token.symbol, address);
 
         // Simulate network delay (50-150ms)
         tokio::time::sleep(tokio::time::Duration::from_millis(
@@ -145,105 +383,158 @@ impl ArkClient {
 
         // In production, this would make an RPC call like:
         // POST {rpc_url}/token/balance
-        // Body: { address, token: "USDC" }
-        // Response: { balance: "1000.00" }
+        // Body: { address, token: token.address }
+        // Response: { balance: "1000000000" }, in base units of `token.decimals`
+        let balance = self.chain.token_balance(&token.symbol, address);
 
-        // For testnet/development: simulate sufficient balance
-        // In real implementation, this would query the actual USDC contract
-        let balance = 10000.0; // Mock: 10,000 USDC available
-
-        log::info!("USDC balance query result: {} USDC for address {}", balance, address);
+        log::info!(
+            "{} balance query result: {} for address {}",
+            token.symbol,
+            balance,
+            address
+        );
 
         Ok(balance)
     }
 
     /// Execute escrow smart contract transaction on ARK testnet
     ///
-    /// This transfers the NFT from seller to buyer and USDC from buyer to seller atomically.
-    ///
-    /// In production, this would:
-    /// 1. Prepare smart contract call data
-    /// 2. Estimate gas
-    /// 3. Sign transaction with private key
-    /// 4. Submit to blockchain
-    /// 5. Wait for confirmation (minimum 3 blocks)
-    ///
-    /// For testnet/development, we simulate the full transaction lifecycle with realistic timing.
+    /// This transfers the NFTs from seller to buyer and the price from buyer to the
+    /// seller, treasury and creators atomically.
     pub async fn execute_escrow_transaction(
         &self,
-        buyer_address: &str,
-        seller_address: &str,
-        nft_collection: &str,
-        nft_token_id: &str,
-        price_usdc: f64,
+        wallet: &HotWallet,
+        escrow: &EscrowTransaction,
     ) -> Result<TransactionReceipt, ArkError> {
+        let nfts: Vec<String> = escrow.nfts.iter().map(ToString::to_string).collect();
         log::info!(
-            "Executing escrow transaction: NFT {} #{} from {} to {} for {} USDC",
-            nft_collection,
-            nft_token_id,
-            seller_address,
-            buyer_address,
-            price_usdc
+            "Executing escrow transaction: NFT {} from {} to {} for {}",
+            nfts.join(", "),
+            escrow.seller_address,
+            escrow.buyer_address,
+            escrow.price
         );
 
-        // Step 1: Gas estimation (simulate 20-50ms)
-        tokio::time::sleep(tokio::time::Duration::from_millis(
-            rand::random::<u64>() % 30 + 20
-        ))
-        .await;
-        let estimated_gas = 250000u64; // Typical gas for NFT + token transfer
-        log::debug!("Gas estimation: {} units", estimated_gas);
+        self.execute_contract_call(wallet, ContractCall::Escrow(escrow.clone()))
+            .await
+    }
 
-        // Step 2: Transaction signing and submission (simulate 100-200ms)
-        tokio::time::sleep(tokio::time::Duration::from_millis(
-            rand::random::<u64>() % 100 + 100
-        ))
-        .await;
+    /// Execute an atomic swap between two parties on ARK testnet
+    ///
+    /// Each side's NFTs and tokens go to the other side in the same transaction; if
+    /// either side no longer holds what it offered, the whole swap reverts.
+    pub async fn execute_swap_transaction(
+        &self,
+        wallet: &HotWallet,
+        swap: &SwapTransaction,
+    ) -> Result<TransactionReceipt, ArkError> {
+        log::info!(
+            "Executing swap: {} gives {} NFTs and {} tokens, {} gives {} NFTs and {} tokens",
+            swap.maker.address,
+            swap.maker.nfts.len(),
+            swap.maker.tokens.len(),
+            swap.taker.address,
+            swap.taker.nfts.len(),
+            swap.taker.tokens.len()
+        );
+
+        self.execute_contract_call(wallet, ContractCall::Swap(swap.clone()))
+            .await
+    }
 
-        // Generate deterministic transaction hash based on transaction details
-        let tx_data = format!(
-            "{}:{}:{}:{}:{}:{}",
-            buyer_address,
-            seller_address,
-            nft_collection,
-            nft_token_id,
-            price_usdc,
-            chrono::Utc::now().timestamp()
+    /// Sign, submit and track a call to the escrow contract until it is final
+    ///
+    /// The transaction is:
+    /// 1. Gas-estimated
+    /// 2. Signed locally by the operator hot wallet with the next account nonce
+    /// 3. Submitted to the blockchain
+    /// 4. Tracked until it has the minimum number of confirmations (3 blocks)
+    ///
+    /// The nonce stays locked from signing until the node accepts the transaction, so
+    /// concurrent calls are numbered consecutively; waiting for confirmations happens
+    /// after the lock is released.
+    async fn execute_contract_call(
+        &self,
+        wallet: &HotWallet,
+        call: ContractCall,
+    ) -> Result<TransactionReceipt, ArkError> {
+        // Step 1: Gas estimation and fees
+        let estimated_gas = self.estimate_gas(wallet.address(), &call).await?;
+        let gas_limit = self.fee_policy.gas_limit(estimated_gas);
+        let base_fee = self.get_base_fee().await?;
+        let (max_fee_per_gas, max_priority_fee_per_gas) = self.fee_policy.initial_fees(base_fee);
+        log::debug!(
+            "Gas estimation: {} units (limit {}), base fee {} gwei, max fee {} gwei, tip {} gwei",
+            estimated_gas,
+            gas_limit,
+            base_fee,
+            max_fee_per_gas,
+            max_priority_fee_per_gas
         );
 
-        let mut hasher = Sha256::new();
-        hasher.update(tx_data.as_bytes());
-        let hash_result = hasher.finalize();
-        let tx_hash = format!("0x{}", hex::encode(hash_result));
+        // Step 2: Transaction signing and submission
+        let account = self.account(wallet);
+        let signed = {
+            let mut nonce_guard = account.lock_nonce().await;
+            let nonce = match nonce_guard.next() {
+                Some(nonce) => nonce,
+                None => self.get_transaction_count(wallet.address()).await?,
+            };
 
-        log::info!("Transaction submitted: {}", tx_hash);
+            let signed = wallet.sign(UnsignedTransaction {
+                chain_id: self.config.chain_id,
+                from: wallet.address().to_string(),
+                to: self.config.escrow_contract.clone(),
+                nonce,
+                gas_limit,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                call,
+            });
 
-        // Step 3: Wait for confirmations (simulate 3 block times: ~6-9 seconds on ARK testnet)
-        // Each block on ARK testnet takes approximately 2-3 seconds
-        log::info!("Waiting for 3 confirmations...");
+            match self.send_raw_transaction(&signed).await {
+                Ok(_) => {
+                    nonce_guard.consume(nonce);
+                    account.pending().track(&signed);
+                    signed
+                }
+                Err(e) => {
+                    // The node's view of the nonce may differ from ours; re-sync next time
+                    nonce_guard.reset();
+                    return Err(e);
+                }
+            }
+        };
 
-        for conf in 1..=3 {
-            tokio::time::sleep(tokio::time::Duration::from_millis(
-                rand::random::<u64>() % 1000 + 2000 // 2-3 seconds per confirmation
-            ))
-            .await;
-            log::debug!("Confirmation {}/3 received", conf);
-        }
+        log::info!("Transaction submitted: {}", signed.tx_hash);
 
-        // Step 4: Generate transaction receipt
-        let mut rng = rand::thread_rng();
-        let block_number: u64 = rand::Rng::gen_range(&mut rng, 1000000..2000000);
+        // Steps 3 and 4: wait for inclusion and confirmations
+        self.await_contract_call(wallet, &signed.tx_hash).await
+    }
 
-        let receipt = TransactionReceipt {
-            tx_hash: tx_hash.clone(),
-            block_number,
-            status: "success".to_string(),
-            confirmations: 3,
-            gas_used: estimated_gas - 10000, // Actual gas is usually slightly less than estimate
+    /// Wait until a submitted operator transaction is mined and final.
+    ///
+    /// Also resumes waiting for a transaction that timed out before: one still
+    /// pending is followed through its replacements, one already mined only needs
+    /// its confirmations.
+    pub async fn await_contract_call(
+        &self,
+        wallet: &HotWallet,
+        tx_hash: &str,
+    ) -> Result<TransactionReceipt, ArkError> {
+        // Wait for inclusion; the stuck-transaction monitor handles low fees
+        let tx_hash = match self.account(wallet).pending().find(tx_hash) {
+            Some(pending) => self.wait_for_inclusion(wallet, pending.nonce).await?,
+            None => tx_hash.to_string(),
         };
 
+        // Wait for the chain's confirmation depth (~7.5 seconds on ARK testnet)
+        let receipt = self
+            .wait_for_confirmations(&tx_hash, self.config.confirmations)
+            .await?;
+
         log::info!(
-            "Escrow transaction confirmed: tx_hash={}, block={}, gas_used={}",
+            "Contract call confirmed: tx_hash={}, block={}, gas_used={}",
             receipt.tx_hash,
             receipt.block_number,
             receipt.gas_used
@@ -252,7 +543,149 @@ impl ArkClient {
         Ok(receipt)
     }
 
-    /// Verify that a transaction has sufficient confirmations
+    /// Wait until the operator transaction with `nonce` is mined and return the hash
+    /// of the version that was mined.
+    ///
+    /// Replacements recorded in the wallet's pending registry (speed-ups and
+    /// cancellations by the stuck-transaction monitor or an operator) are followed, but
+    /// never made here. A mined cancellation returns `ArkError::TransactionCancelled`;
+    /// if nothing is mined by the confirmation timeout the transaction stays pending
+    /// and `ArkError::ConfirmationTimeout` is returned with its original hash.
+    pub async fn wait_for_inclusion(
+        &self,
+        wallet: &HotWallet,
+        nonce: u64,
+    ) -> Result<String, ArkError> {
+        let poll_interval = self.chain.block_time() / 2;
+        let deadline = Instant::now() + self.confirmation_timeout;
+        let account = self.account(wallet);
+
+        loop {
+            let pending = account.pending().get(nonce).ok_or_else(|| {
+                ArkError::TransactionNotFound(format!("pending transaction with nonce {}", nonce))
+            })?;
+
+            if let Some(mined) = self.find_mined_version(&pending).await? {
+                account.pending().remove(nonce);
+                if matches!(mined.tx.call, ContractCall::Cancel) {
+                    log::warn!(
+                        "Transaction {} was cancelled by {}",
+                        pending.original_tx_hash(),
+                        mined.tx_hash
+                    );
+                    return Err(ArkError::TransactionCancelled(
+                        pending.original_tx_hash().to_string(),
+                    ));
+                }
+                return Ok(mined.tx_hash.clone());
+            }
+
+            continue_waiting(poll_interval, deadline, pending.original_tx_hash()).await?;
+        }
+    }
+
+    /// The version of a pending transaction that made it into a block, if any
+    async fn find_mined_version<'a>(
+        &self,
+        pending: &'a PendingTransaction,
+    ) -> Result<Option<&'a SignedTransaction>, ArkError> {
+        for version in pending.versions.iter().rev() {
+            match self.get_transaction_receipt(&version.tx_hash).await {
+                Ok(_) => return Ok(Some(version)),
+                Err(ArkError::TransactionNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Replace a pending operator transaction with the same call at higher fees
+    pub async fn speed_up_transaction(
+        &self,
+        wallet: &HotWallet,
+        nonce: u64,
+    ) -> Result<SignedTransaction, ArkError> {
+        let pending = self.account(wallet).pending().get(nonce).ok_or_else(|| {
+            ArkError::TransactionNotFound(format!("pending transaction with nonce {}", nonce))
+        })?;
+        let tx = pending.current().tx.clone();
+        self.replace_transaction(wallet, &pending, tx).await
+    }
+
+    /// Replace a pending operator transaction with a no-op at higher fees, so the
+    /// original call can no longer be mined
+    pub async fn cancel_transaction(
+        &self,
+        wallet: &HotWallet,
+        nonce: u64,
+    ) -> Result<SignedTransaction, ArkError> {
+        let pending = self.account(wallet).pending().get(nonce).ok_or_else(|| {
+            ArkError::TransactionNotFound(format!("pending transaction with nonce {}", nonce))
+        })?;
+        let current = &pending.current().tx;
+        let call = ContractCall::Cancel;
+        let tx = UnsignedTransaction {
+            chain_id: current.chain_id,
+            from: current.from.clone(),
+            to: current.from.clone(),
+            nonce,
+            gas_limit: self.estimate_gas(&current.from, &call).await?,
+            max_fee_per_gas: current.max_fee_per_gas,
+            max_priority_fee_per_gas: current.max_priority_fee_per_gas,
+            call,
+        };
+        self.replace_transaction(wallet, &pending, tx).await
+    }
+
+    /// Sign `tx` with bumped fees over the current version and submit it as a replacement
+    async fn replace_transaction(
+        &self,
+        wallet: &HotWallet,
+        pending: &PendingTransaction,
+        mut tx: UnsignedTransaction,
+    ) -> Result<SignedTransaction, ArkError> {
+        let current = pending.current();
+        let (max_fee, tip) = self
+            .fee_policy
+            .bumped_fees(
+                current.tx.max_fee_per_gas,
+                current.tx.max_priority_fee_per_gas,
+            )
+            .ok_or_else(|| {
+                ArkError::TransactionFailed(format!(
+                    "Transaction {} is already at the fee cap of {} gwei",
+                    current.tx_hash, self.fee_policy.max_fee_per_gas_gwei
+                ))
+            })?;
+        tx.max_fee_per_gas = max_fee;
+        tx.max_priority_fee_per_gas = tip;
+
+        let replacement = wallet.sign(tx);
+        self.send_raw_transaction(&replacement).await?;
+        self.account(wallet).pending().replace(&replacement);
+
+        log::warn!(
+            "Transaction {} (nonce {}) replaced by {} (max fee {} gwei{})",
+            current.tx_hash,
+            pending.nonce,
+            replacement.tx_hash,
+            max_fee,
+            if matches!(replacement.tx.call, ContractCall::Cancel) {
+                ", cancellation"
+            } else {
+                ""
+            }
+        );
+
+        Ok(replacement)
+    }
+
+    /// Wait until a transaction has at least `min_confirmations` confirmations
+    ///
+    /// Polls the head height and the transaction's inclusion block. If a transaction
+    /// that was already included disappears, it was orphaned by a reorg and
+    /// `ArkError::TransactionReorged` is returned so the caller can resubmit or roll
+    /// back. If it is re-included in a different block, counting restarts from there.
     pub async fn wait_for_confirmations(
         &self,
         tx_hash: &str,
@@ -264,19 +697,66 @@ impl ArkClient {
             tx_hash
         );
 
-        // In production, this would poll the blockchain for confirmation status
-        // For now, simulate the wait time
-        let wait_time_ms = min_confirmations as u64 * 2500; // ~2.5 seconds per confirmation
-        tokio::time::sleep(tokio::time::Duration::from_millis(wait_time_ms)).await;
+        let poll_interval = self.chain.block_time() / 2;
+        let deadline = Instant::now() + self.confirmation_timeout;
+        let mut inclusion_block: Option<u64> = None;
 
-        // Generate mock receipt
-        let mut rng = rand::thread_rng();
-        let receipt = TransactionReceipt {
-            tx_hash: tx_hash.to_string(),
-            block_number: rand::Rng::gen_range(&mut rng, 1000000..2000000),
-            status: "success".to_string(),
-            confirmations: min_confirmations,
-            gas_used: 240000,
+        let receipt = loop {
+            let head = self.get_block_number().await?;
+
+            match self.get_transaction_receipt(tx_hash).await {
+                Ok(mut receipt) => {
+                    if let Some(previous) = inclusion_block {
+                        if previous != receipt.block_number {
+                            log::warn!(
+                                "Transaction {} moved from block {} to block {} after a reorg",
+                                tx_hash,
+                                previous,
+                                receipt.block_number
+                            );
+                        }
+                    }
+                    inclusion_block = Some(receipt.block_number);
+
+                    receipt.confirmations =
+                        (head + 1).saturating_sub(receipt.block_number) as u32;
+                    log::debug!(
+                        "Transaction {} has {}/{} confirmations",
+                        tx_hash,
+                        receipt.confirmations,
+                        min_confirmations
+                    );
+                    if receipt.confirmations >= min_confirmations {
+                        break receipt;
+                    }
+                }
+                Err(ArkError::TransactionNotFound(_)) => {
+                    if let Some(block_number) = inclusion_block {
+                        if self.get_transaction_status(tx_hash).await? == TxStatus::Pending {
+                            log::warn!(
+                                "Transaction {} left block {} and is back in the mempool",
+                                tx_hash,
+                                block_number
+                            );
+                            continue_waiting(poll_interval, deadline, tx_hash).await?;
+                            continue;
+                        }
+                        log::error!(
+                            "Transaction {} disappeared from block {} (reorg)",
+                            tx_hash,
+                            block_number
+                        );
+                        return Err(ArkError::TransactionReorged {
+                            tx_hash: tx_hash.to_string(),
+                            block_number,
+                        });
+                    }
+                    // Still pending
+                }
+                Err(e) => return Err(e),
+            }
+
+            continue_waiting(poll_interval, deadline, tx_hash).await?;
         };
 
         log::info!(
@@ -288,74 +768,337 @@ impl ArkClient {
         Ok(receipt)
     }
 
+    /// Lifecycle status of a transaction, including ones that were never mined
+    pub async fn get_transaction_status(&self, tx_hash: &str) -> Result<TxStatus, ArkError> {
+        // In production, this would combine the receipt, the mempool and the
+        // account nonce (a lower-nonce hash that never mined was replaced)
+        self.chain
+            .status(tx_hash)
+            .ok_or_else(|| ArkError::TransactionNotFound(tx_hash.to_string()))
+    }
+
     /// Get transaction receipt by hash
     pub async fn get_transaction_receipt(
         &self,
         tx_hash: &str,
     ) -> Result<TransactionReceipt, ArkError> {
-        log::info!("Fetching transaction receipt for {}", tx_hash);
-
-        // Simulate network delay
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        log::debug!("Fetching transaction receipt for {}", tx_hash);
 
         // In production, this would query the blockchain for the receipt
-        let mut rng = rand::thread_rng();
+        let tx = self
+            .chain
+            .transaction(tx_hash)
+            .ok_or_else(|| ArkError::TransactionNotFound(tx_hash.to_string()))?;
+        let confirmations = (self.chain.head_height() + 1).saturating_sub(tx.block_number);
+        let fee_paid = fee_in_native(tx.gas_used, tx.effective_gas_price_gwei);
+
         let receipt = TransactionReceipt {
-            tx_hash: tx_hash.to_string(),
-            block_number: rand::Rng::gen_range(&mut rng, 1000000..2000000),
-            status: "success".to_string(),
-            confirmations: 10,
-            gas_used: 240000,
+            tx_hash: tx.tx_hash,
+            block_number: tx.block_number,
+            block_hash: self.chain.block_hash(tx.block_number),
+            timestamp: self.chain.block_timestamp(tx.block_number),
+            status: tx.status,
+            confirmations: confirmations as u32,
+            gas_used: tx.gas_used,
+            effective_gas_price_gwei: tx.effective_gas_price_gwei,
+            fee_paid,
+            fee_paid_usdc: self.fee_policy.fee_in_usdc(fee_paid),
+            value: tx.value,
+            logs: tx.logs,
+            revert_reason: tx.revert_reason,
         };
 
         Ok(receipt)
     }
 }
 
+/// Sleep for one poll interval, or fail if the confirmation deadline of `tx_hash`
+/// has passed
+async fn continue_waiting(
+    poll_interval: Duration,
+    deadline: Instant,
+    tx_hash: &str,
+) -> Result<(), ArkError> {
+    if Instant::now() >= deadline {
+        log::error!("Timed out waiting for confirmations of transaction {}", tx_hash);
+        return Err(ArkError::ConfirmationTimeout {
+            tx_hash: tx_hash.to_string(),
+        });
+    }
+    tokio::time::sleep(poll_interval).await;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chains::{ChainRegistry, DEFAULT_CHAIN};
+    use crate::settlement::PayoutKind;
+    use crate::simulator::SimulatedTransaction;
+    use crate::tokens::USDC;
+    use crate::transactions::{recover_stuck_transactions, StuckTxAction};
+
+    /// Client of ARK testnet, as the service connects it with default settings
+    fn ark_client() -> ArkClient {
+        let settings = Config::default();
+        let chain = ChainRegistry::load(&settings.chains)
+            .unwrap()
+            .get(DEFAULT_CHAIN)
+            .unwrap()
+            .clone();
+        ArkClient::connect(chain, &settings).unwrap()
+    }
+
+    /// Client of ARK testnet backed by its own simulation, with default settings
+    fn simulated_client(chain: Arc<SimulatedChain>, confirmation_timeout_secs: u64) -> ArkClient {
+        let mut settings = Config::default();
+        settings.rpc.confirmation_timeout_secs = confirmation_timeout_secs;
+        let config = ChainRegistry::default().get(DEFAULT_CHAIN).unwrap().clone();
+        ArkClient::with_simulator(config, chain, &settings).unwrap()
+    }
+
+    fn escrow(token_id: &str) -> EscrowTransaction {
+        EscrowTransaction {
+            buyer_address: "0xbuyer...".to_string(),
+            seller_address: "0xseller...".to_string(),
+            nfts: vec![format!("BAYC#{}", token_id).parse().unwrap()],
+            price: TokenAmount::new(USDC, 50.0),
+            payouts: vec![Payout {
+                kind: PayoutKind::Seller,
+                recipient: "0xseller...".to_string(),
+                amount: TokenAmount::new(USDC, 50.0),
+            }],
+        }
+    }
 
     #[tokio::test]
     async fn test_ark_client_creation() {
-        let client = ArkClient::new();
-        assert!(client.is_ok());
+        let client = ark_client();
+        assert_eq!(client.chain_config().name, DEFAULT_CHAIN);
     }
 
     #[tokio::test]
-    async fn test_nft_ownership_query() {
-        let client = ArkClient::new().unwrap();
-        let result = client
-            .query_nft_ownership("BAYC", "1234", "0x123...")
-            .await;
+    async fn test_nft_balance_query() {
+        let client = ark_client();
+        // A token no other test trades on the shared chain, so nobody's holding is known
+        let nft = format!("BAYC#{}", rand::random::<u32>()).parse().unwrap();
+        let result = client.query_nft_balance(&nft, "0x123...").await;
         assert!(result.is_ok());
-        assert!(result.unwrap());
+        assert_eq!(result.unwrap(), 1);
     }
 
     #[tokio::test]
-    async fn test_usdc_balance_query() {
-        let client = ArkClient::new().unwrap();
-        let result = client.query_usdc_balance("0x123...").await;
+    async fn test_token_balance_query() {
+        let client = ark_client();
+        let usdc = client.tokens().get(USDC).unwrap();
+        let result = client.query_token_balance(usdc, "0x123...").await;
         assert!(result.is_ok());
         assert!(result.unwrap() > 0.0);
     }
 
     #[tokio::test]
     async fn test_escrow_transaction() {
-        let client = ArkClient::new().unwrap();
+        let client = ark_client();
+        let wallet = HotWallet::generate();
         let result = client
-            .execute_escrow_transaction(
-                "0xbuyer...",
-                "0xseller...",
-                "BAYC",
-                "1234",
-                50000.0,
-            )
+            .execute_escrow_transaction(&wallet, &escrow("1234"))
             .await;
         assert!(result.is_ok());
         let receipt = result.unwrap();
-        assert_eq!(receipt.status, "success");
+        assert_eq!(receipt.status, TxStatus::Success);
+        assert_eq!(receipt.logs.len(), 2);
+        assert!(receipt.block_hash.starts_with("0x"));
         assert_eq!(receipt.confirmations, 3);
         assert!(receipt.tx_hash.starts_with("0x"));
     }
+
+    #[tokio::test]
+    async fn test_concurrent_escrows_use_consecutive_nonces() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(20)));
+        let client = Arc::new(simulated_client(chain.clone(), 10));
+        let wallet = Arc::new(HotWallet::generate());
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let client = client.clone();
+                let wallet = wallet.clone();
+                tokio::spawn(async move {
+                    client
+                        .execute_escrow_transaction(&wallet, &escrow(&i.to_string()))
+                        .await
+                })
+            })
+            .collect();
+
+        let mut tx_hashes = Vec::new();
+        for handle in handles {
+            tx_hashes.push(handle.await.unwrap().unwrap().tx_hash);
+        }
+        tx_hashes.sort();
+        tx_hashes.dedup();
+
+        assert_eq!(tx_hashes.len(), 8);
+        assert_eq!(chain.transaction_count(wallet.address()), 8);
+    }
+
+    #[tokio::test]
+    async fn test_stuck_transaction_is_mined_once_sped_up() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(20)));
+        let client = simulated_client(chain.clone(), 1);
+        let wallet = HotWallet::generate();
+
+        let call = ContractCall::Escrow(escrow("77"));
+        let (max_fee_per_gas, max_priority_fee_per_gas) =
+            client.fee_policy.initial_fees(chain.base_fee());
+        let signed = wallet.sign(UnsignedTransaction {
+            chain_id: chain.chain_id(),
+            from: wallet.address().to_string(),
+            to: "0xescrow".to_string(),
+            nonce: 0,
+            gas_limit: 200000,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            call: call.clone(),
+        });
+
+        // The base fee jumps above the initial max fee of 22 gwei right after submission
+        chain.set_base_fee(30);
+        client.send_raw_transaction(&signed).await.unwrap();
+        client.account(&wallet).pending().track(&signed);
+
+        // Waiting never replaces the transaction by itself
+        let result = client.wait_for_inclusion(&wallet, 0).await;
+        assert!(matches!(
+            result,
+            Err(ArkError::ConfirmationTimeout { ref tx_hash }) if tx_hash == &signed.tx_hash
+        ));
+        assert_eq!(client.account(&wallet).pending().get(0).unwrap().versions.len(), 1);
+
+        // Two 20% bumps take the max fee past the base fee
+        let policy = StuckTxPolicy {
+            deadline: Duration::ZERO,
+            action: StuckTxAction::SpeedUp,
+        };
+        for _ in 0..2 {
+            recover_stuck_transactions(&client, &wallet, &policy).await;
+        }
+        // Waiting again by the hash that timed out follows the replacement
+        let receipt = client
+            .await_contract_call(&wallet, &signed.tx_hash)
+            .await
+            .unwrap();
+        assert_ne!(receipt.tx_hash, signed.tx_hash);
+        assert!(receipt.effective_gas_price_gwei >= 30);
+        assert_eq!(receipt.gas_used, chain.estimate_gas(&call));
+        assert!(receipt.fee_paid > 0.0);
+        assert_eq!(chain.transaction_count(wallet.address()), 1);
+        assert!(client.account(&wallet).pending().all().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_transaction_reports_cancellation() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(20)));
+        let client = simulated_client(chain.clone(), 10);
+        let wallet = HotWallet::generate();
+
+        let signed = wallet.sign(UnsignedTransaction {
+            chain_id: chain.chain_id(),
+            from: wallet.address().to_string(),
+            to: "0xescrow".to_string(),
+            nonce: 0,
+            gas_limit: 200000,
+            max_fee_per_gas: 5,
+            max_priority_fee_per_gas: 1,
+            call: ContractCall::Escrow(escrow("88")),
+        });
+        client.send_raw_transaction(&signed).await.unwrap();
+        client.account(&wallet).pending().track(&signed);
+
+        // Underpriced at the default base fee of 10 gwei; cancelling outbids it
+        let cancellation = client.cancel_transaction(&wallet, 0).await.unwrap();
+        chain.set_base_fee(cancellation.tx.max_fee_per_gas);
+
+        let result = client.wait_for_inclusion(&wallet, 0).await;
+        assert!(matches!(
+            result,
+            Err(ArkError::TransactionCancelled(ref hash)) if hash == &signed.tx_hash
+        ));
+        assert!(client
+            .get_transaction_receipt(&signed.tx_hash)
+            .await
+            .is_err());
+        assert!(client
+            .get_transaction_receipt(&cancellation.tx_hash)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_confirmations_detects_reorg() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(40)));
+        let client = simulated_client(chain.clone(), 5);
+        chain.record_transaction(SimulatedTransaction {
+            tx_hash: "0xreorged".to_string(),
+            block_number: chain.head_height() + 1,
+            status: TxStatus::Success,
+            gas_used: 21000,
+            effective_gas_price_gwei: 10,
+            value: vec![TokenAmount::new(USDC, 10.0)],
+            logs: Vec::new(),
+            revert_reason: None,
+        });
+
+        let reorg_chain = chain.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            reorg_chain.reorg(10, false);
+        });
+
+        let result = client.wait_for_confirmations("0xreorged", 20).await;
+        assert!(matches!(
+            result,
+            Err(ArkError::TransactionReorged { ref tx_hash, .. }) if tx_hash == "0xreorged"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_confirmations_follows_reinclusion() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(40)));
+        let client = simulated_client(chain.clone(), 5);
+        let original_block = chain.head_height() + 1;
+        chain.record_transaction(SimulatedTransaction {
+            tx_hash: "0xreincluded".to_string(),
+            block_number: original_block,
+            status: TxStatus::Success,
+            gas_used: 21000,
+            effective_gas_price_gwei: 10,
+            value: vec![TokenAmount::new(USDC, 10.0)],
+            logs: Vec::new(),
+            revert_reason: None,
+        });
+
+        let reorg_chain = chain.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            reorg_chain.reorg(10, true);
+        });
+
+        let receipt = client
+            .wait_for_confirmations("0xreincluded", 8)
+            .await
+            .unwrap();
+        assert!(receipt.block_number > original_block);
+        assert!(receipt.confirmations >= 8);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_confirmations_times_out() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(40)));
+        let client = simulated_client(chain, 1);
+        let result = client.wait_for_confirmations("0xnever-submitted", 3).await;
+        assert!(matches!(
+            result,
+            Err(ArkError::ConfirmationTimeout { ref tx_hash }) if tx_hash == "0xnever-submitted"
+        ));
+    }
 }
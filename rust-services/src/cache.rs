@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::nft::NftRef;
+
+/// Hit and miss counts of the query cache since startup
+#[derive(Serialize, Debug, Clone)]
+pub struct CacheStats {
+    pub ttl_secs: u64,
+    pub nft_balances: usize,
+    pub token_balances: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Values that are dropped `ttl` after they were stored
+struct TtlMap<K, V> {
+    entries: Mutex<HashMap<K, (V, Instant)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlMap<K, V> {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &K, ttl: Duration) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((value, stored_at)) if stored_at.elapsed() < ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: K, value: V) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key, (value, Instant::now()));
+    }
+
+    fn remove_where(&self, stale: impl Fn(&K) -> bool) {
+        self.entries.lock().unwrap().retain(|key, _| !stale(key));
+    }
+
+    fn len(&self, ttl: Duration) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, stored_at)| stored_at.elapsed() < ttl);
+        entries.len()
+    }
+}
+
+/// Recent results of NFT and token balance queries, so repeated questions about
+/// the same deal during a negotiation do not each cost an RPC call.
+///
+/// Ownership is answered from the cached NFT balance. Entries touched by an escrow
+/// or swap this service submits are dropped; changes made by anyone else show up
+/// once the entry expires. A zero TTL disables caching.
+pub struct QueryCache {
+    ttl: Duration,
+    /// NFT balances by `NftRef::token_key` and lowercase owner
+    nft_balances: TtlMap<(String, String), u64>,
+    /// Token balances in base units by chain, lowercase address and token symbol
+    token_balances: TtlMap<(String, String, String), u128>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl QueryCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            nft_balances: TtlMap::new(),
+            token_balances: TtlMap::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn nft_balance(&self, nft: &NftRef, owner: &str) -> Option<u64> {
+        let balance = self
+            .nft_balances
+            .get(&(nft.token_key(), owner.to_lowercase()), self.ttl);
+        self.count(balance.is_some());
+        balance
+    }
+
+    pub fn put_nft_balance(&self, nft: &NftRef, owner: &str, balance: u64) {
+        if !self.ttl.is_zero() {
+            self.nft_balances
+                .insert((nft.token_key(), owner.to_lowercase()), balance);
+        }
+    }
+
+    pub fn token_balance(&self, chain: &str, address: &str, token: &str) -> Option<u128> {
+        let balance = self.token_balances.get(
+            &(chain.to_string(), address.to_lowercase(), token.to_string()),
+            self.ttl,
+        );
+        self.count(balance.is_some());
+        balance
+    }
+
+    pub fn put_token_balance(&self, chain: &str, address: &str, token: &str, balance: u128) {
+        if !self.ttl.is_zero() {
+            self.token_balances.insert(
+                (chain.to_string(), address.to_lowercase(), token.to_string()),
+                balance,
+            );
+        }
+    }
+
+    /// Drop the cached balances of every owner of these NFTs and every token balance
+    /// of these addresses on `chain`
+    pub fn invalidate(&self, chain: &str, nfts: &[NftRef], addresses: &[&str]) {
+        let token_keys: Vec<String> = nfts.iter().map(NftRef::token_key).collect();
+        let addresses: Vec<String> = addresses.iter().map(|a| a.to_lowercase()).collect();
+        self.nft_balances
+            .remove_where(|(token_key, _)| token_keys.contains(token_key));
+        self.token_balances
+            .remove_where(|(cached_chain, address, _)| {
+                cached_chain == chain && addresses.contains(address)
+            });
+    }
+
+    /// Drop everything cached for a chain, e.g. after a reorg
+    pub fn clear_chain(&self, chain: &str) {
+        let prefix = format!("{}:", chain);
+        self.nft_balances
+            .remove_where(|(token_key, _)| token_key.starts_with(&prefix));
+        self.token_balances
+            .remove_where(|(cached_chain, _, _)| cached_chain == chain);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            ttl_secs: self.ttl.as_secs(),
+            nft_balances: self.nft_balances.len(self.ttl),
+            token_balances: self.token_balances.len(self.ttl),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn count(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::USDC;
+
+    const SELLER: &str = "0x00000000000000000000000000000000000a11ce";
+    const BUYER: &str = "0x00000000000000000000000000000000000B0B0B";
+
+    #[test]
+    fn test_entries_are_served_until_invalidated_or_expired() {
+        let cache = QueryCache::new(Duration::from_secs(60));
+        let nft: NftRef = "BAYC#1".parse().unwrap();
+
+        assert!(cache.nft_balance(&nft, SELLER).is_none());
+        cache.put_nft_balance(&nft, SELLER, 1);
+        cache.put_token_balance("ark", BUYER, USDC, 500_000_000);
+        cache.put_token_balance("sepolia", BUYER, USDC, 20_000_000);
+
+        assert_eq!(cache.nft_balance(&nft, &SELLER.to_uppercase()), Some(1));
+        assert_eq!(
+            cache.token_balance("ark", &BUYER.to_lowercase(), USDC),
+            Some(500_000_000)
+        );
+
+        // An escrow on ARK moves the NFT and the buyer's funds there only
+        cache.invalidate("ark", std::slice::from_ref(&nft), &[BUYER]);
+        assert!(cache.nft_balance(&nft, SELLER).is_none());
+        assert!(cache.token_balance("ark", BUYER, USDC).is_none());
+        assert_eq!(cache.token_balance("sepolia", BUYER, USDC), Some(20_000_000));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 3));
+        assert_eq!((stats.nft_balances, stats.token_balances), (0, 1));
+
+        let disabled = QueryCache::new(Duration::ZERO);
+        disabled.put_nft_balance(&nft, SELLER, 1);
+        assert!(disabled.nft_balance(&nft, SELLER).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::time::Duration;
+
+use crate::ark_client::ArkError;
+use crate::config::env_or;
+use crate::tokens::{TokenInfo, TokenRegistry, USDC};
+use crate::wallet::validate_address;
+
+/// Chain assumed when a request or NFT reference does not name one
+pub const DEFAULT_CHAIN: &str = "ark";
+
+/// EIP-155 chain id of ARK testnet
+pub const ARK_TESTNET_CHAIN_ID: u64 = 7_070;
+
+/// Everything needed to route requests to one chain's backend
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    /// Name requests and NFT references use, e.g. `ark`
+    pub name: String,
+    /// Id signed into every transaction so it cannot be replayed on another chain
+    pub chain_id: u64,
+    pub rpc_url: String,
+    /// Confirmations required before an escrow is considered final
+    pub confirmations: u32,
+    pub block_time: Duration,
+    pub escrow_contract: String,
+    /// Tokens the escrow contract on this chain accepts
+    pub tokens: TokenRegistry,
+    /// NFT collections (slugs or contract addresses) portfolios list holdings of
+    pub collections: Vec<String>,
+}
+
+/// A chain's `[chains.<name>]` table of the config file; unset fields keep the
+/// chain's built-in values
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSection {
+    pub chain_id: Option<u64>,
+    pub rpc_url: Option<String>,
+    pub confirmations: Option<u32>,
+    pub block_time_ms: Option<u64>,
+    pub escrow_contract: Option<String>,
+    /// Comma-separated `SYMBOL:address:decimals` entries added to the chain's tokens
+    pub tokens: Option<String>,
+    /// Comma-separated collections replacing the chain's portfolio collections
+    pub collections: Option<String>,
+}
+
+impl ChainConfig {
+    /// ARK testnet, also configured by the `ARK_*` variables that predate multi-chain support
+    fn ark_testnet() -> Self {
+        Self {
+            name: DEFAULT_CHAIN.to_string(),
+            chain_id: ARK_TESTNET_CHAIN_ID,
+            rpc_url: "https://testnet-rpc.ark.network".to_string(),
+            confirmations: 3,
+            block_time: Duration::from_millis(2500),
+            escrow_contract: "0x00000000000000000000000000000000000e5c40".to_string(),
+            tokens: TokenRegistry::default(),
+            collections: vec!["BAYC".to_string(), "COOLCATS".to_string()],
+        }
+    }
+
+    fn sepolia() -> Self {
+        Self {
+            name: "sepolia".to_string(),
+            chain_id: 11_155_111,
+            rpc_url: "https://rpc.sepolia.org".to_string(),
+            confirmations: 6,
+            block_time: Duration::from_secs(12),
+            escrow_contract: "0x00000000000000000000000000000000000e5c40".to_string(),
+            tokens: TokenRegistry::new(vec![TokenInfo::new(
+                USDC,
+                "0x1c7d4b196cb0c7b01d743fbc6116a902379c7238",
+                6,
+            )]),
+            collections: Vec::new(),
+        }
+    }
+
+    /// Apply the chain's table of the config file
+    fn with_section(mut self, section: &ChainSection) -> Result<Self, ArkError> {
+        let section = section.clone();
+        self.chain_id = section.chain_id.unwrap_or(self.chain_id);
+        self.rpc_url = section.rpc_url.unwrap_or(self.rpc_url);
+        self.confirmations = section.confirmations.unwrap_or(self.confirmations);
+        if let Some(block_time_ms) = section.block_time_ms {
+            self.block_time = Duration::from_millis(block_time_ms);
+        }
+        self.escrow_contract = section.escrow_contract.unwrap_or(self.escrow_contract);
+        if let Some(list) = &section.tokens {
+            self.tokens = self.tokens.with_list(list)?;
+        }
+        if let Some(list) = &section.collections {
+            self.collections = parse_collections(list);
+        }
+        Ok(self)
+    }
+
+    /// Apply `ARK_CHAIN_<NAME>_*` overrides, e.g. `ARK_CHAIN_SEPOLIA_RPC_URL`
+    fn with_env_overrides(mut self) -> Result<Self, ArkError> {
+        let prefix = format!("ARK_CHAIN_{}_", self.name.to_uppercase().replace('-', "_"));
+        let key = |suffix: &str| format!("{}{}", prefix, suffix);
+
+        self.chain_id = env_or(&key("CHAIN_ID"), self.chain_id)?;
+        self.rpc_url = env_or(&key("RPC_URL"), self.rpc_url)?;
+        self.confirmations = env_or(&key("CONFIRMATIONS"), self.confirmations)?;
+        self.block_time = Duration::from_millis(env_or(
+            &key("BLOCK_TIME_MS"),
+            self.block_time.as_millis() as u64,
+        )?);
+        self.escrow_contract = env_or(&key("ESCROW_CONTRACT"), self.escrow_contract)?;
+        if let Ok(list) = env::var(key("TOKENS")) {
+            self.tokens = self.tokens.with_list(&list)?;
+        }
+        if let Ok(list) = env::var(key("COLLECTIONS")) {
+            self.collections = parse_collections(&list);
+        }
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<(), ArkError> {
+        let invalid = |reason: &str| {
+            Err(ArkError::ConfigError(format!(
+                "Chain {}: {}",
+                self.name, reason
+            )))
+        };
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return invalid("name must be lowercase letters, digits and dashes");
+        }
+        if self.chain_id == 0 {
+            return invalid("chain id must be set");
+        }
+        if self.confirmations == 0 || self.block_time.is_zero() {
+            return invalid("confirmations and block time must be positive");
+        }
+        if validate_address(&self.escrow_contract).is_err() {
+            return invalid("escrow contract is not an address");
+        }
+        if self.tokens.all().is_empty() {
+            return invalid("no tokens are configured");
+        }
+        if self.collections.iter().any(|c| c.contains(['#', ':'])) {
+            return invalid("collections must be slugs or contract addresses");
+        }
+        Ok(())
+    }
+}
+
+/// Chains the service can route requests to, by name
+#[derive(Debug, Clone)]
+pub struct ChainRegistry {
+    chains: Vec<ChainConfig>,
+}
+
+impl Default for ChainRegistry {
+    fn default() -> Self {
+        Self {
+            chains: vec![ChainConfig::ark_testnet(), ChainConfig::sepolia()],
+        }
+    }
+}
+
+impl ChainRegistry {
+    /// ARK testnet and Sepolia, plus the chains with a table in the config file or
+    /// named in `ARK_CHAINS` (comma-separated). Each is adjusted by its table of the
+    /// config file, then by its `ARK_CHAIN_<NAME>_*` variables.
+    ///
+    /// An added chain needs at least a chain id, an RPC URL, an escrow contract and
+    /// tokens.
+    pub fn load(sections: &BTreeMap<String, ChainSection>) -> Result<Self, ArkError> {
+        let mut chains = vec![ChainConfig::ark_testnet(), ChainConfig::sepolia()];
+        let env_chains = env::var("ARK_CHAINS").unwrap_or_default();
+        let added = sections
+            .keys()
+            .map(String::as_str)
+            .chain(env_chains.split(',').map(str::trim).filter(|n| !n.is_empty()));
+        for name in added {
+            if chains.iter().any(|c| c.name == name) {
+                continue;
+            }
+            chains.push(ChainConfig {
+                name: name.to_string(),
+                chain_id: 0,
+                rpc_url: String::new(),
+                confirmations: 12,
+                block_time: Duration::from_secs(2),
+                escrow_contract: String::new(),
+                tokens: TokenRegistry::new(Vec::new()),
+                collections: Vec::new(),
+            });
+        }
+
+        let chains = chains
+            .into_iter()
+            .map(|mut chain| {
+                if let Some(section) = sections.get(&chain.name) {
+                    chain = chain.with_section(section)?;
+                }
+                if chain.name == DEFAULT_CHAIN {
+                    chain.rpc_url = env_or("ARK_TESTNET_URL", chain.rpc_url)?;
+                    chain.escrow_contract = env_or("ARK_ESCROW_CONTRACT", chain.escrow_contract)?;
+                    if let Ok(list) = env::var("ARK_TOKENS") {
+                        chain.tokens = chain.tokens.with_list(&list)?;
+                    }
+                }
+                let chain = chain.with_env_overrides()?;
+                chain.validate()?;
+                Ok(chain)
+            })
+            .collect::<Result<_, ArkError>>()?;
+        Ok(Self { chains })
+    }
+
+    pub fn all(&self) -> &[ChainConfig] {
+        &self.chains
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, name: &str) -> Result<&ChainConfig, ArkError> {
+        self.chains
+            .iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| ArkError::UnknownChain(name.to_string()))
+    }
+}
+
+/// Comma-separated collection slugs or addresses, without duplicates
+fn parse_collections(list: &str) -> Vec<String> {
+    let mut collections: Vec<String> = Vec::new();
+    for collection in list.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        if !collections.iter().any(|c| c.eq_ignore_ascii_case(collection)) {
+            collections.push(collection.to_string());
+        }
+    }
+    collections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_chains_are_valid_and_distinct() {
+        let registry = ChainRegistry::default();
+        for chain in registry.all() {
+            chain.validate().unwrap();
+        }
+        let ark = registry.get(DEFAULT_CHAIN).unwrap();
+        let sepolia = registry.get("sepolia").unwrap();
+        assert_ne!(ark.chain_id, sepolia.chain_id);
+        assert!(sepolia.tokens.get(USDC).is_ok());
+        assert!(sepolia.tokens.get("DAI").is_err());
+        assert!(matches!(
+            registry.get("solana"),
+            Err(ArkError::UnknownChain(_))
+        ));
+
+        let mut unnamed = ark.clone();
+        unnamed.name = "Ark Testnet".to_string();
+        assert!(unnamed.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_file_sections_adjust_and_add_chains() {
+        let config = crate::config::Config::parse(
+            r#"
+            [chains.sepolia]
+            confirmations = 3
+
+            [chains.base-sepolia]
+            chain_id = 84532
+            rpc_url = "https://sepolia.base.org"
+            escrow_contract = "0x00000000000000000000000000000000000e5c41"
+            tokens = "USDC:0x00000000000000000000000000000000000ba5e0:6"
+
+            [chains.incomplete]
+            chain_id = 1
+            "#,
+        )
+        .unwrap();
+
+        assert!(ChainRegistry::load(&config.chains).is_err());
+
+        let mut sections = config.chains;
+        sections.remove("incomplete");
+        let registry = ChainRegistry::load(&sections).unwrap();
+        assert_eq!(registry.get("sepolia").unwrap().confirmations, 3);
+        let base = registry.get("base-sepolia").unwrap();
+        assert_eq!(base.chain_id, 84532);
+        assert_eq!(base.tokens.get(USDC).unwrap().decimals, 6);
+    }
+}
@@ -0,0 +1,665 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ark_client::ArkError;
+use crate::chains::ChainSection;
+use crate::fees::FeePolicy;
+use crate::settlement::SettlementPolicy;
+use crate::transactions::{StuckTxAction, StuckTxPolicy};
+use crate::wallet::parse_secret_key;
+
+/// Config file read when `ARK_CONFIG_FILE` is not set, if it exists
+const DEFAULT_CONFIG_FILE: &str = "config/ark.toml";
+
+/// Service configuration.
+///
+/// Built-in defaults are overridden by a TOML file, whose values are in turn
+/// overridden by environment variables, so one image can run in every
+/// environment. Unknown keys in the file are rejected.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub rpc: RpcConfig,
+    pub cache: CacheConfig,
+    pub batch: BatchConfig,
+    /// Endpoint settings by chain name; names that are not built in add a chain
+    pub chains: BTreeMap<String, ChainSection>,
+    pub consensus: ConsensusConfig,
+    pub fees: FeePolicy,
+    pub settlement: SettlementConfig,
+    pub wallet: WalletConfig,
+    pub mandates: MandatesConfig,
+    pub holds: HoldsConfig,
+    pub locks: LocksConfig,
+    pub transactions: TransactionsConfig,
+    pub reconciliation: ReconciliationConfig,
+    pub storage: StorageConfig,
+    pub indexer: IndexerConfig,
+    pub simulation: SimulationConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// `host:port` the HTTP server listens on
+    pub bind_address: String,
+    /// Serve HTTPS instead of HTTP
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0:8080".to_string(),
+            tls: None,
+        }
+    }
+}
+
+/// PEM files of the server certificate chain and its private key
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// How long to wait for a submitted transaction to be confirmed
+    pub confirmation_timeout_secs: u64,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            confirmation_timeout_secs: 120,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How long NFT and token balance query results are reused; 0 disables caching
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 10 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    /// Most items one batch query may list
+    pub max_items: usize,
+    /// Items of a batch queried at the same time
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_items: 100,
+            concurrency: 8,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusConfig {
+    /// Verifiers polled for every deal
+    pub verifiers: usize,
+    /// Share of verifiers that must approve, in (0, 1]
+    pub threshold: f64,
+    /// Signatures a deal needs before verifiers approve it
+    pub min_signatures: usize,
+}
+
+impl Default for ConsensusConfig {
+    fn default() -> Self {
+        Self {
+            verifiers: 7,
+            threshold: 0.67, // 5 out of 7 verifiers
+            min_signatures: 2,
+        }
+    }
+}
+
+/// How escrow prices are split between the seller, the marketplace and creators
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettlementConfig {
+    /// Marketplace fee taken from every escrow price, in basis points
+    pub platform_fee_bps: u32,
+    /// Address the marketplace fee is paid to
+    pub treasury_address: String,
+    /// Comma-separated `collection:recipient:bps` creator royalties
+    pub royalties: String,
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        Self {
+            platform_fee_bps: 250,
+            treasury_address: "0x00000000000000000000000000000000007ea5e7".to_string(),
+            royalties: String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalletConfig {
+    /// Hex-encoded 32-byte Ed25519 seed of the operator wallet; an ephemeral key is
+    /// generated when unset, which is only useful against the simulated testnet
+    pub private_key: Option<String>,
+}
+
+impl fmt::Debug for WalletConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalletConfig")
+            .field(
+                "private_key",
+                &self.private_key.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MandatesConfig {
+    /// Refuse escrows and swaps that are not made under an owner's mandate
+    pub required: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HoldsConfig {
+    /// Expiry of a fund hold placed without one of its own
+    pub ttl_secs: u64,
+    /// Longest expiry a hold may be placed with
+    pub max_ttl_secs: u64,
+}
+
+impl Default for HoldsConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 900,
+            max_ttl_secs: 86400,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocksConfig {
+    /// How long an NFT stays locked for a deal after the deal last took the lock
+    pub ttl_secs: u64,
+}
+
+impl Default for LocksConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 600 }
+    }
+}
+
+/// Recovery of operator transactions that stay unmined
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactionsConfig {
+    /// Pause between two checks for stuck transactions; 0 disables the monitor
+    pub check_interval_secs: u64,
+    /// How long a transaction may stay unmined after its last submission
+    pub stuck_deadline_secs: u64,
+    /// What the monitor does with a transaction past the deadline
+    pub stuck_action: StuckTxAction,
+}
+
+impl Default for TransactionsConfig {
+    fn default() -> Self {
+        Self {
+            check_interval_secs: 15,
+            stuck_deadline_secs: 60,
+            stuck_action: StuckTxAction::SpeedUp,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconciliationConfig {
+    /// Pause between two reconciliations of escrow records with the chains; 0
+    /// disables the background job
+    pub interval_secs: u64,
+}
+
+impl Default for ReconciliationConfig {
+    fn default() -> Self {
+        Self { interval_secs: 300 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Directory the service keeps its files in, created at startup
+    pub data_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("data"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexerConfig {
+    /// Follow every chain's new blocks in the background
+    pub enabled: bool,
+    /// Pause between two indexing steps
+    pub poll_interval_ms: u64,
+    /// Most blocks indexed in one step
+    pub batch_blocks: u64,
+    /// Block a chain without a checkpoint is indexed from; its head by default
+    pub start_block: Option<u64>,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_ms: 2000,
+            batch_blocks: 500,
+            start_block: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    /// Back every chain with the in-process simulator
+    pub enabled: bool,
+    /// Serve `/ledger/deposit` and the `/simulator/*` endpoints that manipulate
+    /// simulated chains; off unless a development setup turns it on
+    pub control_endpoints: bool,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            control_endpoints: false,
+        }
+    }
+}
+
+impl Config {
+    /// Load `ARK_CONFIG_FILE` (or `config/ark.toml` when present), apply the
+    /// environment overrides and validate the result
+    pub fn load() -> Result<Self, ArkError> {
+        let config = match env::var("ARK_CONFIG_FILE") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            Err(_) => Self::default(),
+        };
+        let config = config.with_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ArkError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ArkError::ConfigError(format!("Cannot read {}: {}", path.display(), e)))?;
+        Self::parse(&text).map_err(|e| ArkError::ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ArkError> {
+        toml::from_str(text).map_err(|e| ArkError::ConfigError(e.to_string()))
+    }
+
+    /// Apply the `ARK_*` variables on top of the file's values
+    fn with_env_overrides(mut self) -> Result<Self, ArkError> {
+        self.server.bind_address = env_or("ARK_BIND_ADDRESS", self.server.bind_address)?;
+        match (env::var("ARK_TLS_CERT_PATH"), env::var("ARK_TLS_KEY_PATH")) {
+            (Ok(cert_path), Ok(key_path)) => {
+                self.server.tls = Some(TlsConfig {
+                    cert_path: PathBuf::from(cert_path),
+                    key_path: PathBuf::from(key_path),
+                })
+            }
+            (Err(_), Err(_)) => {}
+            _ => {
+                return Err(ArkError::ConfigError(
+                    "ARK_TLS_CERT_PATH and ARK_TLS_KEY_PATH must be set together".to_string(),
+                ))
+            }
+        }
+
+        self.rpc.confirmation_timeout_secs = env_or(
+            "ARK_CONFIRMATION_TIMEOUT_SECS",
+            self.rpc.confirmation_timeout_secs,
+        )?;
+        self.cache.ttl_secs = env_or("ARK_CACHE_TTL_SECS", self.cache.ttl_secs)?;
+        self.batch.max_items = env_or("ARK_BATCH_MAX_ITEMS", self.batch.max_items)?;
+        self.batch.concurrency = env_or("ARK_BATCH_CONCURRENCY", self.batch.concurrency)?;
+
+        self.consensus.verifiers = env_or("ARK_CONSENSUS_VERIFIERS", self.consensus.verifiers)?;
+        self.consensus.threshold = env_or("ARK_CONSENSUS_THRESHOLD", self.consensus.threshold)?;
+        self.consensus.min_signatures = env_or(
+            "ARK_CONSENSUS_MIN_SIGNATURES",
+            self.consensus.min_signatures,
+        )?;
+
+        self.fees = self.fees.with_env_overrides()?;
+        self.settlement.platform_fee_bps =
+            env_or("ARK_PLATFORM_FEE_BPS", self.settlement.platform_fee_bps)?;
+        self.settlement.treasury_address =
+            env_or("ARK_TREASURY_ADDRESS", self.settlement.treasury_address)?;
+        self.settlement.royalties = env_or("ARK_ROYALTIES", self.settlement.royalties)?;
+        match env::var("ARK_PRIVATE_KEY") {
+            Ok(key) if !key.trim().is_empty() => self.wallet.private_key = Some(key),
+            _ => {}
+        }
+        self.mandates.required = env_or("ARK_REQUIRE_MANDATE", self.mandates.required)?;
+        self.holds.ttl_secs = env_or("ARK_HOLD_TTL_SECS", self.holds.ttl_secs)?;
+        self.holds.max_ttl_secs = env_or("ARK_HOLD_MAX_TTL_SECS", self.holds.max_ttl_secs)?;
+        self.locks.ttl_secs = env_or("ARK_NFT_LOCK_TTL_SECS", self.locks.ttl_secs)?;
+        self.transactions.check_interval_secs = env_or(
+            "ARK_STUCK_TX_CHECK_INTERVAL_SECS",
+            self.transactions.check_interval_secs,
+        )?;
+        self.transactions.stuck_deadline_secs = env_or(
+            "ARK_STUCK_TX_DEADLINE_SECS",
+            self.transactions.stuck_deadline_secs,
+        )?;
+        self.transactions.stuck_action =
+            env_or("ARK_STUCK_TX_ACTION", self.transactions.stuck_action)?;
+        self.reconciliation.interval_secs = env_or(
+            "ARK_RECONCILIATION_INTERVAL_SECS",
+            self.reconciliation.interval_secs,
+        )?;
+        self.storage.data_dir = env_or("ARK_DATA_DIR", self.storage.data_dir)?;
+        self.indexer.enabled = env_or("ARK_INDEXER_ENABLED", self.indexer.enabled)?;
+        self.indexer.poll_interval_ms = env_or(
+            "ARK_INDEXER_POLL_INTERVAL_MS",
+            self.indexer.poll_interval_ms,
+        )?;
+        self.indexer.batch_blocks = env_or("ARK_INDEXER_BATCH_BLOCKS", self.indexer.batch_blocks)?;
+        if env::var("ARK_INDEXER_START_BLOCK").is_ok() {
+            self.indexer.start_block = Some(env_or("ARK_INDEXER_START_BLOCK", 0)?);
+        }
+        self.simulation.enabled = env_or("ARK_SIMULATION", self.simulation.enabled)?;
+        self.simulation.control_endpoints = env_or(
+            "ARK_SIMULATION_CONTROL_ENDPOINTS",
+            self.simulation.control_endpoints,
+        )?;
+        Ok(self)
+    }
+
+    /// Check every section; chain endpoints are checked when the registry is built
+    pub fn validate(&self) -> Result<(), ArkError> {
+        let invalid = |reason: String| Err(ArkError::ConfigError(reason));
+
+        if self.server.bind_address.parse::<SocketAddr>().is_err() {
+            return invalid(format!(
+                "server.bind_address '{}' is not an ip:port address",
+                self.server.bind_address
+            ));
+        }
+        if let Some(tls) = &self.server.tls {
+            for (key, path) in [("cert_path", &tls.cert_path), ("key_path", &tls.key_path)] {
+                if !path.is_file() {
+                    return invalid(format!(
+                        "server.tls.{} '{}' is not a file",
+                        key,
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        if self.rpc.confirmation_timeout_secs == 0 {
+            return invalid("rpc.confirmation_timeout_secs must be positive".to_string());
+        }
+        if self.batch.max_items == 0 || self.batch.concurrency == 0 {
+            return invalid("batch.max_items and batch.concurrency must be positive".to_string());
+        }
+
+        let consensus = &self.consensus;
+        if consensus.verifiers == 0 || consensus.min_signatures == 0 {
+            return invalid("consensus needs at least one verifier and one signature".to_string());
+        }
+        if !(consensus.threshold > 0.0 && consensus.threshold <= 1.0) {
+            return invalid(format!(
+                "consensus.threshold {} is not in (0, 1]",
+                consensus.threshold
+            ));
+        }
+
+        self.fees.validate()?;
+        SettlementPolicy::from_config(&self.settlement)?;
+        if let Some(key) = &self.wallet.private_key {
+            parse_secret_key(key)?;
+        }
+        if self.holds.ttl_secs == 0 || self.locks.ttl_secs == 0 {
+            return invalid("holds.ttl_secs and locks.ttl_secs must be positive".to_string());
+        }
+        if self.holds.ttl_secs > self.holds.max_ttl_secs {
+            return invalid("holds.ttl_secs cannot exceed holds.max_ttl_secs".to_string());
+        }
+        // A zero deadline would replace a stuck transaction on every check
+        if self.transactions.stuck_deadline_secs == 0 {
+            return invalid("transactions.stuck_deadline_secs must be positive".to_string());
+        }
+
+        if self.storage.data_dir.as_os_str().is_empty() {
+            return invalid("storage.data_dir must be set".to_string());
+        }
+        if self.indexer.poll_interval_ms == 0 || self.indexer.batch_blocks == 0 {
+            return invalid(
+                "indexer.poll_interval_ms and indexer.batch_blocks must be positive".to_string(),
+            );
+        }
+        if !self.simulation.enabled {
+            return invalid(
+                "simulation.enabled = false needs live RPC backends, which are not supported yet"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl RpcConfig {
+    pub fn confirmation_timeout(&self) -> Duration {
+        Duration::from_secs(self.confirmation_timeout_secs)
+    }
+}
+
+impl CacheConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+}
+
+impl HoldsConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    pub fn max_ttl(&self) -> Duration {
+        Duration::from_secs(self.max_ttl_secs)
+    }
+}
+
+impl LocksConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+}
+
+impl TransactionsConfig {
+    /// Interval of the stuck-transaction monitor, unless it is disabled
+    pub fn check_interval(&self) -> Option<Duration> {
+        (self.check_interval_secs > 0).then(|| Duration::from_secs(self.check_interval_secs))
+    }
+
+    pub fn stuck_tx_policy(&self) -> StuckTxPolicy {
+        StuckTxPolicy {
+            deadline: Duration::from_secs(self.stuck_deadline_secs),
+            action: self.stuck_action,
+        }
+    }
+}
+
+impl ReconciliationConfig {
+    /// Interval of the background reconciliation, unless it is disabled
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval_secs > 0).then(|| Duration::from_secs(self.interval_secs))
+    }
+}
+
+impl IndexerConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+
+/// Value of an environment variable, or `default` when it is not set; a value that
+/// does not parse is an error rather than silently replaced by the default
+pub fn env_or<T: std::str::FromStr>(key: &str, default: T) -> Result<T, ArkError> {
+    match env::var(key) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| ArkError::ConfigError(format!("{} has an invalid value: {}", key, value))),
+        Err(_) => Ok(default),
+    }
+}
+
+impl TlsConfig {
+    /// Load the certificate chain and key into a rustls server configuration
+    pub fn server_config(&self) -> Result<rustls::ServerConfig, ArkError> {
+        let open = |path: &Path| {
+            fs::File::open(path).map(BufReader::new).map_err(|e| {
+                ArkError::ConfigError(format!("Cannot read {}: {}", path.display(), e))
+            })
+        };
+        let invalid_pem = |path: &Path, e: String| {
+            ArkError::ConfigError(format!("{} is not valid PEM: {}", path.display(), e))
+        };
+
+        let certs = rustls_pemfile::certs(&mut open(&self.cert_path)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid_pem(&self.cert_path, e.to_string()))?;
+        if certs.is_empty() {
+            return Err(invalid_pem(&self.cert_path, "no certificate".to_string()));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&self.key_path)?)
+            .map_err(|e| invalid_pem(&self.key_path, e.to_string()))?
+            .ok_or_else(|| invalid_pem(&self.key_path, "no private key".to_string()))?;
+
+        rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| ArkError::ConfigError(format!("Invalid TLS configuration: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_values_replace_defaults() {
+        let config = Config::parse(
+            r#"
+            [server]
+            bind_address = "127.0.0.1:9090"
+
+            [consensus]
+            verifiers = 4
+            threshold = 0.75
+
+            [fees]
+            max_fee_per_gas_gwei = 50
+
+            [chains.sepolia]
+            rpc_url = "https://sepolia.example.org"
+
+            [chains.base-sepolia]
+            chain_id = 84532
+
+            [simulation]
+            control_endpoints = true
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.server.bind_address, "127.0.0.1:9090");
+        assert_eq!(config.consensus.verifiers, 4);
+        assert_eq!(config.consensus.min_signatures, 2);
+        assert_eq!(config.fees.max_fee_per_gas_gwei, 50);
+        assert_eq!(config.fees.priority_fee_per_gas_gwei, 2);
+        assert_eq!(config.rpc.confirmation_timeout(), Duration::from_secs(120));
+        assert_eq!(config.chains.len(), 2);
+        assert_eq!(config.chains["base-sepolia"].chain_id, Some(84532));
+        assert!(config.simulation.control_endpoints);
+        assert!(!Config::default().simulation.control_endpoints);
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys_and_invalid_values() {
+        assert!(Config::parse("[server]\nport = 8080").is_err());
+        assert!(Config::parse("[consensus]\nverifiers = \"seven\"").is_err());
+        assert!(Config::parse("[transactions]\nstuck_action = \"retry\"").is_err());
+
+        let invalid = [
+            "[server]\nbind_address = \"localhost\"",
+            "[server.tls]\ncert_path = \"missing.pem\"\nkey_path = \"missing.key\"",
+            "[consensus]\nthreshold = 1.5",
+            "[rpc]\nconfirmation_timeout_secs = 0",
+            "[fees]\npriority_fee_per_gas_gwei = 500",
+            "[settlement]\ntreasury_address = \"0x1234\"",
+            "[settlement]\nroyalties = \"BAYC:0x00000000000000000000000000000000000c4ea7:10000\"",
+            "[wallet]\nprivate_key = \"not-hex\"",
+            "[holds]\nttl_secs = 0",
+            "[holds]\nttl_secs = 3600\nmax_ttl_secs = 60",
+            "[transactions]\nstuck_deadline_secs = 0",
+            "[simulation]\nenabled = false",
+        ];
+        for text in invalid {
+            let config = Config::parse(text).unwrap();
+            assert!(
+                matches!(config.validate(), Err(ArkError::ConfigError(_))),
+                "{} should be rejected",
+                text
+            );
+        }
+    }
+}
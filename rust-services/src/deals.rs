@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::ark_client::ArkError;
+
+/// Deals whose escrow or swap is being executed, so one deal cannot run twice at once.
+///
+/// A deal is registered before anything is reserved for it and stays registered
+/// until its transaction has an outcome.
+pub struct ActiveDeals {
+    deals: Mutex<HashSet<String>>,
+}
+
+impl ActiveDeals {
+    pub fn new() -> Self {
+        Self {
+            deals: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Register a deal for execution; fails if it is already being executed
+    pub fn start(&self, deal_id: &str) -> Result<(), ArkError> {
+        if !self.deals.lock().unwrap().insert(deal_id.to_string()) {
+            return Err(ArkError::DealConflict(format!(
+                "deal {} is already being executed",
+                deal_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Unregister a deal whose transaction has an outcome
+    pub fn finish(&self, deal_id: &str) {
+        self.deals.lock().unwrap().remove(deal_id);
+    }
+}
+
+impl Default for ActiveDeals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_deal_runs_once_at_a_time() {
+        let deals = ActiveDeals::new();
+        deals.start("deal-1").unwrap();
+        assert!(matches!(
+            deals.start("deal-1"),
+            Err(ArkError::DealConflict(_))
+        ));
+        deals.start("deal-2").unwrap();
+
+        // Once it has an outcome, the deal may be registered again
+        deals.finish("deal-1");
+        deals.start("deal-1").unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use serde::Deserialize;
+
+use crate::ark_client::ArkError;
+use crate::config::env_or;
+
+/// Fee policy for transactions submitted by the operator wallet.
+///
+/// Gas prices are in gwei of the chain's native token.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeePolicy {
+    /// Hard cap on the max fee per gas, bumps never exceed it
+    pub max_fee_per_gas_gwei: u64,
+    /// Priority tip offered to block producers
+    pub priority_fee_per_gas_gwei: u64,
+    /// Percentage a replacement raises the fees by
+    pub bump_percent: u64,
+    /// Extra gas limit on top of the estimate
+    pub gas_limit_margin_percent: u64,
+    /// Price of one native token in USDC, used to charge fees to agents' budgets
+    pub native_token_price_usdc: f64,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        Self {
+            max_fee_per_gas_gwei: 100,
+            priority_fee_per_gas_gwei: 2,
+            bump_percent: 20,
+            gas_limit_margin_percent: 20,
+            native_token_price_usdc: 0.25,
+        }
+    }
+}
+
+impl FeePolicy {
+    /// Apply the `ARK_FEE_*` environment variables on top of this policy
+    pub fn with_env_overrides(self) -> Result<Self, ArkError> {
+        Ok(Self {
+            max_fee_per_gas_gwei: env_or("ARK_FEE_MAX_GWEI", self.max_fee_per_gas_gwei)?,
+            priority_fee_per_gas_gwei: env_or(
+                "ARK_FEE_PRIORITY_GWEI",
+                self.priority_fee_per_gas_gwei,
+            )?,
+            bump_percent: env_or("ARK_FEE_BUMP_PERCENT", self.bump_percent)?,
+            gas_limit_margin_percent: env_or(
+                "ARK_GAS_LIMIT_MARGIN_PERCENT",
+                self.gas_limit_margin_percent,
+            )?,
+            native_token_price_usdc: env_or(
+                "ARK_NATIVE_TOKEN_PRICE_USDC",
+                self.native_token_price_usdc,
+            )?,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), ArkError> {
+        if self.priority_fee_per_gas_gwei > self.max_fee_per_gas_gwei {
+            return Err(ArkError::ConfigError(format!(
+                "Priority fee ({} gwei) exceeds the max fee cap ({} gwei)",
+                self.priority_fee_per_gas_gwei, self.max_fee_per_gas_gwei
+            )));
+        }
+        if self.bump_percent < 10 {
+            return Err(ArkError::ConfigError(
+                "Fee bumps need bump_percent >= 10".to_string(),
+            ));
+        }
+        if !self.native_token_price_usdc.is_finite() || self.native_token_price_usdc < 0.0 {
+            return Err(ArkError::ConfigError(
+                "Native token price must be a non-negative number".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Gas limit for a transaction whose estimate is `estimated_gas`
+    pub fn gas_limit(&self, estimated_gas: u64) -> u64 {
+        estimated_gas + estimated_gas * self.gas_limit_margin_percent / 100
+    }
+
+    /// Initial `(max_fee, priority_fee)` for the current base fee: room for the base
+    /// fee to double before the transaction stops being includable, within the cap
+    pub fn initial_fees(&self, base_fee_gwei: u64) -> (u64, u64) {
+        let tip = self.priority_fee_per_gas_gwei;
+        let max_fee = (base_fee_gwei * 2 + tip).min(self.max_fee_per_gas_gwei);
+        (max_fee, tip.min(max_fee))
+    }
+
+    /// Raised `(max_fee, priority_fee)` for a replacement, or `None` once at the cap
+    pub fn bumped_fees(&self, max_fee_gwei: u64, priority_fee_gwei: u64) -> Option<(u64, u64)> {
+        if max_fee_gwei >= self.max_fee_per_gas_gwei {
+            return None;
+        }
+        let raise = |fee: u64| fee + (fee * self.bump_percent).div_ceil(100).max(1);
+        let max_fee = raise(max_fee_gwei).min(self.max_fee_per_gas_gwei);
+        Some((max_fee, raise(priority_fee_gwei).min(max_fee)))
+    }
+
+    /// Value of a fee paid in native tokens, in USDC
+    pub fn fee_in_usdc(&self, fee_native: f64) -> f64 {
+        fee_native * self.native_token_price_usdc
+    }
+}
+
+/// Fee in native tokens for `gas_used` at `gas_price_gwei`
+pub fn fee_in_native(gas_used: u64, gas_price_gwei: u64) -> f64 {
+    gas_used as f64 * gas_price_gwei as f64 / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_fees_respect_cap() {
+        let policy = FeePolicy::default();
+        assert_eq!(policy.initial_fees(10), (22, 2));
+        assert_eq!(policy.initial_fees(80), (100, 2));
+    }
+
+    #[test]
+    fn test_bumps_stop_at_cap() {
+        let policy = FeePolicy::default();
+        assert_eq!(policy.bumped_fees(22, 2), Some((27, 3)));
+        assert_eq!(policy.bumped_fees(95, 2), Some((100, 3)));
+        assert_eq!(policy.bumped_fees(100, 3), None);
+    }
+
+    #[test]
+    fn test_invalid_policy_rejected() {
+        let policy = FeePolicy {
+            priority_fee_per_gas_gwei: 200,
+            ..FeePolicy::default()
+        };
+        assert!(matches!(policy.validate(), Err(ArkError::ConfigError(_))));
+    }
+}
@@ -1,10 +1,35 @@
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, Responder};
 use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use futures::future::try_join_all;
+use futures::{stream, FutureExt, StreamExt};
 use rand::Rng;
-use sha2::{Digest, Sha256};
+use std::future::Future;
 
-use crate::ark_client::ArkClient;
+use crate::ark_client::{
+    ArkClient, ArkError, EscrowTransaction, SwapSide, SwapTransaction, TransactionLog,
+    TransactionReceipt, TxStatus,
+};
+use crate::chains::DEFAULT_CHAIN;
+use crate::holds::FundHolds;
+use crate::ledger::{from_units, to_units, Ledger};
+use crate::locks::LockStage;
+use crate::mandates::{MandateReservation, MandateUse, Mandates, SignedMandate};
 use crate::models::*;
+use crate::nft::{validate_bundle, NftRef, TokenStandard};
+use crate::policy::{
+    PolicyReservation, PolicySubject, PolicyViolation, Spend, SpendingPolicies, SpendingPolicy,
+};
+use crate::reconciliation::reconcile;
+use crate::records::{DealEvent, EscrowRecord};
+use crate::settlement::Payout;
+use crate::state::AppState;
+use crate::tokens::{totals, TokenAmount, TokenInfo, TokenRegistry};
+use crate::transactions::PendingTransaction;
+use crate::wallet::validate_address;
+
+/// Most entries one page of a portfolio or transaction history lists
+const MAX_PAGE_SIZE: usize = 100;
 
 /// Health check endpoint
 pub async fn health_check() -> impl Responder {
@@ -16,7 +41,10 @@ pub async fn health_check() -> impl Responder {
 }
 
 /// Verify Ed25519 signature
-pub async fn verify_signature(payload: web::Json<VerifySignatureRequest>) -> impl Responder {
+pub async fn verify_signature(
+    state: web::Data<AppState>,
+    payload: web::Json<VerifySignatureRequest>,
+) -> impl Responder {
     log::info!("Verifying signature for message: {}", payload.message);
 
     // Decode public key from hex
@@ -85,6 +113,16 @@ pub async fn verify_signature(payload: web::Json<VerifySignatureRequest>) -> imp
 
     log::info!("Signature verification result: {}", valid);
 
+    if let Some(deal_id) = &payload.deal_id {
+        state.timelines.record(
+            deal_id,
+            DealEvent::SignatureVerified {
+                public_key: payload.public_key.clone(),
+                valid,
+            },
+        );
+    }
+
     HttpResponse::Ok().json(VerifySignatureResponse {
         valid,
         error: None,
@@ -92,17 +130,68 @@ pub async fn verify_signature(payload: web::Json<VerifySignatureRequest>) -> imp
 }
 
 /// Run BFT consensus with 7 mock verifiers
-pub async fn run_consensus(payload: web::Json<ConsensusRequest>) -> impl Responder {
+pub async fn run_consensus(
+    state: web::Data<AppState>,
+    payload: web::Json<ConsensusRequest>,
+) -> impl Responder {
     use std::time::Instant;
 
+    let AppState {
+        nft_locks: locks,
+        spending_policies: policies,
+        mandates,
+        holds,
+        ..
+    } = state.get_ref();
     let start_time = Instant::now();
     log::info!("Running BFT consensus for deal: {}", payload.deal_id);
 
-    const VERIFIER_COUNT: usize = 7;
-    const THRESHOLD: f64 = 0.67; // 67% approval required (5 out of 7 verifiers)
+    // Quantities are part of what the buyer agent signs for under a mandate
+    let nfts = match consensus_nfts(&payload) {
+        Ok(nfts) => nfts,
+        Err(e) => {
+            log::error!("Invalid consensus request: {}", e);
+            return invalid_request_response(&e);
+        }
+    };
+
+    // Verifiers are only asked about deals the buyer agent is allowed to enter
+    if let Some(response) = consensus_spend_rejection(policies, mandates, &payload, &nfts) {
+        return response;
+    }
+
+    // The buyer's balance must cover the price on top of what other deals hold
+    let held = payload.buyer_address.as_deref().map_or(0.0, |address| {
+        holds.held_by_others(&payload.deal_id, &payload.chain, address, &payload.token)
+    });
+    let funded = to_units(payload.buyer_balance).and_then(|balance| {
+        let needed = to_units(held)? + to_units(payload.price.unwrap_or(0.0))?;
+        Ok(balance > 0 && balance >= needed)
+    });
+    let funded = match funded {
+        Ok(funded) => funded,
+        Err(e) => return invalid_request_response(&e),
+    };
+
+    // Verifiers check what the seller holds on chain, not what the request claims
+    let nft_check = match seller_holds_nfts(&state, &payload, &nfts).await {
+        Ok(held) => held,
+        Err(e) => {
+            log::error!("Failed to query seller holdings: {}", e);
+            let (status, error) = query_error(&e, "NFT_QUERY_FAILED", "seller holdings");
+            return HttpResponse::build(status).json(error);
+        }
+    };
+
+    // The NFTs stay locked for the deal until its escrow completes, fails or the lock expires
+    if let Err(e) = locks.acquire(&payload.deal_id, &nfts, LockStage::Consensus) {
+        return nft_locked_response(&payload.deal_id, &e);
+    }
+
+    let consensus = &state.config.consensus;
 
-    // Generate 7 random verifier IDs
-    let verifier_ids: Vec<String> = (0..VERIFIER_COUNT)
+    // Generate random verifier IDs
+    let verifier_ids: Vec<String> = (0..consensus.verifiers)
         .map(|_| format!("verifier-{:02x}", rand::thread_rng().gen::<u8>()))
         .collect();
 
@@ -116,9 +205,8 @@ pub async fn run_consensus(payload: web::Json<ConsensusRequest>) -> impl Respond
         // 2. Buyer has sufficient balance (from blockchain query)
         // 3. Signatures are valid (cryptographic verification)
 
-        let nft_check = payload.nft_ownership;
-        let balance_check = payload.buyer_balance > 0.0;
-        let signature_check = !payload.signatures.is_empty() && payload.signatures.len() >= 2;
+        let balance_check = funded;
+        let signature_check = payload.signatures.len() >= consensus.min_signatures;
 
         // Verifier approves if all checks pass
         let approves = nft_check && balance_check && signature_check;
@@ -135,7 +223,7 @@ pub async fn run_consensus(payload: web::Json<ConsensusRequest>) -> impl Respond
             nft_check,
             payload.buyer_balance,
             payload.signatures.len(),
-            2
+            consensus.min_signatures
         );
 
         verifier_results.push(crate::models::VerifierResult {
@@ -154,159 +242,2967 @@ pub async fn run_consensus(payload: web::Json<ConsensusRequest>) -> impl Respond
         )).await;
     }
 
-    let approval_rate = approval_count as f64 / VERIFIER_COUNT as f64;
-    let approved = approval_rate >= THRESHOLD;
+    let approval_rate = approval_count as f64 / consensus.verifiers as f64;
+    let approved = approval_rate >= consensus.threshold;
 
     let execution_time = start_time.elapsed().as_millis();
+    if !approved {
+        locks.release(&payload.deal_id);
+    }
+    state.timelines.record(
+        &payload.deal_id,
+        DealEvent::ConsensusReached {
+            approved,
+            approval_count,
+            verifier_count: consensus.verifiers,
+        },
+    );
 
     log::info!(
         "Consensus result: {} ({}/{} verifiers approved, rate: {:.2}%, time: {}ms)",
         approved,
         approval_count,
-        VERIFIER_COUNT,
+        consensus.verifiers,
         approval_rate * 100.0,
         execution_time
     );
 
     HttpResponse::Ok().json(crate::models::ConsensusResponse {
         approved,
-        verifier_count: VERIFIER_COUNT,
+        verifier_count: consensus.verifiers,
         approval_count,
-        threshold: THRESHOLD,
+        threshold: consensus.threshold,
         verifiers: verifier_results,
         execution_time_ms: execution_time,
     })
 }
 
+/// Rejection of a deal put to consensus by the buyer agent's spending policies or
+/// mandate, as far as the request describes the deal
+fn consensus_spend_rejection(
+    policies: &SpendingPolicies,
+    mandates: &Mandates,
+    payload: &ConsensusRequest,
+    nfts: &[NftRef],
+) -> Option<HttpResponse> {
+    let price = payload
+        .price
+        .map(|price| TokenAmount::new(&payload.token, price));
+
+    let checked = policies.check(&Spend {
+        deal_id: &payload.deal_id,
+        wallet: payload.buyer_address.as_deref(),
+        agent_id: payload.spender.agent_id.as_deref(),
+        owner_id: payload.spender.owner_id.as_deref(),
+        amount: price.as_ref(),
+        nfts,
+        counterparty: Some(&payload.seller_address),
+    });
+    match checked {
+        Ok(violations) if violations.is_empty() => {}
+        Ok(violations) => return Some(policy_violation_response(&payload.deal_id, violations)),
+        Err(e) => return Some(invalid_request_response(&e)),
+    }
+
+    mandate_use(
+        mandates,
+        &payload.spender,
+        &payload.deal_id,
+        None,
+        Some(&payload.seller_address),
+        price.as_slice(),
+        nfts,
+    )
+    .and_then(|spend| spend.map_or(Ok(()), |spend| mandates.check(&spend)))
+    .err()
+    .map(|e| mandate_rejected_response(&payload.deal_id, &e))
+}
+
+/// The agent's claim to pay `amounts` to `seller` in the deal under its owner's
+/// mandate, or none if the agent names no mandate and mandates are optional
+fn mandate_use<'a>(
+    mandates: &Mandates,
+    spender: &'a Spender,
+    deal_id: &'a str,
+    buyer_address: Option<&'a str>,
+    seller: Option<&'a str>,
+    amounts: &'a [TokenAmount],
+    nfts: &'a [NftRef],
+) -> Result<Option<MandateUse<'a>>, ArkError> {
+    let Some(mandate_id) = &spender.mandate_id else {
+        if mandates.required() {
+            return Err(ArkError::InvalidMandate(
+                "deals must be made under an owner's mandate".to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+    let (Some(agent_signature), Some(seller), false) =
+        (&spender.agent_signature, seller, amounts.is_empty())
+    else {
+        return Err(ArkError::InvalidMandate(
+            "a deal under a mandate needs the price, the seller and the agent's signature"
+                .to_string(),
+        ));
+    };
+
+    Ok(Some(MandateUse {
+        mandate_id,
+        agent_signature,
+        deal_id,
+        buyer_address,
+        seller,
+        amounts,
+        nfts,
+    }))
+}
+
+/// Error response for a deal whose spending could not be checked: 409 if the deal is
+/// already counted, 400 if the request is malformed
+fn spend_error_response(deal_id: &str, e: &ArkError) -> HttpResponse {
+    match e {
+        ArkError::DealConflict(_) => {
+            log::warn!("Deal {} rejected: {}", deal_id, e);
+            HttpResponse::Conflict().json(ErrorResponse {
+                error: "DEAL_CONFLICT".to_string(),
+                message: e.to_string(),
+            })
+        }
+        _ => invalid_request_response(e),
+    }
+}
+
+/// 409 response for a deal trading an NFT another deal has locked
+fn nft_locked_response(deal_id: &str, e: &ArkError) -> HttpResponse {
+    log::warn!("Deal {} rejected: {}", deal_id, e);
+    HttpResponse::Conflict().json(ErrorResponse {
+        error: "NFT_LOCKED".to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// 403 response for a deal the agent has no valid mandate for, 409 for one already
+/// committed against its mandate
+fn mandate_rejected_response(deal_id: &str, e: &ArkError) -> HttpResponse {
+    if let ArkError::DealConflict(_) = e {
+        return spend_error_response(deal_id, e);
+    }
+    log::warn!("Deal {} rejected: {}", deal_id, e);
+    HttpResponse::Forbidden().json(ErrorResponse {
+        error: "MANDATE_REJECTED".to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// 403 response listing every spending policy rule a deal breaks
+fn policy_violation_response(deal_id: &str, violations: Vec<PolicyViolation>) -> HttpResponse {
+    let messages: Vec<&str> = violations.iter().map(|v| v.message.as_str()).collect();
+    log::warn!(
+        "Deal {} rejected by spending policy: {}",
+        deal_id,
+        messages.join("; ")
+    );
+    HttpResponse::Forbidden().json(PolicyViolationResponse {
+        error: "POLICY_VIOLATION".to_string(),
+        message: format!("Deal {} breaks the spending policy: {}", deal_id, messages.join("; ")),
+        violations,
+    })
+}
+
 /// Query NFT ownership on ARK Network
-pub async fn query_nft_ownership(payload: web::Json<NftOwnershipRequest>) -> impl Responder {
+pub async fn query_nft_ownership(
+    state: web::Data<AppState>,
+    payload: web::Json<NftOwnershipRequest>,
+) -> impl Responder {
     log::info!(
-        "Querying NFT ownership: collection={}, token_id={}, owner={}",
-        payload.collection,
-        payload.token_id,
+        "Querying NFT ownership: nft={}, owner={}",
+        payload.nft,
         payload.owner_address
     );
 
-    match ArkClient::new() {
-        Ok(client) => {
-            match client
-                .query_nft_ownership(&payload.collection, &payload.token_id, &payload.owner_address)
-                .await
-            {
-                Ok(owned) => {
-                    log::info!("NFT ownership result: {}", owned);
-                    HttpResponse::Ok().json(NftOwnershipResponse {
-                        owned,
-                        collection: payload.collection.clone(),
-                        token_id: payload.token_id.clone(),
-                        owner: payload.owner_address.clone(),
-                    })
-                }
-                Err(e) => {
-                    log::error!("Failed to query NFT ownership: {}", e);
-                    HttpResponse::InternalServerError().json(ErrorResponse {
-                        error: "NFT_QUERY_FAILED".to_string(),
-                        message: format!("Failed to query NFT ownership: {}", e),
-                    })
-                }
-            }
+    match nft_ownership(&state, &payload).await {
+        Ok(response) => {
+            log::info!(
+                "NFT ownership result: {} (source: {:?})",
+                response.owned,
+                response.source
+            );
+            HttpResponse::Ok().json(response)
         }
         Err(e) => {
-            log::error!("Failed to create ARK client: {}", e);
-            HttpResponse::InternalServerError().json(ErrorResponse {
-                error: "ARK_CLIENT_ERROR".to_string(),
-                message: format!("Failed to initialize ARK client: {}", e),
-            })
+            log::error!("Failed to query NFT ownership: {}", e);
+            let (status, error) = query_error(&e, "NFT_QUERY_FAILED", "NFT ownership");
+            HttpResponse::build(status).json(error)
         }
     }
 }
 
-/// Query USDC balance on ARK Network
-pub async fn query_usdc_balance(payload: web::Json<BalanceRequest>) -> impl Responder {
-    log::info!("Querying USDC balance for address: {}", payload.address);
+/// Query the ownership of several NFTs at once, a bounded number concurrently
+pub async fn query_nft_ownership_batch(
+    state: web::Data<AppState>,
+    payload: web::Json<BatchRequest<NftOwnershipRequest>>,
+) -> impl Responder {
+    log::info!("Querying NFT ownership of {} items", payload.items.len());
+    if let Some(response) = batch_size_rejection(&state, payload.items.len()) {
+        return response;
+    }
 
-    match ArkClient::new() {
-        Ok(client) => {
-            match client.query_usdc_balance(&payload.address).await {
-                Ok(balance) => {
-                    log::info!("USDC balance: {} USDC", balance);
-                    HttpResponse::Ok().json(BalanceResponse {
-                        address: payload.address.clone(),
-                        balance,
-                    })
-                }
-                Err(e) => {
-                    log::error!("Failed to query USDC balance: {}", e);
-                    HttpResponse::InternalServerError().json(ErrorResponse {
-                        error: "BALANCE_QUERY_FAILED".to_string(),
-                        message: format!("Failed to query USDC balance: {}", e),
-                    })
+    let response = run_batch(&state, &payload.items, "NFT_QUERY_FAILED", "NFT ownership", |item| {
+        nft_ownership(&state, item)
+    })
+    .await;
+    HttpResponse::Ok().json(response)
+}
+
+/// Whether an address holds an NFT, answered from the index or query cache when possible
+async fn nft_ownership(
+    state: &AppState,
+    query: &NftOwnershipRequest,
+) -> Result<NftOwnershipResponse, ArkError> {
+    let balance = nft_balance(state, query).await?;
+    Ok(NftOwnershipResponse {
+        owned: balance.balance >= query.nft.amount,
+        nft: balance.nft,
+        owner: balance.owner,
+        locked_by: state.nft_locks.get(&query.nft).map(|lock| lock.deal_id),
+        cached: balance.cached,
+        source: balance.source,
+    })
+}
+
+/// Units of an NFT token id an address holds, answered from the index or query cache
+/// when possible
+async fn nft_balance(
+    state: &AppState,
+    query: &NftOwnershipRequest,
+) -> Result<NftBalanceResponse, ArkError> {
+    query.nft.validate()?;
+    validate_address(&query.owner_address)?;
+    let client = state.client(&query.nft.chain)?;
+    let asset = Asset::Nft(&query.nft);
+    let (balance, source) = cached_balance(state, client, asset, &query.owner_address).await?;
+    Ok(NftBalanceResponse {
+        nft: query.nft.clone(),
+        owner: query.owner_address.clone(),
+        // NFT balances are read as u64 in the first place
+        balance: u64::try_from(balance).unwrap_or(u64::MAX),
+        cached: source == BalanceSource::Cache,
+        source,
+    })
+}
+
+/// Query how many units of an NFT token id an address holds on ARK Network
+pub async fn query_nft_balance(
+    state: web::Data<AppState>,
+    payload: web::Json<NftOwnershipRequest>,
+) -> impl Responder {
+    log::info!(
+        "Querying NFT balance: nft={}, owner={}",
+        payload.nft,
+        payload.owner_address
+    );
+
+    match nft_balance(&state, &payload).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            log::error!("Failed to query NFT balance: {}", e);
+            let (status, error) = query_error(&e, "NFT_QUERY_FAILED", "NFT balance");
+            HttpResponse::build(status).json(error)
+        }
+    }
+}
+
+/// Query the balance of a registered token (USDC unless another is named) on ARK Network,
+/// less the funds held for pending deals
+pub async fn query_token_balance(
+    state: web::Data<AppState>,
+    payload: web::Json<BalanceRequest>,
+) -> impl Responder {
+    log::info!(
+        "Querying {} balance on {} for address: {}",
+        payload.token,
+        payload.chain,
+        payload.address
+    );
+
+    match token_balance(&state, &payload).await {
+        Ok(response) => {
+            log::info!(
+                "{} balance: {} ({} held, cached: {})",
+                response.token,
+                response.balance,
+                response.held,
+                response.cached
+            );
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => {
+            log::error!("Failed to query {} balance: {}", payload.token, e);
+            let what = format!("{} balance", payload.token);
+            let (status, error) = query_error(&e, "BALANCE_QUERY_FAILED", &what);
+            HttpResponse::build(status).json(error)
+        }
+    }
+}
+
+/// Query several token balances at once, a bounded number concurrently
+pub async fn query_token_balance_batch(
+    state: web::Data<AppState>,
+    payload: web::Json<BatchRequest<BalanceRequest>>,
+) -> impl Responder {
+    log::info!("Querying {} token balances", payload.items.len());
+    if let Some(response) = batch_size_rejection(&state, payload.items.len()) {
+        return response;
+    }
+
+    let response = run_batch(&state, &payload.items, "BALANCE_QUERY_FAILED", "balance", |item| {
+        token_balance(&state, item)
+    })
+    .await;
+    HttpResponse::Ok().json(response)
+}
+
+/// Balance of an address less its held funds, answered from the index or query cache
+/// when possible
+async fn token_balance(
+    state: &AppState,
+    query: &BalanceRequest,
+) -> Result<BalanceResponse, ArkError> {
+    validate_address(&query.address)?;
+    let client = state.client(&query.chain)?;
+    let token = client.tokens().get(&query.token)?;
+    let asset = Asset::Token(token);
+    let (units, source) = cached_balance(state, client, asset, &query.address).await?;
+    let balance = token.amount(units);
+    let held = state.holds.held(&query.chain, &query.address, &token.symbol);
+    let available = to_units(balance).unwrap_or(0) - to_units(held).unwrap_or(0);
+    Ok(BalanceResponse {
+        address: query.address.clone(),
+        chain: query.chain.clone(),
+        token: token.symbol.clone(),
+        balance: from_units(available.max(0)),
+        held,
+        cached: source == BalanceSource::Cache,
+        source,
+    })
+}
+
+/// Status and body of a failed ownership or balance query of `what`
+fn query_error(e: &ArkError, failed: &str, what: &str) -> (StatusCode, ErrorResponse) {
+    let (status, error) = match e {
+        ArkError::UnknownChain(_) => (StatusCode::BAD_REQUEST, "UNKNOWN_CHAIN"),
+        ArkError::InvalidAddress(_) => (StatusCode::BAD_REQUEST, "INVALID_ADDRESS"),
+        ArkError::InvalidNft(_) => (StatusCode::BAD_REQUEST, "INVALID_NFT"),
+        ArkError::InvalidToken(_) => (StatusCode::BAD_REQUEST, "INVALID_TOKEN"),
+        _ => {
+            let message = format!("Failed to query {}: {}", what, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    error: failed.to_string(),
+                    message,
+                },
+            );
+        }
+    };
+    (
+        status,
+        ErrorResponse {
+            error: error.to_string(),
+            message: e.to_string(),
+        },
+    )
+}
+
+/// Refuse a batch with more items than `batch.max_items`
+fn batch_size_rejection(state: &AppState, items: usize) -> Option<HttpResponse> {
+    let max_items = state.config.batch.max_items;
+    (items > max_items).then(|| {
+        HttpResponse::BadRequest().json(ErrorResponse {
+            error: "BATCH_TOO_LARGE".to_string(),
+            message: format!("A batch takes at most {} items, got {}", max_items, items),
+        })
+    })
+}
+
+/// Run `query` on every item, at most `batch.concurrency` at a time, keeping the
+/// items' order; a failed item does not fail the others
+async fn run_batch<'a, Q, T, F, Fut>(
+    state: &AppState,
+    items: &'a [Q],
+    failed: &str,
+    what: &str,
+    query: F,
+) -> BatchResponse<T>
+where
+    F: Fn(&'a Q) -> Fut,
+    Fut: Future<Output = Result<T, ArkError>>,
+{
+    let results: Vec<BatchItemResult<T>> = stream::iter(items.iter().enumerate())
+        .map(|(index, item)| query(item).map(move |result| (index, result)))
+        .buffered(state.config.batch.concurrency)
+        .map(|(index, result)| match result {
+            Ok(result) => BatchItemResult {
+                index,
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => {
+                log::warn!("Batch item {} failed: {}", index, e);
+                BatchItemResult {
+                    index,
+                    result: None,
+                    error: Some(query_error(&e, failed, what).1),
                 }
             }
+        })
+        .collect()
+        .await;
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    BatchResponse {
+        succeeded: results.len() - failed,
+        failed,
+        results,
+    }
+}
+
+/// What a balance is looked up of
+#[derive(Clone, Copy)]
+enum Asset<'a> {
+    Nft(&'a NftRef),
+    Token(&'a TokenInfo),
+}
+
+/// Balance of an asset in its smallest unit (NFT units, or token base units) from the
+/// chain index or the query cache, or from the chain when neither knows it, and where
+/// it came from
+async fn cached_balance(
+    state: &AppState,
+    client: &ArkClient,
+    asset: Asset<'_>,
+    owner: &str,
+) -> Result<(u128, BalanceSource), ArkError> {
+    let chain = &client.chain_config().name;
+    let (indexed, cached) = match asset {
+        Asset::Nft(nft) => (
+            state.indexer.nft_balance(client, nft, owner).await?.map(u128::from),
+            state.query_cache.nft_balance(nft, owner).map(u128::from),
+        ),
+        Asset::Token(token) => (
+            state.indexer.token_balance(client, token, owner).await?,
+            state.query_cache.token_balance(chain, owner, &token.symbol),
+        ),
+    };
+    if let Some(balance) = indexed {
+        return Ok((balance, BalanceSource::Index));
+    }
+    if let Some(balance) = cached {
+        return Ok((balance, BalanceSource::Cache));
+    }
+
+    let balance = match asset {
+        Asset::Nft(nft) => {
+            let balance = client.query_nft_balance(nft, owner).await?;
+            state.query_cache.put_nft_balance(nft, owner, balance);
+            u128::from(balance)
+        }
+        Asset::Token(token) => {
+            let head = client.get_block_number().await?;
+            let balance = token.base_units(client.query_token_balance(token, owner).await?)?;
+            state
+                .query_cache
+                .put_token_balance(chain, owner, &token.symbol, balance);
+            state
+                .indexer
+                .put_token_balance(client, token, owner, balance, head)
+                .await?;
+            balance
         }
+    };
+    Ok((balance, BalanceSource::Chain))
+}
+
+/// NFTs of the chain's portfolio collections and token balances an address holds,
+/// with the NFTs paginated
+pub async fn wallet_portfolio(
+    state: web::Data<AppState>,
+    address: web::Path<String>,
+    query: web::Query<PortfolioQuery>,
+) -> impl Responder {
+    log::info!(
+        "Querying portfolio of {} on {} (offset {}, limit {})",
+        address,
+        query.chain,
+        query.offset,
+        query.limit
+    );
+
+    if let Err(e) = validate_address(&address) {
+        return invalid_request_response(&e);
+    }
+    if let Some(response) = page_size_rejection(query.limit) {
+        return response;
+    }
+    let client = match state.client(&query.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+
+    let collections = &client.chain_config().collections;
+    let holdings = try_join_all(
+        collections
+            .iter()
+            .map(|collection| client.query_nft_holdings(collection, &address)),
+    );
+    let balances = try_join_all(
+        client
+            .tokens()
+            .all()
+            .iter()
+            .map(|token| portfolio_token(&state, client, token, &address)),
+    );
+    let (holdings, tokens) = match futures::try_join!(holdings, balances) {
+        Ok(portfolio) => portfolio,
         Err(e) => {
-            log::error!("Failed to create ARK client: {}", e);
-            HttpResponse::InternalServerError().json(ErrorResponse {
-                error: "ARK_CLIENT_ERROR".to_string(),
-                message: format!("Failed to initialize ARK client: {}", e),
-            })
+            log::error!("Failed to query portfolio of {}: {}", address, e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "PORTFOLIO_QUERY_FAILED".to_string(),
+                message: format!("Failed to query portfolio: {}", e),
+            });
+        }
+    };
+
+    let holdings: Vec<(NftRef, u64)> = holdings.into_iter().flatten().collect();
+    let total_nfts = holdings.len();
+    let nfts = holdings
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|(nft, units)| PortfolioNft {
+            locked_by: state.nft_locks.get(&nft).map(|lock| lock.deal_id),
+            nft: NftRef { amount: units, ..nft },
+        })
+        .collect();
+    let next_offset = query.offset.saturating_add(query.limit);
+
+    HttpResponse::Ok().json(PortfolioResponse {
+        address: address.into_inner(),
+        chain: query.chain.clone(),
+        collections: collections.clone(),
+        nfts,
+        total_nfts,
+        offset: query.offset,
+        limit: query.limit,
+        next_offset: (next_offset < total_nfts).then_some(next_offset),
+        tokens,
+    })
+}
+
+fn page_size_rejection(limit: usize) -> Option<HttpResponse> {
+    (limit == 0 || limit > MAX_PAGE_SIZE).then(|| {
+        HttpResponse::BadRequest().json(ErrorResponse {
+            error: "INVALID_PAGE".to_string(),
+            message: format!("limit must be between 1 and {}", MAX_PAGE_SIZE),
+        })
+    })
+}
+
+/// Available and held balance of one token of a portfolio
+async fn portfolio_token(
+    state: &AppState,
+    client: &ArkClient,
+    token: &TokenInfo,
+    address: &str,
+) -> Result<PortfolioToken, ArkError> {
+    let (units, _) = cached_balance(state, client, Asset::Token(token), address).await?;
+    let balance = token.amount(units);
+    let held = state
+        .holds
+        .held(&client.chain_config().name, address, &token.symbol);
+    let available = to_units(balance).unwrap_or(0) - to_units(held).unwrap_or(0);
+    Ok(PortfolioToken {
+        token: token.symbol.clone(),
+        balance: from_units(available.max(0)),
+        held,
+    })
+}
+
+/// Checkpoint and size of every chain's transfer index
+pub async fn indexer_status(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(IndexerStatusResponse {
+        chains: state.indexer.status(),
+    })
+}
+
+/// What the transfer index knows of an address: the NFTs last sent to it and the
+/// tokens it received and sent
+pub async fn indexed_address(
+    state: web::Data<AppState>,
+    address: web::Path<String>,
+    query: web::Query<ChainQuery>,
+) -> impl Responder {
+    if let Err(e) = validate_address(&address) {
+        return invalid_request_response(&e);
+    }
+    if let Err(e) = state.client(&query.chain) {
+        return client_error_response(&e);
+    }
+    HttpResponse::Ok().json(IndexedAddressResponse {
+        nfts: state.indexer.owned_nfts(&query.chain, &address),
+        flows: state.indexer.flows(&query.chain, &address),
+        address: address.into_inner(),
+        chain: query.chain.clone(),
+    })
+}
+
+/// Deals an address traded in or was paid by, with the NFTs and tokens it sent and
+/// received in each, from the records of the escrows and swaps this service executed
+pub async fn address_history(
+    state: web::Data<AppState>,
+    address: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    if let Err(e) = validate_address(&address) {
+        return invalid_request_response(&e);
+    }
+    if let Some(response) = page_size_rejection(query.limit) {
+        return response;
+    }
+    if let Some(Err(e)) = query.chain.as_deref().map(|chain| state.client(chain)) {
+        return client_error_response(&e);
+    }
+
+    let records = state.escrows.involving(&address, query.chain.as_deref());
+    let total_deals = records.len();
+    let deals = records
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|record| history_entry(record, &address))
+        .collect();
+    let next_offset = query.offset.saturating_add(query.limit);
+
+    HttpResponse::Ok().json(HistoryResponse {
+        address: address.into_inner(),
+        deals,
+        total_deals,
+        offset: query.offset,
+        limit: query.limit,
+        next_offset: (next_offset < total_deals).then_some(next_offset),
+    })
+}
+
+/// A recorded deal from the side of one address
+fn history_entry(record: EscrowRecord, address: &str) -> HistoryEntry {
+    let counterparty = if record.buyer_address.eq_ignore_ascii_case(address) {
+        Some(record.seller_address)
+    } else if record.seller_address.eq_ignore_ascii_case(address) {
+        Some(record.buyer_address)
+    } else {
+        None
+    };
+    let (mut sent, mut received) = (Vec::new(), Vec::new());
+    for log in record.transfers {
+        let (from, to) = match &log {
+            TransactionLog::NftTransfer { from, to, .. }
+            | TransactionLog::TokenTransfer { from, to, .. } => (from, to),
+        };
+        if from.eq_ignore_ascii_case(address) {
+            sent.push(log.clone());
         }
+        if to.eq_ignore_ascii_case(address) {
+            received.push(log);
+        }
+    }
+    HistoryEntry {
+        deal_id: record.deal_id,
+        chain: record.chain,
+        tx_hash: record.tx_hash,
+        block_number: record.block_number,
+        status: record.status,
+        failure: record.failure,
+        counterparty,
+        sent,
+        received,
+        recorded_at: record.recorded_at,
+    }
+}
+
+/// Everything that happened to a deal, from the signatures of its parties through
+/// consensus to the receipt of its escrow or swap
+pub async fn deal_timeline(
+    state: web::Data<AppState>,
+    deal_id: web::Path<String>,
+) -> impl Responder {
+    let events = state.timelines.timeline(&deal_id);
+    let record = state.escrows.get(&deal_id);
+    if events.is_empty() && record.is_none() {
+        return HttpResponse::NotFound().json(ErrorResponse {
+            error: "DEAL_NOT_FOUND".to_string(),
+            message: format!("Nothing recorded for deal {}", deal_id),
+        });
+    }
+    HttpResponse::Ok().json(DealTimelineResponse {
+        deal_id: deal_id.into_inner(),
+        events,
+        record,
+    })
+}
+
+/// Hit and miss counts of the ownership and balance query cache
+pub async fn cache_stats(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.query_cache.stats())
+}
+
+/// Tokens escrows and swaps can be settled in on a chain
+pub async fn list_tokens(
+    state: web::Data<AppState>,
+    query: web::Query<ChainQuery>,
+) -> impl Responder {
+    match state.client(&query.chain) {
+        Ok(client) => HttpResponse::Ok().json(TokensResponse {
+            chain: query.chain.clone(),
+            tokens: client.tokens().all().to_vec(),
+        }),
+        Err(e) => client_error_response(&e),
+    }
+}
+
+/// Chains requests can be routed to
+pub async fn list_chains(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(ChainsResponse {
+        chains: state
+            .chains()
+            .all()
+            .iter()
+            .map(|chain| ChainView {
+                name: chain.name.clone(),
+                chain_id: chain.chain_id,
+                confirmations: chain.confirmations,
+                block_time_ms: chain.block_time.as_millis() as u64,
+                escrow_contract: chain.escrow_contract.clone(),
+                tokens: chain.tokens.all().to_vec(),
+                collections: chain.collections.clone(),
+            })
+            .collect(),
+    })
+}
+
+/// Error response when no chain client serves the requested chain
+fn client_error_response(e: &ArkError) -> HttpResponse {
+    log::error!("No client for the requested chain: {}", e);
+    match e {
+        ArkError::UnknownChain(_) => HttpResponse::BadRequest().json(ErrorResponse {
+            error: "UNKNOWN_CHAIN".to_string(),
+            message: e.to_string(),
+        }),
+        _ => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: "ARK_CLIENT_ERROR".to_string(),
+            message: format!("Failed to initialize ARK client: {}", e),
+        }),
     }
 }
 
 /// Execute escrow transaction on ARK Network
-pub async fn execute_escrow(payload: web::Json<EscrowRequest>) -> impl Responder {
+pub async fn execute_escrow(
+    state: web::Data<AppState>,
+    payload: web::Json<EscrowRequest>,
+) -> impl Responder {
     log::info!(
-        "Executing escrow for deal: {} (NFT: {} from {} to {} for {} USDC)",
+        "Executing escrow for deal: {} on {} (NFT: {} from {} to {} for {} {})",
         payload.deal_id,
-        payload.nft_id,
+        payload.chain,
+        match &payload.nft_id {
+            Some(nft_id) => nft_id.clone(),
+            None => format!("bundle of {}", payload.bundle.len()),
+        },
         payload.seller_address,
         payload.buyer_address,
-        payload.price
+        payload.price,
+        payload.token
     );
 
-    match ArkClient::new() {
-        Ok(client) => {
-            // Execute escrow transaction with proper error handling
-            match client
-                .execute_escrow_transaction(
-                    &payload.buyer_address,
-                    &payload.seller_address,
-                    &payload.nft_id, // Using nft_id as collection for now
-                    "1", // Token ID placeholder - in production would parse from nft_id
-                    payload.price,
-                )
-                .await
-            {
-                Ok(receipt) => {
-                    log::info!(
-                        "Escrow transaction successful: tx_hash={}, block={}, confirmations={}",
-                        receipt.tx_hash,
-                        receipt.block_number,
-                        receipt.confirmations
-                    );
-
-                    HttpResponse::Ok().json(EscrowResponse {
-                        success: receipt.status == "success",
-                        tx_hash: receipt.tx_hash,
-                        block_number: receipt.block_number,
-                    })
-                }
-                Err(e) => {
-                    log::error!("Escrow transaction failed: {}", e);
-                    HttpResponse::InternalServerError().json(ErrorResponse {
-                        error: "ESCROW_FAILED".to_string(),
-                        message: format!("Escrow transaction failed: {}", e),
-                    })
-                }
-            }
+    // A deal goes through once; a resubmission is refused before anything is reserved
+    if let Err(e) = start_deal(&state, &payload.deal_id) {
+        return spend_error_response(&payload.deal_id, &e);
+    }
+    let response = escrow_deal(&state, &payload).await;
+    // A deal whose transaction is still unconfirmed stays registered until it resolves
+    if response.status() != StatusCode::ACCEPTED {
+        state.active_deals.finish(&payload.deal_id);
+    }
+    response
+}
+
+/// Reserve the deal's spending, hold the buyer's funds and lock the NFTs, then submit
+/// the escrow and settle or roll back everything according to its outcome
+async fn escrow_deal(state: &web::Data<AppState>, payload: &EscrowRequest) -> HttpResponse {
+    let AppState {
+        nft_locks: locks,
+        holds,
+        spending_policies: policies,
+        mandates,
+        ledger,
+        wallet,
+        ..
+    } = state.get_ref();
+
+    let nfts = match escrow_nfts(payload) {
+        Ok(nfts) => nfts,
+        Err(e) => {
+            log::error!("Invalid escrow request: {}", e);
+            return invalid_request_response(&e);
+        }
+    };
+
+    let client = match state.client(&payload.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let (price, amount) = match ledger_amount(client.tokens(), &payload.token, payload.price) {
+        Ok(price) => price,
+        Err(e) => {
+            log::error!("Invalid escrow price: {}", e);
+            return invalid_request_response(&e);
+        }
+    };
+    // Marketplace fee and creator royalties come out of the seller's proceeds
+    let payouts = match state.settlement.split(&price, &payload.seller_address, &nfts) {
+        Ok(payouts) => payouts,
+        Err(e) => {
+            log::error!("Invalid escrow price: {}", e);
+            return invalid_request_response(&e);
+        }
+    };
+
+    // The buyer agent's spending policies are a hard cap, whatever consensus said
+    let reserved = policies.reserve(&Spend {
+        deal_id: &payload.deal_id,
+        wallet: Some(&payload.buyer_address),
+        agent_id: payload.spender.agent_id.as_deref(),
+        owner_id: payload.spender.owner_id.as_deref(),
+        amount: Some(&price),
+        nfts: &nfts,
+        counterparty: Some(&payload.seller_address),
+    });
+    let mut reserved = match reserved {
+        Ok(Ok(reservation)) => SpendReservations {
+            policies: vec![reservation],
+            mandates: Vec::new(),
+        },
+        Ok(Err(violations)) => return policy_violation_response(&payload.deal_id, violations),
+        Err(e) => return spend_error_response(&payload.deal_id, &e),
+    };
+
+    // Agents spending an owner's wallet must hold a mandate that covers the deal
+    let mandated = mandate_use(
+        mandates,
+        &payload.spender,
+        &payload.deal_id,
+        Some(&payload.buyer_address),
+        Some(&payload.seller_address),
+        std::slice::from_ref(&price),
+        &nfts,
+    )
+    .and_then(|spend| spend.map(|spend| mandates.reserve(&spend)).transpose());
+    match mandated {
+        Ok(reservation) => reserved.mandates.extend(reservation),
+        Err(e) => {
+            reserved.release(state);
+            return mandate_rejected_response(&payload.deal_id, &e);
         }
+    }
+
+    // Funds other deals hold cannot pay for this one
+    let unheld = check_unheld_funds(
+        client,
+        holds,
+        &payload.deal_id,
+        &payload.chain,
+        &payload.buyer_address,
+        &price,
+    );
+    if let Err(e) = unheld.await {
+        log::error!("Escrow price not covered by available funds: {}", e);
+        reserved.release(state);
+        return hold_error_response(&e);
+    }
+
+    // Takes over the locks from consensus; another deal's lock refuses the escrow
+    if let Err(e) = locks.acquire(&payload.deal_id, &nfts, LockStage::Escrow) {
+        reserved.release(state);
+        return nft_locked_response(&payload.deal_id, &e);
+    }
+
+    // Lock the buyer's funds in the ledger before submitting on-chain
+    if let Err(e) =
+        ledger.hold_escrow(&payload.deal_id, &payload.buyer_address, &price.token, amount)
+    {
+        log::error!("Failed to hold escrow funds: {}", e);
+        reserved.release(state);
+        holds.release(&payload.deal_id);
+        locks.release(&payload.deal_id);
+        return HttpResponse::InternalServerError().json(ErrorResponse {
+            error: "LEDGER_ERROR".to_string(),
+            message: format!("Failed to hold escrow funds: {}", e),
+        });
+    }
+
+    // Execute escrow transaction with proper error handling
+    let escrow = EscrowTransaction {
+        buyer_address: payload.buyer_address.clone(),
+        seller_address: payload.seller_address.clone(),
+        nfts,
+        price,
+        payouts,
+    };
+
+    let submitted_at = chrono::Utc::now().timestamp();
+    let result = client.execute_escrow_transaction(wallet, &escrow).await;
+    let deal = SubmittedDeal {
+        deal_id: payload.deal_id.clone(),
+        chain: payload.chain.clone(),
+        submitted_at,
+        record: EscrowRecord {
+            deal_id: payload.deal_id.clone(),
+            chain: payload.chain.clone(),
+            tx_hash: None,
+            block_number: 0,
+            status: TxStatus::Pending,
+            buyer_address: payload.buyer_address.clone(),
+            seller_address: payload.seller_address.clone(),
+            nfts: escrow.nfts.clone(),
+            value: vec![escrow.price.clone()],
+            transfers: Vec::new(),
+            failure: None,
+            recorded_at: chrono::Utc::now().timestamp(),
+        },
+        reserved,
+        call: SubmittedCall::Escrow { escrow, amount },
+    };
+    conclude_deal(state, deal, result)
+}
+
+/// Execute an atomic swap of NFTs and tokens between two parties
+pub async fn execute_swap(
+    state: web::Data<AppState>,
+    payload: web::Json<SwapRequest>,
+) -> impl Responder {
+    log::info!(
+        "Executing swap for deal: {} on {} ({} <-> {})",
+        payload.deal_id,
+        payload.chain,
+        payload.maker.address,
+        payload.taker.address
+    );
+
+    if let Err(e) = start_deal(&state, &payload.deal_id) {
+        return spend_error_response(&payload.deal_id, &e);
+    }
+    let response = swap_deal(&state, &payload).await;
+    if response.status() != StatusCode::ACCEPTED {
+        state.active_deals.finish(&payload.deal_id);
+    }
+    response
+}
+
+/// Reserve both sides' spending, hold their funds and lock the NFTs, then submit the
+/// swap and settle or roll back everything according to its outcome
+async fn swap_deal(state: &web::Data<AppState>, payload: &SwapRequest) -> HttpResponse {
+    let AppState {
+        nft_locks: locks,
+        holds,
+        ledger,
+        wallet,
+        ..
+    } = state.get_ref();
+
+    let client = match state.client(&payload.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+
+    let swap = match swap_transaction(payload, client.tokens()) {
+        Ok(swap) => swap,
         Err(e) => {
-            log::error!("Failed to create ARK client: {}", e);
-            HttpResponse::InternalServerError().json(ErrorResponse {
-                error: "ARK_CLIENT_ERROR".to_string(),
-                message: format!("Failed to initialize ARK client: {}", e),
+            log::error!("Invalid swap request: {}", e);
+            return invalid_request_response(&e);
+        }
+    };
+    // Every token a side offers is one ledger payment to the other side
+    let payments: Vec<SwapPayment> =
+        match [(&swap.maker, &swap.taker), (&swap.taker, &swap.maker)]
+            .into_iter()
+            .flat_map(|(from, to)| {
+                from.tokens.iter().map(move |t| {
+                    Ok(SwapPayment {
+                        payer: from.address.clone(),
+                        payee: to.address.clone(),
+                        token: t.token.clone(),
+                        units: to_units(t.amount)?,
+                    })
+                })
             })
+            .collect::<Result<_, ArkError>>()
+        {
+            Ok(payments) => payments,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(ErrorResponse {
+                    error: "INVALID_AMOUNT".to_string(),
+                    message: format!("Invalid swap amount: {}", e),
+                });
+            }
+        };
+
+    // Both parties may be agents, each held to its own spending limits and mandate
+    let mut reserved = SpendReservations::default();
+    for (role, request, paid, received) in [
+        ("maker", &payload.maker, &swap.maker, &swap.taker),
+        ("taker", &payload.taker, &swap.taker, &swap.maker),
+    ] {
+        let rejection = swap_side_rejection(
+            state,
+            &payload.deal_id,
+            role,
+            request,
+            paid,
+            received,
+            &mut reserved,
+        );
+        if let Some(response) = rejection {
+            reserved.release(state);
+            return response;
+        }
+    }
+
+    // Funds other deals hold cannot pay for this one, on either side
+    let paid = [&swap.maker, &swap.taker]
+        .into_iter()
+        .flat_map(|side| side.tokens.iter().map(move |amount| (&side.address, amount)));
+    for (payer, amount) in paid {
+        let unheld =
+            check_unheld_funds(client, holds, &payload.deal_id, &payload.chain, payer, amount);
+        if let Err(e) = unheld.await {
+            log::error!("Swap payment of {} not covered by available funds: {}", payer, e);
+            reserved.release(state);
+            return hold_error_response(&e);
+        }
+    }
+
+    let nfts = [swap.maker.nfts.clone(), swap.taker.nfts.clone()].concat();
+    if let Err(e) = locks.acquire(&payload.deal_id, &nfts, LockStage::Escrow) {
+        reserved.release(state);
+        return nft_locked_response(&payload.deal_id, &e);
+    }
+
+    // Lock both sides' funds before submitting; the NFTs are checked by the contract
+    for (i, payment) in payments.iter().enumerate() {
+        let held =
+            ledger.hold_escrow(&payload.deal_id, &payment.payer, &payment.token, payment.units);
+        if let Err(e) = held {
+            log::error!("Failed to hold swap funds of {}: {}", payment.payer, e);
+            release_swap_funds(ledger, &payload.deal_id, &payments[..i]);
+            reserved.release(state);
+            holds.release(&payload.deal_id);
+            locks.release(&payload.deal_id);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "LEDGER_ERROR".to_string(),
+                message: format!("Failed to hold swap funds: {}", e),
+            });
+        }
+    }
+
+    let submitted_at = chrono::Utc::now().timestamp();
+    let result = client.execute_swap_transaction(wallet, &swap).await;
+    let deal = SubmittedDeal {
+        deal_id: payload.deal_id.clone(),
+        chain: payload.chain.clone(),
+        submitted_at,
+        record: EscrowRecord {
+            deal_id: payload.deal_id.clone(),
+            chain: payload.chain.clone(),
+            tx_hash: None,
+            block_number: 0,
+            status: TxStatus::Pending,
+            buyer_address: swap.taker.address.clone(),
+            seller_address: swap.maker.address.clone(),
+            nfts,
+            value: totals(swap.maker.tokens.iter().chain(&swap.taker.tokens)),
+            transfers: Vec::new(),
+            failure: None,
+            recorded_at: chrono::Utc::now().timestamp(),
+        },
+        reserved,
+        call: SubmittedCall::Swap { swap, payments },
+    };
+    conclude_deal(state, deal, result)
+}
+
+/// A deal whose transaction was submitted, with everything its outcome settles or
+/// rolls back
+struct SubmittedDeal {
+    deal_id: String,
+    chain: String,
+    submitted_at: i64,
+    /// Record of the deal, completed with the outcome
+    record: EscrowRecord,
+    reserved: SpendReservations,
+    call: SubmittedCall,
+}
+
+/// The contract call a deal submitted, with the ledger funds held for it
+enum SubmittedCall {
+    Escrow {
+        escrow: EscrowTransaction,
+        amount: i64,
+    },
+    Swap {
+        swap: SwapTransaction,
+        payments: Vec<SwapPayment>,
+    },
+}
+
+/// A token one side of a swap pays the other, in ledger units
+struct SwapPayment {
+    payer: String,
+    payee: String,
+    token: String,
+    units: i64,
+}
+
+impl SubmittedCall {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Escrow { .. } => "Escrow",
+            Self::Swap { .. } => "Swap",
         }
     }
+
+    /// Who the gas the operator wallet paid is charged to: the buyer of an escrow, or
+    /// the taker of a swap, who accepted the trade
+    fn gas_payer(&self) -> &str {
+        match self {
+            Self::Escrow { escrow, .. } => &escrow.buyer_address,
+            Self::Swap { swap, .. } => &swap.taker.address,
+        }
+    }
+
+    fn payouts(&self) -> Vec<Payout> {
+        match self {
+            Self::Escrow { escrow, .. } => escrow.payouts.clone(),
+            Self::Swap { .. } => Vec::new(),
+        }
+    }
+
+    /// Everyone whose balances the call changes
+    fn parties(&self) -> Vec<&str> {
+        match self {
+            Self::Escrow { escrow, .. } => {
+                let mut parties = vec![escrow.buyer_address.as_str(), &escrow.seller_address];
+                parties.extend(escrow.payouts.iter().map(|p| p.recipient.as_str()));
+                parties
+            }
+            Self::Swap { swap, .. } => vec![&swap.maker.address, &swap.taker.address],
+        }
+    }
+
+    /// Move the held funds to their recipients in the ledger, after the call succeeded
+    fn settle(&self, ledger: &Ledger, deal_id: &str) {
+        match self {
+            Self::Escrow { escrow, .. } => {
+                let posted = escrow
+                    .payouts
+                    .iter()
+                    .map(|p| Ok((p.recipient.as_str(), to_units(p.amount.amount)?)))
+                    .collect::<Result<Vec<_>, ArkError>>()
+                    .and_then(|payouts| {
+                        ledger.settle_escrow_payouts(
+                            deal_id,
+                            &escrow.buyer_address,
+                            &escrow.price.token,
+                            &payouts,
+                        )
+                    });
+                if let Err(e) = posted {
+                    log::error!("Failed to post escrow outcome to ledger: {}", e);
+                }
+            }
+            Self::Swap { payments, .. } => {
+                for p in payments {
+                    let settled =
+                        ledger.settle_escrow(deal_id, &p.payer, &p.payee, &p.token, p.units);
+                    if let Err(e) = settled {
+                        log::error!("Failed to post swap payment of {} to ledger: {}", p.payer, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return the held funds to their owners in the ledger, after the call failed
+    fn release_funds(&self, ledger: &Ledger, deal_id: &str) {
+        match self {
+            Self::Escrow { escrow, amount } => {
+                let released = ledger.release_escrow(
+                    deal_id,
+                    &escrow.buyer_address,
+                    &escrow.price.token,
+                    *amount,
+                );
+                if let Err(e) = released {
+                    log::error!("Failed to release escrow funds: {}", e);
+                }
+            }
+            Self::Swap { payments, .. } => release_swap_funds(ledger, deal_id, payments),
+        }
+    }
+}
+
+fn release_swap_funds(ledger: &Ledger, deal_id: &str, payments: &[SwapPayment]) {
+    for p in payments {
+        if let Err(e) = ledger.release_escrow(deal_id, &p.payer, &p.token, p.units) {
+            log::error!("Failed to release swap funds of {}: {}", p.payer, e);
+        }
+    }
+}
+
+/// Settle or roll back a submitted deal according to its transaction's outcome.
+///
+/// A transaction still unconfirmed when the wait timed out may yet be mined, so its
+/// deal keeps everything it reserved, held and locked, and is settled or rolled back
+/// in the background once the transaction resolves. Its NFT locks and fund hold are
+/// pinned meanwhile, so they cannot expire under a transaction that is still pending.
+fn conclude_deal(
+    state: &web::Data<AppState>,
+    deal: SubmittedDeal,
+    result: Result<TransactionReceipt, ArkError>,
+) -> HttpResponse {
+    record_submission(state, &deal, &result);
+    let Err(ArkError::ConfirmationTimeout { tx_hash }) = &result else {
+        return settle_deal(state, deal, result, None);
+    };
+    log::warn!(
+        "{} transaction {} for deal {} not confirmed yet; settling once it resolves",
+        deal.call.kind(),
+        tx_hash,
+        deal.deal_id
+    );
+    state.escrows.insert(deal.record.clone().with_outcome(&result));
+    state.nft_locks.pin(&deal.deal_id);
+    state.holds.pin(&deal.deal_id);
+    let response = HttpResponse::Accepted().json(PendingDealResponse {
+        deal_id: deal.deal_id.clone(),
+        chain: deal.chain.clone(),
+        status: TxStatus::Pending,
+        tx_hash: tx_hash.clone(),
+    });
+    actix_web::rt::spawn(resolve_deal(state.clone(), deal, tx_hash.clone()));
+    response
+}
+
+/// Keep waiting for the transaction of a deal that timed out, then settle or roll
+/// back the deal and let it be executed again
+async fn resolve_deal(state: web::Data<AppState>, deal: SubmittedDeal, tx_hash: String) {
+    let result = match state.client(&deal.chain) {
+        Ok(client) => loop {
+            match client.await_contract_call(&state.wallet, &tx_hash).await {
+                Err(ArkError::ConfirmationTimeout { .. }) => log::warn!(
+                    "Transaction {} for deal {} still not confirmed",
+                    tx_hash,
+                    deal.deal_id
+                ),
+                result => break result,
+            }
+        },
+        Err(e) => Err(e),
+    };
+    let deal_id = deal.deal_id.clone();
+    settle_deal(&state, deal, result, Some(&tx_hash));
+    state.active_deals.finish(&deal_id);
+}
+
+/// Post the outcome of a deal's transaction: settle the ledger and capture the deal's
+/// hold if it succeeded, release everything the deal reserved and held if not.
+///
+/// `pending_tx_hash` is the transaction of a deal recorded as pending when its wait
+/// timed out; that record is replaced by the outcome.
+fn settle_deal(
+    state: &AppState,
+    deal: SubmittedDeal,
+    result: Result<TransactionReceipt, ArkError>,
+    pending_tx_hash: Option<&str>,
+) -> HttpResponse {
+    let AppState { holds, ledger, .. } = state;
+    let SubmittedDeal {
+        deal_id,
+        chain,
+        record,
+        reserved,
+        call,
+        ..
+    } = deal;
+
+    record_outcome(state, &deal_id, &result);
+    let nfts = record.nfts.clone();
+    // Failed and cancelled deals are recorded too, for the parties' history
+    let record = record.with_outcome(&result);
+    match pending_tx_hash {
+        Some(tx_hash) => state.escrows.resolve(tx_hash, record),
+        None => state.escrows.insert(record),
+    }
+    let response = match result {
+        Ok(receipt) => {
+            log::info!(
+                "{} transaction successful: tx_hash={}, block={}, confirmations={}",
+                call.kind(),
+                receipt.tx_hash,
+                receipt.block_number,
+                receipt.confirmations
+            );
+
+            let success = receipt.status == TxStatus::Success;
+            if success {
+                if let Some(hold) = holds.capture(&deal_id) {
+                    log::info!("Captured hold {} for deal {}", hold.hold_id, hold.deal_id);
+                }
+                call.settle(ledger, &deal_id);
+            } else {
+                log::warn!(
+                    "{} transaction {} reverted: {}",
+                    call.kind(),
+                    receipt.tx_hash,
+                    receipt.revert_reason.as_deref().unwrap_or("no reason given")
+                );
+                call.release_funds(ledger, &deal_id);
+                reserved.release(state);
+                holds.release(&deal_id);
+            }
+
+            // The operator wallet paid the gas; charge it to the deal's payer
+            charge_network_fee(ledger, &deal_id, call.gas_payer(), &receipt);
+
+            HttpResponse::Ok().json(EscrowResponse {
+                success,
+                payouts: call.payouts(),
+                receipt,
+            })
+        }
+        Err(e) => {
+            log::error!("{} transaction failed: {}", call.kind(), e);
+            call.release_funds(ledger, &deal_id);
+            reserved.release(state);
+            holds.release(&deal_id);
+            escrow_failure_response(&e)
+        }
+    };
+    // Completed or failed, the deal no longer needs its NFTs, and the cached
+    // balances of everyone it pays may be out of date
+    state.nft_locks.release(&deal_id);
+    state.query_cache.invalidate(&chain, &nfts, &call.parties());
+    state.indexer.forget(&chain, &nfts, &call.parties());
+    response
+}
+
+/// Register a deal for execution, refusing one that is already being executed or
+/// whose transaction went through or may still be mined
+fn start_deal(state: &AppState, deal_id: &str) -> Result<(), ArkError> {
+    state.active_deals.start(deal_id)?;
+    if let Some(record) = state.escrows.outstanding(deal_id) {
+        state.active_deals.finish(deal_id);
+        return Err(ArkError::DealConflict(format!(
+            "deal {} was already submitted (transaction {}, {})",
+            deal_id,
+            record.tx_hash.as_deref().unwrap_or("unknown"),
+            record.status
+        )));
+    }
+    Ok(())
+}
+
+/// What a deal committed against spending policies and mandates, released together
+/// if the deal does not go through
+#[derive(Default)]
+struct SpendReservations {
+    policies: Vec<PolicyReservation>,
+    mandates: Vec<MandateReservation>,
+}
+
+impl SpendReservations {
+    fn release(self, state: &AppState) {
+        for reservation in self.policies {
+            state.spending_policies.release(reservation);
+        }
+        for reservation in self.mandates {
+            state.mandates.release(reservation);
+        }
+    }
+}
+
+/// Rejection of one side of a swap by its agent's spending policies or mandate. What
+/// the side pays is added to `reserved`, counting against their daily limits and the
+/// mandate's budget until released.
+///
+/// Each token a side pays is reserved against the policies under its own key, so the
+/// two sides of a deal do not refuse each other as resubmissions. The mandate covers
+/// every token of the side at once, with one signature. Mandates budget payments, so
+/// a side that only hands over NFTs needs none.
+fn swap_side_rejection(
+    state: &AppState,
+    deal_id: &str,
+    role: &str,
+    request: &SwapSideRequest,
+    paid: &SwapSide,
+    received: &SwapSide,
+    reserved: &mut SpendReservations,
+) -> Option<HttpResponse> {
+    let amounts: Vec<Option<&TokenAmount>> = if paid.tokens.is_empty() {
+        vec![None]
+    } else {
+        paid.tokens.iter().map(Some).collect()
+    };
+    for amount in amounts {
+        let key = match amount {
+            Some(amount) => format!("{}#{}:{}", deal_id, role, amount.token),
+            None => format!("{}#{}", deal_id, role),
+        };
+        let checked = state.spending_policies.reserve(&Spend {
+            deal_id: &key,
+            wallet: Some(&paid.address),
+            agent_id: request.spender.agent_id.as_deref(),
+            owner_id: request.spender.owner_id.as_deref(),
+            amount,
+            nfts: &received.nfts,
+            counterparty: Some(&received.address),
+        });
+        match checked {
+            Ok(Ok(reservation)) => reserved.policies.push(reservation),
+            Ok(Err(violations)) => return Some(policy_violation_response(deal_id, violations)),
+            Err(e) => return Some(spend_error_response(deal_id, &e)),
+        }
+    }
+
+    if paid.tokens.is_empty() {
+        return None;
+    }
+    let mandates = &state.mandates;
+    let mandated = mandate_use(
+        mandates,
+        &request.spender,
+        deal_id,
+        Some(&paid.address),
+        Some(&received.address),
+        &paid.tokens,
+        &received.nfts,
+    )
+    .and_then(|spend| spend.map(|spend| mandates.reserve(&spend)).transpose());
+    match mandated {
+        Ok(reservation) => {
+            reserved.mandates.extend(reservation);
+            None
+        }
+        Err(e) => Some(mandate_rejected_response(deal_id, &e)),
+    }
+}
+
+/// Add the submission of a deal's transaction to its timeline, if it was broadcast
+fn record_submission(
+    state: &AppState,
+    deal: &SubmittedDeal,
+    result: &Result<TransactionReceipt, ArkError>,
+) {
+    let tx_hash = match result {
+        Ok(receipt) => Some(receipt.tx_hash.as_str()),
+        Err(e) => e.tx_hash(),
+    };
+    if let Some(tx_hash) = tx_hash {
+        state.timelines.record_at(
+            &deal.deal_id,
+            deal.submitted_at,
+            DealEvent::TransactionSubmitted {
+                chain: deal.chain.clone(),
+                tx_hash: tx_hash.to_string(),
+            },
+        );
+    }
+}
+
+/// Add the confirmation and outcome of a deal's transaction to its timeline
+fn record_outcome(state: &AppState, deal_id: &str, result: &Result<TransactionReceipt, ArkError>) {
+    let timelines = &state.timelines;
+    match result {
+        Ok(receipt) => {
+            timelines.record(
+                deal_id,
+                DealEvent::TransactionConfirmed {
+                    tx_hash: receipt.tx_hash.clone(),
+                    block_number: receipt.block_number,
+                    confirmations: receipt.confirmations,
+                },
+            );
+            timelines.record(
+                deal_id,
+                DealEvent::Receipt {
+                    receipt: receipt.clone(),
+                },
+            );
+        }
+        Err(e) => timelines.record(
+            deal_id,
+            DealEvent::TransactionFailed {
+                reason: e.to_string(),
+            },
+        ),
+    }
+}
+
+/// Charge the gas the operator wallet paid for a deal to `payer`'s ledger budget
+fn charge_network_fee(ledger: &Ledger, deal_id: &str, payer: &str, receipt: &TransactionReceipt) {
+    let fee_charged = to_units(receipt.fee_paid_usdc).and_then(|fee| match fee {
+        0 => Ok(()),
+        fee => ledger
+            .charge_fee(&format!("deal:{}", deal_id), payer, fee)
+            .map(|_| ()),
+    });
+    if let Err(e) = fee_charged {
+        log::error!("Failed to charge network fee to ledger: {}", e);
+    }
+}
+
+/// Error response for an escrow or swap whose transaction did not go through
+fn escrow_failure_response(e: &ArkError) -> HttpResponse {
+    // A reorged or cancelled escrow was rolled back and can be resubmitted
+    let conflict = match e {
+        ArkError::TransactionReorged { .. } => Some("ESCROW_REORGED"),
+        ArkError::TransactionCancelled(_) => Some("ESCROW_CANCELLED"),
+        _ => None,
+    };
+    if let Some(error) = conflict {
+        return HttpResponse::Conflict().json(ErrorResponse {
+            error: error.to_string(),
+            message: format!("Escrow transaction failed: {}", e),
+        });
+    }
+    HttpResponse::InternalServerError().json(ErrorResponse {
+        error: "ESCROW_FAILED".to_string(),
+        message: format!("Escrow transaction failed: {}", e),
+    })
+}
+
+/// Check that `address`'s balance, less what other deals hold, covers a deal's price.
+///
+/// The deal's own hold covers the price if it reserves at least the price of the same
+/// funds, as it was checked against the balance when it was placed.
+async fn check_unheld_funds(
+    client: &ArkClient,
+    holds: &FundHolds,
+    deal_id: &str,
+    chain: &str,
+    address: &str,
+    price: &TokenAmount,
+) -> Result<(), ArkError> {
+    let covered = match holds.get(deal_id) {
+        Some(hold) if hold.holds_against(chain, address, &price.token) => {
+            to_units(hold.amount.amount)? >= to_units(price.amount)?
+        }
+        _ => false,
+    };
+    if covered {
+        return Ok(());
+    }
+    let held = holds.held_by_others(deal_id, chain, address, &price.token);
+    if held == 0.0 {
+        return Ok(());
+    }
+    let token = client.tokens().get(&price.token)?;
+    let balance = client.query_token_balance(token, address).await?;
+    let available = from_units((to_units(balance)? - to_units(held)?).max(0));
+    if available < price.amount {
+        return Err(ArkError::InsufficientBalance {
+            token: price.token.clone(),
+            has: available,
+            needs: price.amount,
+        });
+    }
+    Ok(())
+}
+
+/// Validate a price against the token registry and convert it into ledger units
+fn ledger_amount(
+    tokens: &TokenRegistry,
+    token: &str,
+    amount: f64,
+) -> Result<(TokenAmount, i64), ArkError> {
+    let amount = tokens.amount(&TokenAmount::new(token, amount))?;
+    let units = to_units(amount.amount)?;
+    // Amounts below one ledger unit would reach the ledger as zero and be refused there
+    if units <= 0 {
+        return Err(ArkError::InvalidToken(format!(
+            "{} is less than the smallest amount the ledger records",
+            amount
+        )));
+    }
+    Ok((amount, units))
+}
+
+/// Validate the parties and assets of a swap request
+fn swap_transaction(
+    payload: &SwapRequest,
+    tokens: &TokenRegistry,
+) -> Result<SwapTransaction, ArkError> {
+    let side = |request: &SwapSideRequest| -> Result<SwapSide, ArkError> {
+        validate_address(&request.address)?;
+        if request.nfts.is_empty() && request.tokens.is_empty() {
+            return Err(ArkError::InvalidToken(format!(
+                "{} offers nothing in the swap",
+                request.address
+            )));
+        }
+        let mut offered: Vec<TokenAmount> = Vec::new();
+        for amount in &request.tokens {
+            let amount = tokens.amount(amount)?;
+            if offered.iter().any(|t| t.token == amount.token) {
+                return Err(ArkError::InvalidToken(format!(
+                    "{} offers {} twice",
+                    request.address, amount.token
+                )));
+            }
+            offered.push(amount);
+        }
+        Ok(SwapSide {
+            address: request.address.clone(),
+            nfts: request
+                .nfts
+                .iter()
+                .map(|item| parse_escrow_item(&payload.chain, item))
+                .collect::<Result<_, _>>()?,
+            tokens: offered,
+        })
+    };
+
+    let swap = SwapTransaction {
+        maker: side(&payload.maker)?,
+        taker: side(&payload.taker)?,
+    };
+    if swap.maker.address.eq_ignore_ascii_case(&swap.taker.address) {
+        return Err(ArkError::InvalidAddress(format!(
+            "{} cannot swap with itself",
+            swap.maker.address
+        )));
+    }
+    let nfts = [swap.maker.nfts.clone(), swap.taker.nfts.clone()].concat();
+    if !nfts.is_empty() {
+        validate_bundle(&nfts)?;
+    }
+    Ok(swap)
+}
+
+/// Validate the parties of an escrow request and parse the NFTs it trades
+fn escrow_nfts(payload: &EscrowRequest) -> Result<Vec<NftRef>, ArkError> {
+    validate_address(&payload.buyer_address)?;
+    validate_address(&payload.seller_address)?;
+
+    let nfts = match (&payload.nft_id, payload.bundle.is_empty()) {
+        (Some(nft_id), true) => vec![parse_escrow_item(
+            &payload.chain,
+            &EscrowItem {
+                nft_id: nft_id.clone(),
+                nft_standard: payload.nft_standard,
+                nft_amount: payload.nft_amount,
+            },
+        )?],
+        (None, false) => payload
+            .bundle
+            .iter()
+            .map(|item| parse_escrow_item(&payload.chain, item))
+            .collect::<Result<_, _>>()?,
+        _ => {
+            return Err(ArkError::InvalidNft(
+                "an escrow needs either nft_id or a bundle".to_string(),
+            ))
+        }
+    };
+    validate_bundle(&nfts)?;
+    Ok(nfts)
+}
+
+/// Whether the seller holds every unit of every NFT a deal sells, from the chain index,
+/// the query cache or the chain itself
+async fn seller_holds_nfts(
+    state: &AppState,
+    payload: &ConsensusRequest,
+    nfts: &[NftRef],
+) -> Result<bool, ArkError> {
+    let client = state.client(&payload.chain)?;
+    for nft in nfts {
+        let (balance, _) =
+            cached_balance(state, client, Asset::Nft(nft), &payload.seller_address).await?;
+        if balance < u128::from(nft.amount) {
+            log::debug!("Seller holds {} of {}, the deal sells {}", balance, nft, nft.amount);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Parse the NFTs a consensus request asks the verifiers about, the way its escrow
+/// will trade them
+fn consensus_nfts(payload: &ConsensusRequest) -> Result<Vec<NftRef>, ArkError> {
+    validate_address(&payload.seller_address)?;
+    let nfts = match (&payload.nft_id, payload.bundle.is_empty()) {
+        (Some(nft_id), true) => vec![parse_escrow_item(
+            &payload.chain,
+            &EscrowItem {
+                nft_id: nft_id.clone(),
+                nft_standard: payload.nft_standard,
+                nft_amount: payload.nft_amount,
+            },
+        )?],
+        (None, false) => payload
+            .bundle
+            .iter()
+            .map(|item| parse_escrow_item(&payload.chain, item))
+            .collect::<Result<_, _>>()?,
+        _ => {
+            return Err(ArkError::InvalidNft(
+                "a consensus needs either nft_id or a bundle".to_string(),
+            ))
+        }
+    };
+    validate_bundle(&nfts)?;
+    Ok(nfts)
+}
+
+/// Parse an NFT traded on `chain`; references without a chain are placed there
+fn parse_escrow_item(chain: &str, item: &EscrowItem) -> Result<NftRef, ArkError> {
+    let nft = NftRef::parse_on_chain(&item.nft_id, chain)?;
+    if nft.chain != chain {
+        return Err(ArkError::InvalidNft(format!(
+            "{} is on chain {}, the deal runs on {}",
+            nft, nft.chain, chain
+        )));
+    }
+    match (item.nft_standard, item.nft_amount) {
+        (TokenStandard::Erc1155, amount) => nft.with_erc1155_amount(amount.unwrap_or(1)),
+        (TokenStandard::Erc721, None | Some(1)) => Ok(nft),
+        (TokenStandard::Erc721, Some(amount)) => Err(ArkError::InvalidNft(format!(
+            "{}: ERC-721 tokens are unique, got amount {}",
+            nft, amount
+        ))),
+    }
+}
+
+/// 400 response for a request with a malformed NFT reference, token amount or address
+fn invalid_request_response(e: &ArkError) -> HttpResponse {
+    let error = match e {
+        ArkError::InvalidAddress(_) => "INVALID_ADDRESS",
+        ArkError::InvalidToken(_) => "INVALID_TOKEN",
+        ArkError::InvalidPolicy(_) => "INVALID_POLICY",
+        ArkError::InvalidMandate(_) => "INVALID_MANDATE",
+        ArkError::LedgerError(_) => "INVALID_AMOUNT",
+        _ => "INVALID_NFT",
+    };
+    HttpResponse::BadRequest().json(ErrorResponse {
+        error: error.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Spending policy of an agent or owner, with what its deals reserved today
+pub async fn get_spending_policy(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let subject = match PolicySubject::from_path(&path.0, &path.1) {
+        Ok(subject) => subject,
+        Err(e) => return invalid_request_response(&e),
+    };
+    match state.spending_policies.get(&subject) {
+        Some(policy) => HttpResponse::Ok().json(SpendingPolicyResponse {
+            spent_today: state.spending_policies.spent_today(&subject, &policy.token),
+            subject,
+            policy,
+        }),
+        None => HttpResponse::NotFound().json(ErrorResponse {
+            error: "POLICY_NOT_FOUND".to_string(),
+            message: format!("No spending policy for {}", subject),
+        }),
+    }
+}
+
+/// Set the spending policy of an agent or owner, replacing any previous one
+pub async fn set_spending_policy(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    payload: web::Json<SpendingPolicy>,
+) -> impl Responder {
+    let subject = match PolicySubject::from_path(&path.0, &path.1) {
+        Ok(subject) => subject,
+        Err(e) => return invalid_request_response(&e),
+    };
+    log::info!("Setting spending policy for {}", subject);
+
+    let policy = payload.into_inner();
+    match state.spending_policies.set(subject.clone(), policy.clone()) {
+        Ok(()) => HttpResponse::Ok().json(SpendingPolicyResponse {
+            spent_today: state.spending_policies.spent_today(&subject, &policy.token),
+            subject,
+            policy,
+        }),
+        Err(e) => invalid_request_response(&e),
+    }
+}
+
+/// Remove the spending policy of an agent or owner
+pub async fn delete_spending_policy(
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let subject = match PolicySubject::from_path(&path.0, &path.1) {
+        Ok(subject) => subject,
+        Err(e) => return invalid_request_response(&e),
+    };
+    if state.spending_policies.remove(&subject) {
+        log::info!("Removed spending policy of {}", subject);
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(ErrorResponse {
+            error: "POLICY_NOT_FOUND".to_string(),
+            message: format!("No spending policy for {}", subject),
+        })
+    }
+}
+
+/// Register a mandate an owner signed for one of their agents
+pub async fn register_mandate(
+    state: web::Data<AppState>,
+    payload: web::Json<SignedMandate>,
+) -> impl Responder {
+    match state.mandates.register(payload.into_inner()) {
+        Ok(view) => {
+            log::info!(
+                "Registered mandate {} from {} (budget {}, expires {})",
+                view.mandate_id,
+                view.owner_address,
+                view.mandate.budget,
+                view.mandate.expires_at
+            );
+            HttpResponse::Created().json(view)
+        }
+        Err(e) => {
+            log::error!("Invalid mandate: {}", e);
+            invalid_request_response(&e)
+        }
+    }
+}
+
+/// A mandate with its status and remaining budget
+pub async fn get_mandate(
+    state: web::Data<AppState>,
+    mandate_id: web::Path<String>,
+) -> impl Responder {
+    match state.mandates.get(&mandate_id) {
+        Some(view) => HttpResponse::Ok().json(view),
+        None => mandate_not_found_response(&mandate_id),
+    }
+}
+
+/// Revoke a mandate with the owner's signature; deals not yet submitted under it are refused
+pub async fn revoke_mandate(
+    state: web::Data<AppState>,
+    mandate_id: web::Path<String>,
+    payload: web::Json<RevokeMandateRequest>,
+) -> impl Responder {
+    if state.mandates.get(&mandate_id).is_none() {
+        return mandate_not_found_response(&mandate_id);
+    }
+    match state.mandates.revoke(&mandate_id, &payload.signature) {
+        Ok(view) => {
+            log::warn!("Mandate {} revoked by {}", mandate_id, view.owner_address);
+            HttpResponse::Ok().json(view)
+        }
+        Err(e) => mandate_rejected_response(&mandate_id, &e),
+    }
+}
+
+fn mandate_not_found_response(mandate_id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(ErrorResponse {
+        error: "MANDATE_NOT_FOUND".to_string(),
+        message: format!("No mandate with id {}", mandate_id),
+    })
+}
+
+/// Hold part of an address's balance for a deal until its escrow captures it
+pub async fn place_hold(
+    state: web::Data<AppState>,
+    payload: web::Json<PlaceHoldRequest>,
+) -> impl Responder {
+    log::info!(
+        "Placing hold of {} {} on {} for deal {}",
+        payload.amount,
+        payload.token,
+        payload.address,
+        payload.deal_id
+    );
+
+    if let Err(e) = validate_address(&payload.address) {
+        return invalid_request_response(&e);
+    }
+    let client = match state.client(&payload.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let amount = match ledger_amount(client.tokens(), &payload.token, payload.amount) {
+        Ok((amount, _)) => amount,
+        Err(e) => return invalid_request_response(&e),
+    };
+    let token = match client.tokens().get(&amount.token) {
+        Ok(token) => token,
+        Err(e) => return invalid_request_response(&e),
+    };
+    let balance = match client.query_token_balance(token, &payload.address).await {
+        Ok(balance) => balance,
+        Err(e) => {
+            log::error!("Failed to query {} balance: {}", token.symbol, e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "BALANCE_QUERY_FAILED".to_string(),
+                message: format!("Failed to query {} balance: {}", token.symbol, e),
+            });
+        }
+    };
+
+    let ttl = payload
+        .ttl_secs
+        .map(std::time::Duration::from_secs)
+        .unwrap_or_else(|| state.holds.default_ttl());
+    let max_ttl = state.config.holds.max_ttl();
+    if ttl.is_zero() || ttl > max_ttl {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            error: "INVALID_TTL".to_string(),
+            message: format!(
+                "ttl_secs must be between 1 and {}, got {}",
+                max_ttl.as_secs(),
+                ttl.as_secs()
+            ),
+        });
+    }
+    match state.holds.place(
+        &payload.deal_id,
+        &payload.chain,
+        &payload.address,
+        amount,
+        ttl,
+        balance,
+    ) {
+        Ok(hold) => {
+            log::info!("Placed hold {} for deal {}", hold.hold_id, hold.deal_id);
+            HttpResponse::Created().json(hold)
+        }
+        Err(e) => {
+            log::warn!("Hold for deal {} refused: {}", payload.deal_id, e);
+            hold_error_response(&e)
+        }
+    }
+}
+
+/// The latest hold placed for a deal, whatever its status
+pub async fn get_hold(state: web::Data<AppState>, deal_id: web::Path<String>) -> impl Responder {
+    match state.holds.get(&deal_id) {
+        Some(hold) => HttpResponse::Ok().json(hold),
+        None => hold_not_found_response(&deal_id),
+    }
+}
+
+/// Release a deal's hold when the deal is cancelled
+pub async fn release_hold(
+    state: web::Data<AppState>,
+    deal_id: web::Path<String>,
+) -> impl Responder {
+    if let Some(hold) = state.holds.release(&deal_id) {
+        log::info!("Released hold {} for deal {}", hold.hold_id, hold.deal_id);
+        return HttpResponse::Ok().json(hold);
+    }
+    match state.holds.get(&deal_id) {
+        Some(hold) => hold_error_response(&ArkError::HoldConflict(format!(
+            "hold {} for deal {} is already {:?}",
+            hold.hold_id, deal_id, hold.status
+        ))),
+        None => hold_not_found_response(&deal_id),
+    }
+}
+
+/// 409 response when funds cannot be held or are already held for the deal
+fn hold_error_response(e: &ArkError) -> HttpResponse {
+    let error = match e {
+        ArkError::HoldConflict(_) => "HOLD_CONFLICT",
+        ArkError::InsufficientBalance { .. } => "INSUFFICIENT_AVAILABLE_BALANCE",
+        _ => return invalid_request_response(e),
+    };
+    HttpResponse::Conflict().json(ErrorResponse {
+        error: error.to_string(),
+        message: e.to_string(),
+    })
+}
+
+fn hold_not_found_response(deal_id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(ErrorResponse {
+        error: "HOLD_NOT_FOUND".to_string(),
+        message: format!("No hold placed for deal {}", deal_id),
+    })
+}
+
+/// NFTs a deal currently has locked
+pub async fn deal_locks(state: web::Data<AppState>, deal_id: web::Path<String>) -> impl Responder {
+    HttpResponse::Ok().json(state.nft_locks.deal_locks(&deal_id))
+}
+
+/// Unlock a deal's NFTs when the deal is cancelled before escrow
+pub async fn release_deal_locks(
+    state: web::Data<AppState>,
+    deal_id: web::Path<String>,
+) -> impl Responder {
+    let released = state.nft_locks.release(&deal_id);
+    log::info!("Released {} NFT locks of deal {}", released.len(), deal_id);
+    HttpResponse::Ok().json(released)
+}
+
+/// Credit an owner's ledger account with funds from nowhere.
+///
+/// Nothing backs the credit, so this is a testnet control endpoint, served with the
+/// simulator ones.
+pub async fn ledger_deposit(
+    state: web::Data<AppState>,
+    payload: web::Json<LedgerDepositRequest>,
+) -> impl Responder {
+    log::info!("Ledger deposit: {} {} to {}", payload.amount, payload.token, payload.owner);
+
+    // Ledger accounts are kept in the tokens of the default chain
+    let posted = state
+        .client(DEFAULT_CHAIN)
+        .and_then(|client| ledger_amount(client.tokens(), &payload.token, payload.amount))
+        .and_then(|(deposit, units)| {
+            state.ledger.deposit(
+                &payload.owner,
+                &deposit.token,
+                units,
+                payload.memo.as_deref().unwrap_or("deposit"),
+            )?;
+            Ok(deposit.token)
+        });
+
+    match posted {
+        Ok(token) => {
+            HttpResponse::Ok().json(ledger_balance_response(&state.ledger, &payload.owner, &token))
+        }
+        Err(e) => {
+            log::error!("Ledger deposit failed: {}", e);
+            HttpResponse::BadRequest().json(ErrorResponse {
+                error: "LEDGER_ERROR".to_string(),
+                message: format!("Ledger deposit failed: {}", e),
+            })
+        }
+    }
+}
+
+/// Ledger balances of an owner in one token (USDC by default), derived from journal entries
+pub async fn ledger_balances(
+    state: web::Data<AppState>,
+    owner: web::Path<String>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    HttpResponse::Ok().json(ledger_balance_response(
+        &state.ledger,
+        &owner,
+        &query.token.to_uppercase(),
+    ))
+}
+
+/// Journal entries touching any account of an owner
+pub async fn ledger_entries(
+    state: web::Data<AppState>,
+    owner: web::Path<String>,
+) -> impl Responder {
+    HttpResponse::Ok().json(LedgerEntriesResponse {
+        owner: owner.clone(),
+        entries: state.ledger.entries_for_owner(&owner),
+    })
+}
+
+fn ledger_balance_response(ledger: &Ledger, owner: &str, token: &str) -> LedgerBalanceResponse {
+    let balances = ledger.owner_balances(owner, token);
+    LedgerBalanceResponse {
+        owner: owner.to_string(),
+        token: token.to_string(),
+        available: from_units(balances.available),
+        held: from_units(balances.held),
+        fees: from_units(balances.fees),
+    }
+}
+
+/// Latest reconciliation report between stored escrows and chain receipts
+pub async fn reconciliation_report(state: web::Data<AppState>) -> impl Responder {
+    match state.reconciliation_reports.latest() {
+        Some(report) => HttpResponse::Ok().json(report),
+        None => HttpResponse::NotFound().json(ErrorResponse {
+            error: "NO_REPORT".to_string(),
+            message: "Reconciliation has not run yet".to_string(),
+        }),
+    }
+}
+
+/// Run reconciliation immediately and publish the resulting report
+pub async fn run_reconciliation(state: web::Data<AppState>) -> impl Responder {
+    log::info!("Running on-demand reconciliation");
+
+    let report = reconcile(&state).await;
+    state.reconciliation_reports.publish(report.clone());
+    HttpResponse::Ok().json(report)
+}
+
+/// Set the base fee of a simulated chain to exercise fee bumping
+pub async fn simulate_base_fee(
+    state: web::Data<AppState>,
+    payload: web::Json<SimulateBaseFeeRequest>,
+) -> impl Responder {
+    let client = match state.client(&payload.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let chain = client.simulator();
+    chain.set_base_fee(payload.base_fee_gwei);
+    HttpResponse::Ok().json(SimulateBaseFeeRequest {
+        chain: payload.chain.clone(),
+        base_fee_gwei: chain.base_fee(),
+    })
+}
+
+/// Set an NFT holding on a simulated chain, e.g. an edition's supply for a seller
+pub async fn simulate_nft_balance(
+    state: web::Data<AppState>,
+    payload: web::Json<SimulateNftBalanceRequest>,
+) -> impl Responder {
+    if let Err(e) = payload
+        .nft
+        .validate()
+        .and_then(|_| validate_address(&payload.owner_address))
+    {
+        return invalid_request_response(&e);
+    }
+
+    let client = match state.client(&payload.nft.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let chain = client.simulator();
+    chain.set_nft_balance(&payload.nft, &payload.owner_address, payload.balance);
+    state
+        .query_cache
+        .invalidate(&payload.nft.chain, std::slice::from_ref(&payload.nft), &[]);
+    state
+        .indexer
+        .forget(&payload.nft.chain, std::slice::from_ref(&payload.nft), &[]);
+    HttpResponse::Ok().json(SimulateNftBalanceRequest {
+        nft: payload.nft.clone(),
+        owner_address: payload.owner_address.clone(),
+        balance: chain.nft_balance(&payload.nft, &payload.owner_address),
+    })
+}
+
+/// Set a token balance on a simulated chain, e.g. to leave a buyer short of the price
+pub async fn simulate_token_balance(
+    state: web::Data<AppState>,
+    payload: web::Json<SimulateTokenBalanceRequest>,
+) -> impl Responder {
+    let client = match state.client(&payload.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let token = validate_address(&payload.owner_address)
+        .and_then(|_| client.tokens().get(&payload.token));
+    let token = match token {
+        Ok(token) => token,
+        Err(e) => return invalid_request_response(&e),
+    };
+
+    let chain = client.simulator();
+    if let Err(e) = chain.set_token_balance(&token.symbol, &payload.owner_address, payload.balance)
+    {
+        return invalid_request_response(&e);
+    }
+    let owner = [payload.owner_address.as_str()];
+    state.query_cache.invalidate(&payload.chain, &[], &owner);
+    state.indexer.forget(&payload.chain, &[], &owner);
+    HttpResponse::Ok().json(SimulateTokenBalanceRequest {
+        chain: payload.chain.clone(),
+        token: token.symbol.clone(),
+        owner_address: payload.owner_address.clone(),
+        balance: chain.token_balance(&token.symbol, &payload.owner_address),
+    })
+}
+
+/// Orphan recent blocks of a simulated chain to exercise reorg handling
+pub async fn simulate_reorg(
+    state: web::Data<AppState>,
+    payload: web::Json<SimulateReorgRequest>,
+) -> impl Responder {
+    log::warn!(
+        "Simulating reorg of depth {} on {} (reinclude: {})",
+        payload.depth,
+        payload.chain,
+        payload.reinclude
+    );
+
+    let client = match state.client(&payload.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let orphaned = client.simulator().reorg(payload.depth, payload.reinclude);
+    state.query_cache.clear_chain(&payload.chain);
+    HttpResponse::Ok().json(SimulateReorgResponse {
+        depth: payload.depth,
+        orphaned_transactions: orphaned,
+    })
+}
+
+/// Status of any transaction on a chain, with its receipt once mined
+pub async fn transaction_status(
+    state: web::Data<AppState>,
+    tx_hash: web::Path<String>,
+    query: web::Query<ChainQuery>,
+) -> impl Responder {
+    let client = match state.client(&query.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+
+    let status = match client.get_transaction_status(&tx_hash).await {
+        Ok(status) => status,
+        Err(ArkError::TransactionNotFound(_)) => {
+            return HttpResponse::NotFound().json(ErrorResponse {
+                error: "TRANSACTION_NOT_FOUND".to_string(),
+                message: format!("Transaction {} is unknown", tx_hash),
+            });
+        }
+        Err(e) => {
+            log::error!("Failed to query transaction {}: {}", tx_hash, e);
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "QUERY_FAILED".to_string(),
+                message: format!("Failed to query transaction: {}", e),
+            });
+        }
+    };
+    let receipt = match status {
+        TxStatus::Success | TxStatus::Reverted => {
+            client.get_transaction_receipt(&tx_hash).await.ok()
+        }
+        _ => None,
+    };
+
+    HttpResponse::Ok().json(TransactionStatusResponse {
+        tx_hash: tx_hash.into_inner(),
+        status,
+        receipt,
+    })
+}
+
+/// Unmined transactions of the operator wallet on a chain, with their replacement history
+pub async fn pending_transactions(
+    state: web::Data<AppState>,
+    query: web::Query<ChainQuery>,
+) -> impl Responder {
+    let client = match state.client(&query.chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let pending = client.account(&state.wallet).pending().all();
+    HttpResponse::Ok().json(PendingTransactionsResponse {
+        chain: query.chain.clone(),
+        transactions: pending.iter().map(pending_view).collect(),
+    })
+}
+
+/// Replace a pending operator transaction with the same call at a higher fee
+pub async fn speed_up_transaction(
+    state: web::Data<AppState>,
+    tx_hash: web::Path<String>,
+    query: web::Query<ChainQuery>,
+) -> impl Responder {
+    replace_pending_transaction(&state, &query.chain, &tx_hash, false).await
+}
+
+/// Replace a pending operator transaction with a no-op so it can no longer execute
+pub async fn cancel_transaction(
+    state: web::Data<AppState>,
+    tx_hash: web::Path<String>,
+    query: web::Query<ChainQuery>,
+) -> impl Responder {
+    replace_pending_transaction(&state, &query.chain, &tx_hash, true).await
+}
+
+async fn replace_pending_transaction(
+    state: &AppState,
+    chain: &str,
+    tx_hash: &str,
+    cancel: bool,
+) -> HttpResponse {
+    let client = match state.client(chain) {
+        Ok(client) => client,
+        Err(e) => return client_error_response(&e),
+    };
+    let wallet = &state.wallet;
+    let account = client.account(wallet);
+
+    let Some(pending) = account.pending().find(tx_hash) else {
+        return HttpResponse::NotFound().json(ErrorResponse {
+            error: "TRANSACTION_NOT_PENDING".to_string(),
+            message: format!("No pending operator transaction with hash {}", tx_hash),
+        });
+    };
+
+    let replaced = if cancel {
+        client.cancel_transaction(wallet, pending.nonce).await
+    } else {
+        client.speed_up_transaction(wallet, pending.nonce).await
+    };
+    match replaced {
+        Ok(_) => match account.pending().get(pending.nonce) {
+            Some(updated) => HttpResponse::Ok().json(pending_view(&updated)),
+            None => HttpResponse::Conflict().json(ErrorResponse {
+                error: "TRANSACTION_NOT_PENDING".to_string(),
+                message: format!("Transaction {} was mined meanwhile", tx_hash),
+            }),
+        },
+        Err(e) => {
+            log::error!("Failed to replace transaction {}: {}", tx_hash, e);
+            HttpResponse::BadRequest().json(ErrorResponse {
+                error: "REPLACEMENT_FAILED".to_string(),
+                message: format!("Failed to replace transaction: {}", e),
+            })
+        }
+    }
+}
+
+fn pending_view(pending: &PendingTransaction) -> PendingTransactionView {
+    let current = pending.current();
+    PendingTransactionView {
+        nonce: pending.nonce,
+        tx_hash: current.tx_hash.clone(),
+        original_tx_hash: pending.original_tx_hash().to_string(),
+        replaced_tx_hashes: pending.versions[..pending.versions.len() - 1]
+            .iter()
+            .map(|v| v.tx_hash.clone())
+            .collect(),
+        max_fee_per_gas_gwei: current.tx.max_fee_per_gas,
+        cancelled: pending.is_cancelled(),
+        age_secs: pending.age_secs(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::ChainSection;
+    use crate::holds::HoldStatus;
+    use crate::config::Config;
+    use crate::simulator::DEFAULT_BASE_FEE_GWEI;
+    use crate::tokens::USDC;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    /// State over a scratch data directory, so tests do not share stored deals
+    fn state() -> web::Data<AppState> {
+        state_with(|_| {})
+    }
+
+    fn state_with(configure: impl FnOnce(&mut Config)) -> web::Data<AppState> {
+        let mut config = Config::default();
+        config.storage.data_dir =
+            std::env::temp_dir().join(format!("ark-handlers-{:016x}", rand::random::<u64>()));
+        configure(&mut config);
+        web::Data::new(AppState::new(config).unwrap())
+    }
+
+    /// Add a chain of its own with quick blocks, so tests that move NFTs or price
+    /// transactions out leave other tests alone; returns its name
+    fn fast_chain(config: &mut Config) -> String {
+        let chain = format!("fast-{:08x}", rand::random::<u32>());
+        config.chains.insert(
+            chain.clone(),
+            ChainSection {
+                chain_id: Some(rand::random::<u32>() as u64 + 1),
+                block_time_ms: Some(50),
+                confirmations: Some(1),
+                escrow_contract: Some(address()),
+                tokens: Some("USDC:0x0000000000000000000000000000000000c0ffee:6".to_string()),
+                ..Default::default()
+            },
+        );
+        chain
+    }
+
+    fn routes(cfg: &mut web::ServiceConfig) {
+        cfg.route("/run-consensus", web::post().to(run_consensus))
+            .route("/execute-escrow", web::post().to(execute_escrow))
+            .route("/execute-swap", web::post().to(execute_swap))
+            .route("/query-nft-ownership/batch", web::post().to(query_nft_ownership_batch))
+            .route("/query-token-balance", web::post().to(query_token_balance))
+            .route("/portfolio/{address}", web::get().to(wallet_portfolio))
+            .route("/history/{address}", web::get().to(address_history))
+            .route("/deals/{deal_id}/timeline", web::get().to(deal_timeline))
+            .route("/policies/{kind}/{id}", web::get().to(get_spending_policy))
+            .route("/policies/{kind}/{id}", web::put().to(set_spending_policy))
+            .route("/holds", web::post().to(place_hold))
+            .route("/simulator/nft-balance", web::post().to(simulate_nft_balance))
+            .route("/simulator/token-balance", web::post().to(simulate_token_balance));
+    }
+
+    /// A fresh address, so tests on the process-wide simulated chains hold their own funds
+    fn address() -> String {
+        format!("0x{}", hex::encode(rand::random::<[u8; 20]>()))
+    }
+
+    fn post(uri: &str, body: Value) -> test::TestRequest {
+        test::TestRequest::post().uri(uri).set_json(body)
+    }
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(uri)
+    }
+
+    /// Escrow of one BAYC token for 10 USDC
+    fn escrow_body(chain: &str, deal_id: &str, buyer: &str, seller: &str, token_id: u32) -> Value {
+        json!({
+            "deal_id": deal_id,
+            "chain": chain,
+            "buyer_address": buyer,
+            "seller_address": seller,
+            "nft_id": format!("BAYC#{}", token_id),
+            "price": 10.0,
+        })
+    }
+
+    /// Set how many units of a BAYC token `owner` holds
+    fn nft_balance_body(chain: &str, token_id: u32, owner: &str, balance: u64) -> Value {
+        json!({
+            "chain": chain,
+            "collection": "BAYC",
+            "token_id": token_id.to_string(),
+            "owner_address": owner,
+            "balance": balance,
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_token_balances_are_kept_in_the_index() {
+        let state = state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        state
+            .indexer
+            .sync(state.client(DEFAULT_CHAIN).unwrap())
+            .await
+            .unwrap();
+        let owner = address();
+        let post = |uri: &str, body: Value| test::TestRequest::post().uri(uri).set_json(body);
+        let set_balance = |balance: f64| {
+            post(
+                "/simulator/token-balance",
+                json!({ "owner_address": owner, "balance": balance }),
+            )
+            .to_request()
+        };
+        let query = || post("/query-token-balance", json!({ "address": owner })).to_request();
+
+        test::call_service(&app, set_balance(100.0)).await;
+        let first: Value = test::call_and_read_body_json(&app, query()).await;
+        assert_eq!(first["balance"], 100.0);
+        assert_eq!(first["source"], "chain");
+        let second: Value = test::call_and_read_body_json(&app, query()).await;
+        assert_eq!(second["balance"], 100.0);
+        assert_eq!(second["source"], "index");
+
+        // A balance changed outside the chain's transfers is read from the chain again
+        test::call_service(&app, set_balance(30.0)).await;
+        let third: Value = test::call_and_read_body_json(&app, query()).await;
+        assert_eq!(third["balance"], 30.0);
+        assert_eq!(third["source"], "chain");
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_holds_and_consensus_count_against_the_real_balance() {
+        let state = state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let buyer = address();
+        let post = |uri: &str, body: Value| test::TestRequest::post().uri(uri).set_json(body);
+
+        let funded = post(
+            "/simulator/token-balance",
+            json!({ "owner_address": buyer, "balance": 100.0 }),
+        );
+        let funded: Value = test::call_and_read_body_json(&app, funded.to_request()).await;
+        assert_eq!(funded["balance"], 100.0);
+
+        let hold = |deal_id: &str| {
+            post(
+                "/holds",
+                json!({ "deal_id": deal_id, "address": buyer, "amount": 60.0 }),
+            )
+            .to_request()
+        };
+        let first = test::call_service(&app, hold("hold-1")).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        // Together the two holds would exceed what the buyer holds on chain
+        let second = test::call_service(&app, hold("hold-2")).await;
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+        let second: Value = test::read_body_json(second).await;
+        assert_eq!(second["error"], "INSUFFICIENT_AVAILABLE_BALANCE");
+
+        // Verifiers see only the balance the first deal does not hold
+        for (deal_id, price, approved) in [("consensus-1", 50.0, false), ("consensus-2", 40.0, true)] {
+            let consensus = post(
+                "/run-consensus",
+                json!({
+                    "deal_id": deal_id,
+                    "nft_id": format!("BAYC#{}", rand::random::<u32>()),
+                    "seller_address": address(),
+                    "buyer_balance": 100.0,
+                    "buyer_address": buyer,
+                    "price": price,
+                    "signatures": ["sig-1", "sig-2", "sig-3", "sig-4", "sig-5"],
+                }),
+            );
+            let consensus: Value =
+                test::call_and_read_body_json(&app, consensus.to_request()).await;
+            assert_eq!(consensus["approved"], approved, "{}", consensus);
+            assert_eq!(consensus["verifiers"][0]["checks"]["buyer_balance"], approved);
+        }
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_consensus_reads_nfts_the_way_escrow_trades_them() {
+        let state = state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let consensus = |nft: Value| {
+            let mut body = json!({
+                "deal_id": format!("consensus-{}", rand::random::<u32>()),
+                "seller_address": address(),
+                "buyer_balance": 100.0,
+                "signatures": ["sig-1", "sig-2", "sig-3", "sig-4", "sig-5"],
+            });
+            body.as_object_mut().unwrap().extend(nft.as_object().unwrap().clone());
+            test::TestRequest::post().uri("/run-consensus").set_json(body).to_request()
+        };
+
+        let rejected = [
+            // ERC-721 tokens are unique
+            json!({ "nft_id": "BAYC#1", "nft_amount": 2 }),
+            // An edition trades at least one unit
+            json!({ "nft_id": "BAYC#2", "nft_standard": "erc1155", "nft_amount": 0 }),
+            // Every NFT must live on the deal's chain
+            json!({ "nft_id": "sepolia:BAYC#3" }),
+            json!({ "bundle": [{ "nft_id": "BAYC#4", "nft_amount": 3 }] }),
+            // A deal trades something
+            json!({}),
+        ];
+        for nft in rejected {
+            let response = test::call_service(&app, consensus(nft.clone())).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", nft);
+        }
+
+        let edition = json!({ "nft_id": "BAYC#5", "nft_standard": "erc1155", "nft_amount": 3 });
+        let response = test::call_service(&app, consensus(edition)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_consensus_checks_the_sellers_holdings_on_chain() {
+        let state = state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let seller = address();
+        let token_id: u32 = rand::random();
+        let consensus = || {
+            // What the request claims about the seller's holdings is not what counts
+            post(
+                "/run-consensus",
+                json!({
+                    "deal_id": format!("consensus-{}", rand::random::<u32>()),
+                    "nft_id": format!("BAYC#{}", token_id),
+                    "nft_ownership": true,
+                    "seller_nft_balance": 1,
+                    "seller_address": seller,
+                    "buyer_balance": 100.0,
+                    "signatures": ["sig-1", "sig-2", "sig-3", "sig-4", "sig-5"],
+                }),
+            )
+            .to_request()
+        };
+
+        let gone = nft_balance_body(DEFAULT_CHAIN, token_id, &seller, 0);
+        test::call_service(&app, post("/simulator/nft-balance", gone).to_request()).await;
+        let rejected: Value = test::call_and_read_body_json(&app, consensus()).await;
+        assert_eq!(rejected["approved"], false, "{}", rejected);
+        assert_eq!(rejected["verifiers"][0]["checks"]["nft_ownership"], false);
+
+        let held = nft_balance_body(DEFAULT_CHAIN, token_id, &seller, 1);
+        test::call_service(&app, post("/simulator/nft-balance", held).to_request()).await;
+        let approved: Value = test::call_and_read_body_json(&app, consensus()).await;
+        assert_eq!(approved["approved"], true, "{}", approved);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_timed_out_escrow_keeps_its_reservations_until_mined() {
+        let mut chain = String::new();
+        let state = state_with(|config| {
+            config.rpc.confirmation_timeout_secs = 1;
+            chain = fast_chain(config);
+        });
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let (buyer, seller) = (address(), address());
+        let escrow = || {
+            test::TestRequest::post()
+                .uri("/execute-escrow")
+                .set_json(json!({
+                    "deal_id": "timeout-1",
+                    "chain": chain,
+                    "buyer_address": buyer,
+                    "seller_address": seller,
+                    "nft_id": "BAYC#1",
+                    "price": 10.0,
+                }))
+                .to_request()
+        };
+
+        // A hold shorter than the wait for the transaction
+        state
+            .holds
+            .place(
+                "timeout-1",
+                &chain,
+                &buyer,
+                TokenAmount::new(USDC, 10.0),
+                Duration::from_secs(2),
+                100.0,
+            )
+            .unwrap();
+
+        // No max fee the service offers covers the base fee, so nothing is mined
+        let simulated = state.client(&chain).unwrap().simulator();
+        simulated.set_base_fee(state.config.fees.max_fee_per_gas_gwei + 1);
+        let response = test::call_service(&app, escrow()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let pending: Value = test::read_body_json(response).await;
+        assert_eq!(pending["status"], "pending");
+        let tx_hash = pending["tx_hash"].as_str().unwrap();
+
+        // The transaction may still be mined, so the deal keeps everything it took
+        assert_eq!(state.ledger.owner_balances(&buyer, USDC).held, 10_000_000);
+        assert_eq!(state.nft_locks.deal_locks("timeout-1").len(), 1);
+        // and its lock and hold outlive their TTLs until the transaction resolves
+        assert_eq!(state.nft_locks.deal_locks("timeout-1")[0].expires_at, i64::MAX);
+        actix_web::rt::time::sleep(Duration::from_secs(2)).await;
+        let hold = state.holds.get("timeout-1").unwrap();
+        assert_eq!(hold.status, HoldStatus::Active);
+        assert_eq!(hold.expires_at, i64::MAX);
+        let record = state.escrows.get("timeout-1").unwrap();
+        assert_eq!(record.status, TxStatus::Pending);
+        assert_eq!(record.tx_hash.as_deref(), Some(tx_hash));
+        let submitted = state
+            .timelines
+            .timeline("timeout-1")
+            .into_iter()
+            .any(|entry| match entry.event {
+                DealEvent::TransactionSubmitted { tx_hash: h, .. } => h == tx_hash,
+                _ => false,
+            });
+        assert!(submitted);
+        let replayed = test::call_service(&app, escrow()).await;
+        assert_eq!(replayed.status(), StatusCode::CONFLICT);
+
+        // Once mined, the deal is settled in the background
+        simulated.set_base_fee(DEFAULT_BASE_FEE_GWEI);
+        for _ in 0..100 {
+            if state.nft_locks.deal_locks("timeout-1").is_empty() {
+                break;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        // The pending record is replaced by the outcome of the same attempt
+        let records: Vec<EscrowRecord> = state
+            .escrows
+            .all()
+            .into_iter()
+            .filter(|r| r.deal_id == "timeout-1")
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, TxStatus::Success);
+        assert!(state.nft_locks.deal_locks("timeout-1").is_empty());
+        assert_eq!(state.holds.get("timeout-1").unwrap().status, HoldStatus::Captured);
+        assert_eq!(state.ledger.owner_balances(&buyer, USDC).held, 0);
+        assert!(state.ledger.owner_balances(&seller, USDC).available > 0);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_a_mined_deal_cannot_be_replayed() {
+        let mut chain = String::new();
+        let state = state_with(|config| chain = fast_chain(config));
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let (buyer, seller) = (address(), address());
+        let body = escrow_body(&chain, "replay-1", &buyer, &seller, rand::random());
+
+        let escrow = || post("/execute-escrow", body.clone()).to_request();
+        let first: Value = test::call_and_read_body_json(&app, escrow()).await;
+        assert_eq!(first["success"], true);
+        let paid = state.ledger.owner_balances(&seller, USDC).available;
+        assert!(paid > 0);
+
+        let replayed = test::call_service(&app, escrow()).await;
+        assert_eq!(replayed.status(), StatusCode::CONFLICT);
+        let replayed: Value = test::read_body_json(replayed).await;
+        assert_eq!(replayed["error"], "DEAL_CONFLICT");
+
+        // Nothing was held, locked or paid a second time
+        assert_eq!(state.ledger.owner_balances(&seller, USDC).available, paid);
+        assert_eq!(state.ledger.owner_balances(&buyer, USDC).held, 0);
+        assert!(state.nft_locks.deal_locks("replay-1").is_empty());
+        assert_eq!(state.escrows.involving(&buyer, None).len(), 1);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_a_failed_escrow_releases_everything_it_took() {
+        let mut chain = String::new();
+        let state = state_with(|config| chain = fast_chain(config));
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let (buyer, seller) = (address(), address());
+        let token_id = rand::random();
+        let agent = format!("agent-{:08x}", rand::random::<u32>());
+        let policy = test::TestRequest::put()
+            .uri(&format!("/policies/agents/{}", agent))
+            .set_json(json!({ "max_per_day": 100.0 }))
+            .to_request();
+        assert!(test::call_service(&app, policy).await.status().is_success());
+        let spent_today = || get(&format!("/policies/agents/{}", agent)).to_request();
+        let hold = json!({
+            "deal_id": "failed-1",
+            "chain": chain,
+            "address": buyer,
+            "amount": 10.0,
+        });
+        let hold = test::call_service(&app, post("/holds", hold).to_request()).await;
+        assert_eq!(hold.status(), StatusCode::CREATED);
+
+        // The seller no longer holds the NFT, so the escrow reverts
+        let sold = nft_balance_body(&chain, token_id, &seller, 0);
+        test::call_service(&app, post("/simulator/nft-balance", sold).to_request()).await;
+        let mut body = escrow_body(&chain, "failed-1", &buyer, &seller, token_id);
+        body["agent_id"] = json!(agent);
+        let escrow = || post("/execute-escrow", body.clone()).to_request();
+        let failed: Value = test::call_and_read_body_json(&app, escrow()).await;
+        assert_eq!(failed["success"], false);
+        assert_eq!(failed["status"], "reverted");
+
+        assert_eq!(state.ledger.owner_balances(&buyer, USDC).held, 0);
+        assert!(state.nft_locks.deal_locks("failed-1").is_empty());
+        let hold = state.holds.get("failed-1").unwrap();
+        assert_eq!(hold.status, HoldStatus::Released);
+        let policy: Value = test::call_and_read_body_json(&app, spent_today()).await;
+        assert_eq!(policy["spent_today"], 0.0);
+
+        // Once the seller holds the NFT again, the same deal goes through
+        let restored = nft_balance_body(&chain, token_id, &seller, 1);
+        test::call_service(&app, post("/simulator/nft-balance", restored).to_request()).await;
+        let retried: Value = test::call_and_read_body_json(&app, escrow()).await;
+        assert_eq!(retried["success"], true);
+        let policy: Value = test::call_and_read_body_json(&app, spent_today()).await;
+        assert_eq!(policy["spent_today"], 10.0);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_a_failed_swap_releases_both_sides() {
+        let mut chain = String::new();
+        let state = state_with(|config| chain = fast_chain(config));
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let (maker, taker) = (address(), address());
+        let token_id: u32 = rand::random();
+        let gone = nft_balance_body(&chain, token_id, &maker, 0);
+        test::call_service(&app, post("/simulator/nft-balance", gone).to_request()).await;
+
+        let swap = json!({
+            "deal_id": "swap-failed-1",
+            "chain": chain,
+            "maker": { "address": maker, "nfts": [{ "nft_id": format!("BAYC#{}", token_id) }] },
+            "taker": { "address": taker, "tokens": [{ "token": USDC, "amount": 25.0 }] },
+        });
+        let swap = post("/execute-swap", swap).to_request();
+        let failed: Value = test::call_and_read_body_json(&app, swap).await;
+        assert_eq!(failed["success"], false);
+
+        assert_eq!(state.ledger.owner_balances(&taker, USDC).held, 0);
+        assert_eq!(state.ledger.owner_balances(&maker, USDC).available, 0);
+        assert!(state.nft_locks.deal_locks("swap-failed-1").is_empty());
+        let record = state.escrows.get("swap-failed-1").unwrap();
+        assert_eq!(record.status, TxStatus::Reverted);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_history_pages_and_deal_timeline() {
+        let mut chain = String::new();
+        let state = state_with(|config| chain = fast_chain(config));
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let (buyer, seller) = (address(), address());
+        let gone: u32 = rand::random();
+        let sold = nft_balance_body(&chain, gone, &seller, 0);
+        test::call_service(&app, post("/simulator/nft-balance", sold).to_request()).await;
+        let reverted = escrow_body(&chain, "history-1", &buyer, &seller, gone);
+        test::call_service(&app, post("/execute-escrow", reverted).to_request()).await;
+        let mined = escrow_body(&chain, "history-2", &buyer, &seller, rand::random());
+        test::call_service(&app, post("/execute-escrow", mined).to_request()).await;
+
+        // Most recent first, one deal per page
+        let history = |offset: usize| {
+            get(&format!("/history/{}?offset={}&limit=1", buyer, offset)).to_request()
+        };
+        let first: Value = test::call_and_read_body_json(&app, history(0)).await;
+        assert_eq!(first["total_deals"], 2);
+        assert_eq!(first["deals"][0]["deal_id"], "history-2");
+        assert_eq!(first["deals"][0]["counterparty"], seller.as_str());
+        assert_eq!(first["next_offset"], 1);
+        let second: Value = test::call_and_read_body_json(&app, history(1)).await;
+        assert_eq!(second["deals"][0]["deal_id"], "history-1");
+        assert_eq!(second["deals"][0]["status"], "reverted");
+        assert!(second["next_offset"].is_null());
+        let too_large = format!("/history/{}?limit={}", buyer, MAX_PAGE_SIZE + 1);
+        let too_large = get(&too_large).to_request();
+        let too_large = test::call_service(&app, too_large).await;
+        assert_eq!(too_large.status(), StatusCode::BAD_REQUEST);
+
+        let timeline = get("/deals/history-2/timeline").to_request();
+        let timeline: Value = test::call_and_read_body_json(&app, timeline).await;
+        let events: Vec<&str> = timeline["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(events, ["transaction_submitted", "transaction_confirmed", "receipt"]);
+        assert_eq!(timeline["record"]["status"], "success");
+        let unknown = get("/deals/never-made/timeline").to_request();
+        let unknown = test::call_service(&app, unknown).await;
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_portfolio_pages_through_nfts() {
+        let state = state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let owner = address();
+        for token_id in [3, 1, 2] {
+            let owned = nft_balance_body(DEFAULT_CHAIN, token_id, &owner, 1);
+            test::call_service(&app, post("/simulator/nft-balance", owned).to_request()).await;
+        }
+
+        let page = |offset: usize| {
+            get(&format!("/portfolio/{}?offset={}&limit=2", owner, offset)).to_request()
+        };
+        let first: Value = test::call_and_read_body_json(&app, page(0)).await;
+        let token_ids = |page: &Value| -> Vec<String> {
+            page["nfts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|nft| nft["token_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(first["total_nfts"], 3);
+        assert_eq!(token_ids(&first), ["1", "2"]);
+        assert_eq!(first["next_offset"], 2);
+        let usdc = first["tokens"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|token| token["token"] == USDC)
+            .unwrap();
+        assert_eq!(usdc["held"], 0.0);
+        let second: Value = test::call_and_read_body_json(&app, page(2)).await;
+        assert_eq!(token_ids(&second), ["3"]);
+        assert!(second["next_offset"].is_null());
+
+        let empty_page = get(&format!("/portfolio/{}?limit=0", owner)).to_request();
+        let empty_page = test::call_service(&app, empty_page).await;
+        assert_eq!(empty_page.status(), StatusCode::BAD_REQUEST);
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_batches_are_bounded_and_run_concurrently() {
+        // Every item is a chain query taking at least 50ms
+        let items: Vec<Value> = (0..8)
+            .map(|i| {
+                let owner = if i == 3 { "not-an-address".to_string() } else { address() };
+                json!({
+                    "collection": "BAYC",
+                    "token_id": rand::random::<u32>().to_string(),
+                    "owner_address": owner,
+                })
+            })
+            .collect();
+        let run = |concurrency: usize, items: Vec<Value>| async move {
+            let state = state_with(|config| {
+                config.batch.max_items = 8;
+                config.batch.concurrency = concurrency;
+            });
+            let app =
+                test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+            let started = std::time::Instant::now();
+            let batch = post("/query-nft-ownership/batch", json!({ "items": items })).to_request();
+            let response = test::call_service(&app, batch).await;
+            let elapsed = started.elapsed();
+            let status = response.status();
+            let body: Value = test::read_body_json(response).await;
+            std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+            (status, body, elapsed)
+        };
+
+        let mut too_many = items.clone();
+        too_many.push(items[0].clone());
+        let (status, body, _) = run(1, too_many).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "BATCH_TOO_LARGE");
+
+        // One item at a time; the invalid item fails alone, in its place
+        let (status, body, serial) = run(1, items.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((body["succeeded"].as_u64(), body["failed"].as_u64()), (Some(7), Some(1)));
+        let indexes: Vec<u64> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["index"].as_u64().unwrap())
+            .collect();
+        assert_eq!(indexes, (0..8).collect::<Vec<u64>>());
+        assert_eq!(body["results"][3]["error"]["error"], "INVALID_ADDRESS");
+        assert!(serial >= Duration::from_millis(7 * 50));
+
+        let (_, body, concurrent) = run(8, items).await;
+        assert_eq!(body["succeeded"], 7);
+        assert!(concurrent < Duration::from_millis(7 * 50));
+    }
 }
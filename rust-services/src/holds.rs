@@ -0,0 +1,289 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ark_client::ArkError;
+use crate::config::HoldsConfig;
+use crate::ledger::{from_units, to_units};
+use crate::tokens::TokenAmount;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldStatus {
+    /// Reserving the amount against the address
+    Active,
+    /// Used by the deal's escrow
+    Captured,
+    /// Given back because the deal was cancelled
+    Released,
+    /// Not captured before its expiry
+    Expired,
+}
+
+/// Amount of an address's on-chain balance reserved for one deal
+#[derive(Serialize, Debug, Clone)]
+pub struct FundHold {
+    pub hold_id: String,
+    pub deal_id: String,
+    pub chain: String,
+    pub address: String,
+    pub amount: TokenAmount,
+    pub status: HoldStatus,
+    pub created_at: i64,
+    pub expires_at: i64,
+    /// When the hold was captured, released or expired
+    pub settled_at: Option<i64>,
+}
+
+impl FundHold {
+    /// Whether the hold is active and reserves `token` of `address` on `chain`
+    pub fn holds_against(&self, chain: &str, address: &str, token: &str) -> bool {
+        self.status == HoldStatus::Active
+            && self.chain == chain
+            && self.address.eq_ignore_ascii_case(address)
+            && self.amount.token.eq_ignore_ascii_case(token)
+    }
+}
+
+/// Pre-authorizations of buyer funds, so one balance cannot back several deals at once.
+///
+/// Holds live in memory and only reduce the balance this service reports; the
+/// funds stay in the buyer's wallet until the escrow moves them.
+pub struct FundHolds {
+    holds: Mutex<Vec<FundHold>>,
+    default_ttl: Duration,
+}
+
+impl FundHolds {
+    pub fn new(default_ttl: Duration) -> Self {
+        Self {
+            holds: Mutex::new(Vec::new()),
+            default_ttl,
+        }
+    }
+
+    /// Holds expire after `holds.ttl_secs` unless placed with their own expiry
+    pub fn from_config(config: &HoldsConfig) -> Self {
+        Self::new(config.ttl())
+    }
+
+    pub fn default_ttl(&self) -> Duration {
+        self.default_ttl
+    }
+
+    /// Reserve `amount` of `address`'s `balance` for a deal until `ttl` has passed.
+    ///
+    /// Fails if the deal already holds funds or if the balance minus the address's
+    /// other active holds does not cover the amount.
+    pub fn place(
+        &self,
+        deal_id: &str,
+        chain: &str,
+        address: &str,
+        amount: TokenAmount,
+        ttl: Duration,
+        balance: f64,
+    ) -> Result<FundHold, ArkError> {
+        let now = chrono::Utc::now().timestamp();
+        let mut holds = self.holds.lock().unwrap();
+        expire(&mut holds, now);
+
+        if holds
+            .iter()
+            .any(|h| h.deal_id == deal_id && h.status == HoldStatus::Active)
+        {
+            return Err(ArkError::HoldConflict(format!(
+                "deal {} already holds funds",
+                deal_id
+            )));
+        }
+
+        let held = held_units(&holds, chain, address, &amount.token);
+        let available = to_units(balance)?.saturating_sub(held);
+        if to_units(amount.amount)? > available {
+            return Err(ArkError::InsufficientBalance {
+                token: amount.token,
+                has: from_units(available.max(0)),
+                needs: amount.amount,
+            });
+        }
+
+        let hold = FundHold {
+            hold_id: format!("hold_{:016x}", rand::random::<u64>()),
+            deal_id: deal_id.to_string(),
+            chain: chain.to_string(),
+            address: address.to_string(),
+            amount,
+            status: HoldStatus::Active,
+            created_at: now,
+            expires_at: now.saturating_add(i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX)),
+            settled_at: None,
+        };
+        holds.push(hold.clone());
+        Ok(hold)
+    }
+
+    /// Total of `token` actively held against an address
+    pub fn held(&self, chain: &str, address: &str, token: &str) -> f64 {
+        let mut holds = self.holds.lock().unwrap();
+        expire(&mut holds, chrono::Utc::now().timestamp());
+        from_units(held_units(&holds, chain, address, token))
+    }
+
+    /// Total of `token` actively held against an address by deals other than `deal_id`
+    pub fn held_by_others(&self, deal_id: &str, chain: &str, address: &str, token: &str) -> f64 {
+        let mut holds = self.holds.lock().unwrap();
+        expire(&mut holds, chrono::Utc::now().timestamp());
+        let own = holds
+            .iter()
+            .filter(|h| h.deal_id == deal_id && h.holds_against(chain, address, token))
+            .map(|h| to_units(h.amount.amount).unwrap_or(0))
+            .sum::<i64>();
+        from_units(held_units(&holds, chain, address, token) - own)
+    }
+
+    /// Latest hold placed for a deal, whatever its status
+    pub fn get(&self, deal_id: &str) -> Option<FundHold> {
+        let mut holds = self.holds.lock().unwrap();
+        expire(&mut holds, chrono::Utc::now().timestamp());
+        holds.iter().rev().find(|h| h.deal_id == deal_id).cloned()
+    }
+
+    /// Keep the deal's active hold until it is captured or released, however long that
+    /// takes, e.g. while its transaction may still be mined
+    pub fn pin(&self, deal_id: &str) -> Option<FundHold> {
+        let mut holds = self.holds.lock().unwrap();
+        expire(&mut holds, chrono::Utc::now().timestamp());
+
+        let hold = holds
+            .iter_mut()
+            .find(|h| h.deal_id == deal_id && h.status == HoldStatus::Active)?;
+        hold.expires_at = i64::MAX;
+        Some(hold.clone())
+    }
+
+    /// Mark the deal's active hold as used by its escrow
+    pub fn capture(&self, deal_id: &str) -> Option<FundHold> {
+        self.settle(deal_id, HoldStatus::Captured)
+    }
+
+    /// Give back the deal's active hold
+    pub fn release(&self, deal_id: &str) -> Option<FundHold> {
+        self.settle(deal_id, HoldStatus::Released)
+    }
+
+    fn settle(&self, deal_id: &str, status: HoldStatus) -> Option<FundHold> {
+        let now = chrono::Utc::now().timestamp();
+        let mut holds = self.holds.lock().unwrap();
+        expire(&mut holds, now);
+
+        let hold = holds
+            .iter_mut()
+            .find(|h| h.deal_id == deal_id && h.status == HoldStatus::Active)?;
+        hold.status = status;
+        hold.settled_at = Some(now);
+        Some(hold.clone())
+    }
+}
+
+fn held_units(holds: &[FundHold], chain: &str, address: &str, token: &str) -> i64 {
+    holds
+        .iter()
+        .filter(|h| h.holds_against(chain, address, token))
+        .map(|h| to_units(h.amount.amount).unwrap_or(0))
+        .sum()
+}
+
+/// Expire active holds whose time is up
+fn expire(holds: &mut [FundHold], now: i64) {
+    for hold in holds
+        .iter_mut()
+        .filter(|h| h.status == HoldStatus::Active && h.expires_at <= now)
+    {
+        hold.status = HoldStatus::Expired;
+        hold.settled_at = Some(hold.expires_at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::USDC;
+
+    const BUYER: &str = "0x00000000000000000000000000000000000b0b0b";
+    const BUYER_CHECKSUMMED: &str = "0x00000000000000000000000000000000000B0B0B";
+
+    #[test]
+    fn test_holds_reserve_balance_until_captured_released_or_expired() {
+        let holds = FundHolds::new(Duration::from_secs(60));
+        let ttl = holds.default_ttl();
+        let place = |deal_id: &str, amount: f64| {
+            holds.place(
+                deal_id,
+                "ark",
+                BUYER,
+                TokenAmount::new(USDC, amount),
+                ttl,
+                1000.0,
+            )
+        };
+
+        place("deal-1", 600.0).unwrap();
+        assert_eq!(holds.held("ark", BUYER_CHECKSUMMED, USDC), 600.0);
+        assert_eq!(holds.held("sepolia", BUYER, USDC), 0.0);
+
+        // The same balance cannot back a second deal, nor the same deal twice
+        assert!(matches!(
+            place("deal-2", 500.0),
+            Err(ArkError::InsufficientBalance { has, .. }) if has == 400.0
+        ));
+        assert!(matches!(
+            place("deal-1", 100.0),
+            Err(ArkError::HoldConflict(_))
+        ));
+        place("deal-2", 400.0).unwrap();
+
+        assert_eq!(
+            holds.capture("deal-1").unwrap().status,
+            HoldStatus::Captured
+        );
+        assert_eq!(
+            holds.release("deal-2").unwrap().status,
+            HoldStatus::Released
+        );
+        assert!(holds.release("deal-2").is_none());
+        assert_eq!(holds.held("ark", BUYER, USDC), 0.0);
+
+        let expired = holds
+            .place(
+                "deal-3",
+                "ark",
+                BUYER,
+                TokenAmount::new(USDC, 1000.0),
+                Duration::ZERO,
+                1000.0,
+            )
+            .unwrap();
+        assert_eq!(
+            holds.get(&expired.deal_id).unwrap().status,
+            HoldStatus::Expired
+        );
+        assert!(holds.capture("deal-3").is_none());
+        assert_eq!(holds.held("ark", BUYER, USDC), 0.0);
+
+        // An expiry too far away to represent saturates instead of wrapping around
+        let lasting = holds
+            .place(
+                "deal-4",
+                "ark",
+                BUYER,
+                TokenAmount::new(USDC, 100.0),
+                Duration::from_secs(u64::MAX),
+                1000.0,
+            )
+            .unwrap();
+        assert_eq!(lasting.expires_at, i64::MAX);
+        assert_eq!(holds.held_by_others("deal-4", "ark", BUYER, USDC), 0.0);
+        assert_eq!(holds.held_by_others("deal-5", "ark", BUYER, USDC), 100.0);
+    }
+}
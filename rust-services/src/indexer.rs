@@ -0,0 +1,658 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ark_client::{ArkClient, ArkError, BlockLog, TransactionLog};
+use crate::chains::ChainConfig;
+use crate::config::IndexerConfig;
+use crate::nft::{NftRef, TokenStandard};
+use crate::state::AppState;
+use crate::tokens::TokenInfo;
+
+/// Position of a chain's index, saved with it so a restart resumes there
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    /// First block indexed; transfers before it are not in the index
+    pub start_block: u64,
+    /// Last block indexed
+    pub block_number: u64,
+    /// Hash of the last block indexed, to notice it being reorged out
+    pub block_hash: String,
+    pub updated_at: i64,
+}
+
+/// Owner of an ERC-721 token as of its last indexed transfer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexedOwner {
+    pub nft: NftRef,
+    /// Lowercase address
+    pub owner: String,
+    pub block_number: u64,
+    pub tx_hash: String,
+}
+
+/// Token balance of an address as read from the chain, in base units at the token's
+/// decimals
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexedBalance {
+    units: u128,
+    /// Head of the chain when the balance was read; transfers up to it are included
+    block_number: u64,
+    block_hash: String,
+}
+
+/// What an address received and sent of an asset since the index started, in the
+/// asset's smallest unit: base units at the token's decimals, or NFT units
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+struct Flow {
+    received: u128,
+    sent: u128,
+    /// Decimals of the token, 0 for NFTs
+    decimals: u32,
+}
+
+/// What an address received and sent of a token (by symbol) or an NFT (by token key)
+#[derive(Serialize, Debug, Clone)]
+pub struct AssetFlow {
+    pub asset: String,
+    pub received: f64,
+    pub sent: f64,
+    pub net: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexStatus {
+    pub chain: String,
+    pub checkpoint: Option<Checkpoint>,
+    pub nfts_indexed: usize,
+    pub addresses_indexed: usize,
+    pub transfers_indexed: u64,
+}
+
+/// Transfers of one chain's configured collections and tokens
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct ChainIndex {
+    checkpoint: Option<Checkpoint>,
+    /// ERC-721 owners by `NftRef::token_key`
+    owners: HashMap<String, IndexedOwner>,
+    /// Flows by lowercase address, then asset
+    flows: HashMap<String, HashMap<String, Flow>>,
+    /// Token balances by lowercase address, then symbol
+    #[serde(default)]
+    balances: HashMap<String, HashMap<String, IndexedBalance>>,
+    transfers: u64,
+}
+
+impl ChainIndex {
+    fn apply(&mut self, entry: &BlockLog, chain: &ChainConfig) {
+        let (asset, from, to, units, decimals) = match &entry.log {
+            TransactionLog::NftTransfer { nft, from, to } => {
+                if !chain
+                    .collections
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&nft.collection))
+                {
+                    return;
+                }
+                if nft.standard == TokenStandard::Erc721 {
+                    self.owners.insert(
+                        nft.token_key(),
+                        IndexedOwner {
+                            nft: nft.clone(),
+                            owner: to.to_lowercase(),
+                            block_number: entry.block_number,
+                            tx_hash: entry.tx_hash.clone(),
+                        },
+                    );
+                }
+                (nft.token_key(), from, to, Ok(u128::from(nft.amount)), 0)
+            }
+            TransactionLog::TokenTransfer {
+                token,
+                from,
+                to,
+                amount,
+            } => match chain.tokens.get(token) {
+                Ok(token) => (
+                    token.symbol.clone(),
+                    from,
+                    to,
+                    token.base_units(*amount),
+                    token.decimals,
+                ),
+                Err(_) => return,
+            },
+        };
+        let Ok(units) = units else {
+            log::warn!("Skipping transfer with invalid amount in {}", entry.tx_hash);
+            return;
+        };
+
+        for (address, received) in [(from, false), (to, true)] {
+            // A balance read before this transfer no longer holds
+            if let Some(balances) = self.balances.get_mut(&address.to_lowercase()) {
+                if balances
+                    .get(&asset)
+                    .is_some_and(|balance| balance.block_number < entry.block_number)
+                {
+                    balances.remove(&asset);
+                }
+            }
+            let flow = self
+                .flows
+                .entry(address.to_lowercase())
+                .or_default()
+                .entry(asset.clone())
+                .or_default();
+            flow.decimals = decimals;
+            if received {
+                flow.received = flow.received.saturating_add(units);
+            } else {
+                flow.sent = flow.sent.saturating_add(units);
+            }
+        }
+        self.transfers += 1;
+    }
+}
+
+/// Local index of NFT owners and transfer flows, following every chain's blocks once
+/// they are `confirmations` deep, so reorgs seldom reach the index.
+///
+/// Each chain's index is saved to `<data_dir>/index/<chain>.json` after every
+/// step, so a restart resumes from its checkpoint. When the checkpointed block is
+/// reorged out, the chain is indexed again from its start block. ERC-1155 balances
+/// cannot be derived from transfers alone, so editions only have flows. Token
+/// balances cannot either, so they are read from the chain once and kept until a
+/// transfer changes them.
+pub struct ChainIndexer {
+    indexes: HashMap<String, Mutex<ChainIndex>>,
+    dir: PathBuf,
+    /// Most blocks indexed in one step
+    batch_blocks: u64,
+    /// Block a chain without a checkpoint is indexed from; its head otherwise
+    start_block: Option<u64>,
+}
+
+impl ChainIndexer {
+    /// Indexes of `chains`, restored from their files in `dir` where present
+    pub fn load(chains: &[ChainConfig], config: &IndexerConfig, dir: &Path) -> Self {
+        let indexes = chains
+            .iter()
+            .map(|chain| {
+                let index = read_index(&index_path(dir, &chain.name)).unwrap_or_else(|e| {
+                    log::warn!("Indexing {} from scratch: {}", chain.name, e);
+                    ChainIndex::default()
+                });
+                (chain.name.clone(), Mutex::new(index))
+            })
+            .collect();
+        Self {
+            indexes,
+            dir: dir.to_path_buf(),
+            batch_blocks: config.batch_blocks,
+            start_block: config.start_block,
+        }
+    }
+
+    /// Index the confirmed blocks mined after the chain's checkpoint, at most
+    /// `batch_blocks` of them; returns how many were indexed
+    pub async fn sync(&self, client: &ArkClient) -> Result<u64, ArkError> {
+        let chain = client.chain_config();
+        let index = self
+            .indexes
+            .get(&chain.name)
+            .ok_or_else(|| ArkError::UnknownChain(chain.name.clone()))?;
+        // Blocks less than `confirmations` deep may still be reorged out
+        let head = client
+            .get_block_number()
+            .await?
+            .saturating_sub(u64::from(chain.confirmations));
+        let checkpoint = index.lock().unwrap().checkpoint.clone();
+
+        let checkpoint = match checkpoint {
+            Some(checkpoint)
+                if checkpoint.block_number <= head
+                    && client.get_block_hash(checkpoint.block_number).await?
+                        == checkpoint.block_hash =>
+            {
+                checkpoint
+            }
+            previous => {
+                // A new index, or one whose last block left the canonical chain
+                let start_block = match &previous {
+                    Some(previous) => {
+                        log::warn!(
+                            "Block {} of {} was reorged out, indexing again from block {}",
+                            previous.block_number,
+                            chain.name,
+                            previous.start_block
+                        );
+                        previous.start_block
+                    }
+                    None => self.start_block.unwrap_or(head),
+                }
+                .min(head)
+                .max(1);
+                let checkpoint = Checkpoint {
+                    start_block,
+                    block_number: start_block - 1,
+                    block_hash: client.get_block_hash(start_block - 1).await?,
+                    updated_at: chrono::Utc::now().timestamp(),
+                };
+                *index.lock().unwrap() = ChainIndex {
+                    checkpoint: Some(checkpoint.clone()),
+                    ..Default::default()
+                };
+                checkpoint
+            }
+        };
+
+        let from = checkpoint.block_number + 1;
+        if from > head {
+            return Ok(0);
+        }
+        let to = head.min(checkpoint.block_number + self.batch_blocks);
+        let logs = client.get_logs(from, to).await?;
+        let block_hash = client.get_block_hash(to).await?;
+
+        let snapshot = {
+            let mut index = index.lock().unwrap();
+            for entry in &logs {
+                index.apply(entry, chain);
+            }
+            index.checkpoint = Some(Checkpoint {
+                block_number: to,
+                block_hash,
+                updated_at: chrono::Utc::now().timestamp(),
+                ..checkpoint
+            });
+            index.clone()
+        };
+        write_index(&index_path(&self.dir, &chain.name), &snapshot)?;
+
+        log::debug!(
+            "Indexed blocks {}..={} of {}: {} transfers",
+            from,
+            to,
+            chain.name,
+            logs.len()
+        );
+        Ok(to - checkpoint.block_number)
+    }
+
+    /// Units of an ERC-721 token `owner` holds, if the index saw the token move and it
+    /// did not move again in the blocks mined since the index's checkpoint
+    pub async fn nft_balance(
+        &self,
+        client: &ArkClient,
+        nft: &NftRef,
+        owner: &str,
+    ) -> Result<Option<u64>, ArkError> {
+        if nft.standard != TokenStandard::Erc721 {
+            return Ok(None);
+        }
+        let Some(index) = self.indexes.get(&nft.chain) else {
+            return Ok(None);
+        };
+        let (owned, indexed_to) = {
+            let index = index.lock().unwrap();
+            let (Some(indexed), Some(checkpoint)) =
+                (index.owners.get(&nft.token_key()), &index.checkpoint)
+            else {
+                return Ok(None);
+            };
+            (
+                indexed.owner.eq_ignore_ascii_case(owner),
+                checkpoint.block_number,
+            )
+        };
+
+        let head = client.get_block_number().await?;
+        let moved = client
+            .get_logs(indexed_to + 1, head)
+            .await?
+            .iter()
+            .any(|entry| match &entry.log {
+                TransactionLog::NftTransfer { nft: moved, .. } => {
+                    moved.token_key() == nft.token_key()
+                }
+                TransactionLog::TokenTransfer { .. } => false,
+            });
+        Ok((!moved).then_some(u64::from(owned)))
+    }
+
+    /// Base units of a token `owner` holds, if its balance was read from the chain
+    /// and neither the index nor the blocks mined since saw it change
+    pub async fn token_balance(
+        &self,
+        client: &ArkClient,
+        token: &TokenInfo,
+        owner: &str,
+    ) -> Result<Option<u128>, ArkError> {
+        let Some(index) = self.indexes.get(&client.chain_config().name) else {
+            return Ok(None);
+        };
+        let (indexed, indexed_to) = {
+            let index = index.lock().unwrap();
+            let (Some(indexed), Some(checkpoint)) = (
+                index
+                    .balances
+                    .get(&owner.to_lowercase())
+                    .and_then(|balances| balances.get(&token.symbol)),
+                &index.checkpoint,
+            ) else {
+                return Ok(None);
+            };
+            (indexed.clone(), checkpoint.block_number)
+        };
+
+        // The block the balance was read at may have been reorged out since
+        if client.get_block_hash(indexed.block_number).await? != indexed.block_hash {
+            return Ok(None);
+        }
+        let head = client.get_block_number().await?;
+        let changed = client
+            .get_logs(indexed.block_number.max(indexed_to) + 1, head)
+            .await?
+            .iter()
+            .any(|entry| match &entry.log {
+                TransactionLog::TokenTransfer {
+                    token: moved,
+                    from,
+                    to,
+                    ..
+                } => {
+                    moved.eq_ignore_ascii_case(&token.symbol)
+                        && (from.eq_ignore_ascii_case(owner) || to.eq_ignore_ascii_case(owner))
+                }
+                TransactionLog::NftTransfer { .. } => false,
+            });
+        Ok((!changed).then_some(indexed.units))
+    }
+
+    /// Keep a token balance read from the chain when its head was `block_number`;
+    /// only once the chain is being indexed, which is what keeps the balance current
+    pub async fn put_token_balance(
+        &self,
+        client: &ArkClient,
+        token: &TokenInfo,
+        owner: &str,
+        units: u128,
+        block_number: u64,
+    ) -> Result<(), ArkError> {
+        let Some(index) = self.indexes.get(&client.chain_config().name) else {
+            return Ok(());
+        };
+        if index.lock().unwrap().checkpoint.is_none() {
+            return Ok(());
+        }
+        let block_hash = client.get_block_hash(block_number).await?;
+        index
+            .lock()
+            .unwrap()
+            .balances
+            .entry(owner.to_lowercase())
+            .or_default()
+            .insert(
+                token.symbol.clone(),
+                IndexedBalance {
+                    units,
+                    block_number,
+                    block_hash,
+                },
+            );
+        Ok(())
+    }
+
+    /// Drop what the index knows of NFTs and token balances of `addresses` on
+    /// `chain` changed outside the chain's transfers
+    pub fn forget(&self, chain: &str, nfts: &[NftRef], addresses: &[&str]) {
+        let Some(index) = self.indexes.get(chain) else {
+            return;
+        };
+        let mut index = index.lock().unwrap();
+        for nft in nfts {
+            index.owners.remove(&nft.token_key());
+        }
+        for address in addresses {
+            index.balances.remove(&address.to_lowercase());
+        }
+    }
+
+    /// ERC-721 tokens the index last saw move to `address`
+    pub fn owned_nfts(&self, chain: &str, address: &str) -> Vec<IndexedOwner> {
+        let Some(index) = self.indexes.get(chain) else {
+            return Vec::new();
+        };
+        let mut owned: Vec<IndexedOwner> = index
+            .lock()
+            .unwrap()
+            .owners
+            .values()
+            .filter(|indexed| indexed.owner.eq_ignore_ascii_case(address))
+            .cloned()
+            .collect();
+        owned.sort_by_key(|indexed| indexed.block_number);
+        owned
+    }
+
+    /// Everything `address` received and sent on a chain since its index started
+    pub fn flows(&self, chain: &str, address: &str) -> Vec<AssetFlow> {
+        let Some(index) = self.indexes.get(chain) else {
+            return Vec::new();
+        };
+        let index = index.lock().unwrap();
+        let mut flows: Vec<AssetFlow> = index
+            .flows
+            .get(&address.to_lowercase())
+            .into_iter()
+            .flatten()
+            .map(|(asset, flow)| {
+                let scale = 10f64.powi(flow.decimals as i32);
+                AssetFlow {
+                    asset: asset.clone(),
+                    received: flow.received as f64 / scale,
+                    sent: flow.sent as f64 / scale,
+                    net: (flow.received as f64 - flow.sent as f64) / scale,
+                }
+            })
+            .collect();
+        flows.sort_by(|a, b| a.asset.cmp(&b.asset));
+        flows
+    }
+
+    /// Checkpoint and size of every chain's index
+    pub fn status(&self) -> Vec<IndexStatus> {
+        let mut status: Vec<IndexStatus> = self
+            .indexes
+            .iter()
+            .map(|(chain, index)| {
+                let index = index.lock().unwrap();
+                IndexStatus {
+                    chain: chain.clone(),
+                    checkpoint: index.checkpoint.clone(),
+                    nfts_indexed: index.owners.len(),
+                    addresses_indexed: index.flows.len(),
+                    transfers_indexed: index.transfers,
+                }
+            })
+            .collect();
+        status.sort_by(|a, b| a.chain.cmp(&b.chain));
+        status
+    }
+}
+
+fn index_path(dir: &Path, chain: &str) -> PathBuf {
+    dir.join(format!("{}.json", chain))
+}
+
+fn read_index(path: &Path) -> Result<ChainIndex, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ChainIndex::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Replace the index file in one step, so a crash leaves the previous one intact
+fn write_index(path: &Path, index: &ChainIndex) -> Result<(), ArkError> {
+    let failed =
+        |e: String| ArkError::StorageError(format!("Cannot save index {}: {}", path.display(), e));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| failed(e.to_string()))?;
+    }
+    let text = serde_json::to_string(index).map_err(|e| failed(e.to_string()))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, text)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| failed(e.to_string()))
+}
+
+/// Background job indexing every chain's new blocks every `interval`
+pub async fn run_indexer(state: actix_web::web::Data<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        for client in state.clients() {
+            if let Err(e) = state.indexer.sync(client).await {
+                log::error!("Failed to index {}: {}", client.chain_config().name, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ark_client::TxStatus;
+    use crate::chains::{ChainRegistry, DEFAULT_CHAIN};
+    use crate::config::Config;
+    use crate::simulator::{SimulatedChain, SimulatedTransaction};
+    use crate::tokens::USDC;
+    use std::sync::Arc;
+
+    const SELLER: &str = "0x00000000000000000000000000000000000a11ce";
+    const BUYER: &str = "0x00000000000000000000000000000000000B0B0B";
+
+    /// Sale of `nft` mined `depth` blocks below the head
+    fn sale(chain: &SimulatedChain, tx_hash: &str, nft: &NftRef, price: f64, depth: u64) {
+        chain.record_transaction(SimulatedTransaction {
+            tx_hash: tx_hash.to_string(),
+            block_number: chain.head_height() - depth,
+            status: TxStatus::Success,
+            gas_used: 240000,
+            effective_gas_price_gwei: 10,
+            value: Vec::new(),
+            logs: vec![
+                TransactionLog::NftTransfer {
+                    nft: nft.clone(),
+                    from: SELLER.to_string(),
+                    to: BUYER.to_string(),
+                },
+                TransactionLog::TokenTransfer {
+                    token: USDC.to_string(),
+                    from: BUYER.to_string(),
+                    to: SELLER.to_string(),
+                    amount: price,
+                },
+            ],
+            revert_reason: None,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_indexes_transfers_resumes_from_checkpoint_and_follows_reorgs() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_secs(60)));
+        let chain_config = ChainRegistry::default().get(DEFAULT_CHAIN).unwrap().clone();
+        let client =
+            ArkClient::with_simulator(chain_config, chain.clone(), &Config::default()).unwrap();
+        let dir = std::env::temp_dir().join(format!("ark-index-{:016x}", rand::random::<u64>()));
+        let config = IndexerConfig::default();
+        let chains = [client.chain_config().clone()];
+
+        let ape: NftRef = "BAYC#1".parse().unwrap();
+        let unlisted: NftRef = "PUNKS#2".parse().unwrap();
+        let confirmations = u64::from(client.chain_config().confirmations);
+        sale(&chain, "0xsale-1", &ape, 250.0, confirmations);
+        sale(&chain, "0xsale-2", &unlisted, 100.0, confirmations);
+        // Not deep enough to be indexed yet
+        sale(&chain, "0xsale-3", &unlisted, 50.0, 0);
+
+        let indexer = ChainIndexer::load(&chains, &config, &dir);
+        assert_eq!(indexer.sync(&client).await.unwrap(), 1);
+        assert_eq!(indexer.sync(&client).await.unwrap(), 0);
+        assert_eq!(
+            indexer.nft_balance(&client, &ape, BUYER).await.unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            indexer.nft_balance(&client, &ape, SELLER).await.unwrap(),
+            Some(0)
+        );
+        // Collections that are not configured are not indexed
+        assert_eq!(
+            indexer
+                .nft_balance(&client, &unlisted, BUYER)
+                .await
+                .unwrap(),
+            None
+        );
+        let flows = indexer.flows("ark", BUYER);
+        let usdc = flows.iter().find(|f| f.asset == USDC).unwrap();
+        assert_eq!((usdc.sent, usdc.net), (350.0, -350.0));
+        let bought = flows.iter().find(|f| f.asset == ape.token_key()).unwrap();
+        assert_eq!((bought.received, bought.net), (1.0, 1.0));
+
+        // A move in blocks the index has not reached yet is left to the chain
+        sale(&chain, "0xsale-4", &ape, 300.0, 0);
+        assert_eq!(
+            indexer.nft_balance(&client, &ape, BUYER).await.unwrap(),
+            None
+        );
+
+        // A token balance read from the chain is kept until something changes it
+        let usdc = client.tokens().get(USDC).unwrap();
+        let head = chain.head_height();
+        indexer
+            .put_token_balance(&client, usdc, BUYER, 900_000_000, head)
+            .await
+            .unwrap();
+        assert_eq!(
+            indexer.token_balance(&client, usdc, BUYER).await.unwrap(),
+            Some(900_000_000)
+        );
+        indexer.forget("ark", &[], &[BUYER]);
+        assert_eq!(
+            indexer.token_balance(&client, usdc, BUYER).await.unwrap(),
+            None
+        );
+        // The seller was paid in a block mined after this read
+        indexer
+            .put_token_balance(&client, usdc, SELLER, 900_000_000, head - 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            indexer.token_balance(&client, usdc, SELLER).await.unwrap(),
+            None
+        );
+
+        // A restart picks up the saved index
+        let restarted = ChainIndexer::load(&chains, &config, &dir);
+        assert_eq!(restarted.owned_nfts("ark", BUYER).len(), 1);
+        assert_eq!(restarted.status()[0].transfers_indexed, 3);
+
+        // The sales are reorged out, so the index is rebuilt without them
+        chain.reorg(confirmations + 1, false);
+        restarted.sync(&client).await.unwrap();
+        assert_eq!(
+            restarted.nft_balance(&client, &ape, BUYER).await.unwrap(),
+            None
+        );
+        assert!(restarted.flows("ark", BUYER).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,499 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ark_client::ArkError;
+use crate::tokens::{LEDGER_DECIMALS, USDC};
+
+/// Number of ledger units in one whole token, whatever its on-chain decimals
+pub const UNITS_PER_TOKEN: i64 = 10i64.pow(LEDGER_DECIMALS);
+
+/// Owner of the counter-account used for funds entering or leaving the ledger
+/// (deposits, off-chain credit lines, withdrawals back to the chain)
+pub const EXTERNAL_OWNER: &str = "external";
+
+/// Convert a token amount into integer ledger units
+pub fn to_units(amount: f64) -> Result<i64, ArkError> {
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(ArkError::LedgerError(format!(
+            "Invalid token amount: {}",
+            amount
+        )));
+    }
+    Ok((amount * UNITS_PER_TOKEN as f64).round() as i64)
+}
+
+/// Convert integer ledger units back into a token amount
+pub fn from_units(units: i64) -> f64 {
+    units as f64 / UNITS_PER_TOKEN as f64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountKind {
+    /// Spendable funds
+    Available,
+    /// Funds locked by an in-flight escrow
+    Held,
+    /// Fees paid by the owner
+    Fees,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccountId {
+    pub owner: String,
+    pub kind: AccountKind,
+    /// Symbol of the token the account holds
+    pub token: String,
+}
+
+impl AccountId {
+    pub fn new(owner: &str, kind: AccountKind, token: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+            kind,
+            token: token.to_string(),
+        }
+    }
+
+    pub fn external(token: &str) -> Self {
+        Self::new(EXTERNAL_OWNER, AccountKind::Available, token)
+    }
+}
+
+/// A single leg of a journal entry. Positive amounts increase the account balance,
+/// negative amounts decrease it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Posting {
+    pub account: AccountId,
+    pub amount: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub reference: String,
+    pub memo: String,
+    pub postings: Vec<Posting>,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OwnerBalances {
+    pub available: i64,
+    pub held: i64,
+    pub fees: i64,
+}
+
+/// Internal double-entry ledger.
+///
+/// Balances are never stored directly; they are derived by summing the postings of
+/// every journal entry, and every entry must sum to zero in each token.
+pub struct Ledger {
+    entries: Mutex<Vec<JournalEntry>>,
+    /// Journal file entries are appended to, one JSON line each; none keeps the
+    /// journal in memory only
+    path: Option<PathBuf>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(Vec::new()),
+            path: None,
+        }
+    }
+
+    /// Ledger whose journal is appended to `path`, replayed from it where present
+    pub fn load(path: &Path) -> Result<Self, ArkError> {
+        let failed =
+            |e: String| ArkError::StorageError(format!("Cannot read {}: {}", path.display(), e));
+        let entries = match fs::read_to_string(path) {
+            Ok(text) => text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).map_err(|e| failed(e.to_string())))
+                .collect::<Result<_, _>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(failed(e.to_string())),
+        };
+        Ok(Self {
+            entries: Mutex::new(entries),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Append an entry to the journal file. An entry that is not on disk is not
+    /// posted, so balances never include anything a restart would lose.
+    fn append(&self, entry: &JournalEntry) -> Result<(), ArkError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let failed =
+            |e: String| ArkError::StorageError(format!("Cannot save {}: {}", path.display(), e));
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| failed(e.to_string()))?;
+        }
+        let mut line = serde_json::to_string(entry).map_err(|e| failed(e.to_string()))?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()).and_then(|_| file.sync_data()))
+            .map_err(|e| failed(e.to_string()))
+    }
+
+    /// Post a balanced journal entry
+    pub fn post(
+        &self,
+        reference: &str,
+        memo: &str,
+        postings: Vec<Posting>,
+    ) -> Result<JournalEntry, ArkError> {
+        if postings.len() < 2 {
+            return Err(ArkError::LedgerError(
+                "Journal entry needs at least two postings".to_string(),
+            ));
+        }
+
+        for token in postings.iter().map(|p| &p.account.token) {
+            let total: i64 = postings
+                .iter()
+                .filter(|p| &p.account.token == token)
+                .map(|p| p.amount)
+                .sum();
+            if total != 0 {
+                return Err(ArkError::LedgerError(format!(
+                    "Journal entry is unbalanced by {} {} units",
+                    total, token
+                )));
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let entry = JournalEntry {
+            id: entries.len() as u64 + 1,
+            reference: reference.to_string(),
+            memo: memo.to_string(),
+            postings,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.append(&entry)?;
+
+        log::debug!(
+            "Ledger entry #{} posted ({}): {}",
+            entry.id,
+            entry.reference,
+            entry.memo
+        );
+
+        entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    /// Move `amount` units from one account to another as a single two-legged entry
+    pub fn transfer(
+        &self,
+        reference: &str,
+        memo: &str,
+        from: AccountId,
+        to: AccountId,
+        amount: i64,
+    ) -> Result<JournalEntry, ArkError> {
+        if amount <= 0 {
+            return Err(ArkError::LedgerError(format!(
+                "Transfer amount must be positive, got {}",
+                amount
+            )));
+        }
+
+        self.post(
+            reference,
+            memo,
+            vec![
+                Posting {
+                    account: from,
+                    amount: -amount,
+                },
+                Posting {
+                    account: to,
+                    amount,
+                },
+            ],
+        )
+    }
+
+    /// Credit an owner's available balance from outside the ledger
+    pub fn deposit(
+        &self,
+        owner: &str,
+        token: &str,
+        amount: i64,
+        memo: &str,
+    ) -> Result<JournalEntry, ArkError> {
+        self.transfer(
+            &format!("deposit:{}", owner),
+            memo,
+            AccountId::external(token),
+            AccountId::new(owner, AccountKind::Available, token),
+            amount,
+        )
+    }
+
+    /// Lock the buyer's funds for an escrow before the transaction is submitted.
+    ///
+    /// The available balance may go negative here; that is the buyer drawing on
+    /// off-chain credit, which is reconciled against the chain later.
+    pub fn hold_escrow(
+        &self,
+        deal_id: &str,
+        buyer: &str,
+        token: &str,
+        amount: i64,
+    ) -> Result<JournalEntry, ArkError> {
+        self.transfer(
+            &format!("deal:{}", deal_id),
+            "escrow hold",
+            AccountId::new(buyer, AccountKind::Available, token),
+            AccountId::new(buyer, AccountKind::Held, token),
+            amount,
+        )
+    }
+
+    /// Pay held funds out to the seller once the escrow transaction succeeded
+    pub fn settle_escrow(
+        &self,
+        deal_id: &str,
+        buyer: &str,
+        seller: &str,
+        token: &str,
+        amount: i64,
+    ) -> Result<JournalEntry, ArkError> {
+        self.transfer(
+            &format!("deal:{}", deal_id),
+            "escrow settlement",
+            AccountId::new(buyer, AccountKind::Held, token),
+            AccountId::new(seller, AccountKind::Available, token),
+            amount,
+        )
+    }
+
+    /// Pay held funds out to several recipients (seller, treasury, creators) as one
+    /// entry once the escrow transaction succeeded
+    pub fn settle_escrow_payouts(
+        &self,
+        deal_id: &str,
+        buyer: &str,
+        token: &str,
+        payouts: &[(&str, i64)],
+    ) -> Result<JournalEntry, ArkError> {
+        if payouts.iter().any(|(_, amount)| *amount <= 0) {
+            return Err(ArkError::LedgerError(
+                "Payout amounts must be positive".to_string(),
+            ));
+        }
+
+        let mut postings = vec![Posting {
+            account: AccountId::new(buyer, AccountKind::Held, token),
+            amount: -payouts.iter().map(|(_, amount)| amount).sum::<i64>(),
+        }];
+        postings.extend(payouts.iter().map(|(recipient, amount)| Posting {
+            account: AccountId::new(recipient, AccountKind::Available, token),
+            amount: *amount,
+        }));
+        self.post(&format!("deal:{}", deal_id), "escrow settlement", postings)
+    }
+
+    /// Return held funds to the buyer when the escrow transaction failed
+    pub fn release_escrow(
+        &self,
+        deal_id: &str,
+        buyer: &str,
+        token: &str,
+        amount: i64,
+    ) -> Result<JournalEntry, ArkError> {
+        self.transfer(
+            &format!("deal:{}", deal_id),
+            "escrow release",
+            AccountId::new(buyer, AccountKind::Held, token),
+            AccountId::new(buyer, AccountKind::Available, token),
+            amount,
+        )
+    }
+
+    /// Charge a network fee paid on the owner's behalf against their available USDC
+    pub fn charge_fee(
+        &self,
+        reference: &str,
+        owner: &str,
+        amount: i64,
+    ) -> Result<JournalEntry, ArkError> {
+        self.transfer(
+            reference,
+            "network fee",
+            AccountId::new(owner, AccountKind::Available, USDC),
+            AccountId::new(owner, AccountKind::Fees, USDC),
+            amount,
+        )
+    }
+
+    /// Derive all account balances of an owner in one token from the journal
+    pub fn owner_balances(&self, owner: &str, token: &str) -> OwnerBalances {
+        let entries = self.entries.lock().unwrap();
+        let mut balances = OwnerBalances::default();
+
+        for posting in entries.iter().flat_map(|e| e.postings.iter()) {
+            if posting.account.owner != owner || posting.account.token != token {
+                continue;
+            }
+            match posting.account.kind {
+                AccountKind::Available => balances.available += posting.amount,
+                AccountKind::Held => balances.held += posting.amount,
+                AccountKind::Fees => balances.fees += posting.amount,
+            }
+        }
+
+        balances
+    }
+
+    /// All journal entries touching any account of `owner`, oldest first
+    pub fn entries_for_owner(&self, owner: &str) -> Vec<JournalEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.postings.iter().any(|p| p.account.owner == owner))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unbalanced_entry_rejected() {
+        let ledger = Ledger::new();
+        let result = ledger.post(
+            "test",
+            "unbalanced",
+            vec![
+                Posting {
+                    account: AccountId::new("alice", AccountKind::Available, USDC),
+                    amount: 100,
+                },
+                Posting {
+                    account: AccountId::external(USDC),
+                    amount: -50,
+                },
+            ],
+        );
+        assert!(result.is_err());
+        assert_eq!(ledger.owner_balances("alice", USDC).available, 0);
+
+        // Balanced in total, but not within each token
+        let result = ledger.post(
+            "test",
+            "cross-token",
+            vec![
+                Posting {
+                    account: AccountId::new("alice", AccountKind::Available, USDC),
+                    amount: 100,
+                },
+                Posting {
+                    account: AccountId::external("DAI"),
+                    amount: -100,
+                },
+            ],
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_escrow_settlement_flow() {
+        let ledger = Ledger::new();
+        let price = to_units(250.5).unwrap();
+
+        ledger
+            .deposit("buyer", USDC, to_units(1000.0).unwrap(), "credit line")
+            .unwrap();
+        ledger.hold_escrow("deal-1", "buyer", USDC, price).unwrap();
+
+        let buyer = ledger.owner_balances("buyer", USDC);
+        assert_eq!(buyer.available, to_units(749.5).unwrap());
+        assert_eq!(buyer.held, price);
+
+        ledger
+            .settle_escrow("deal-1", "buyer", "seller", USDC, price)
+            .unwrap();
+
+        let buyer = ledger.owner_balances("buyer", USDC);
+        assert_eq!(buyer.held, 0);
+        assert_eq!(ledger.owner_balances("seller", USDC).available, price);
+        assert_eq!(ledger.owner_balances("seller", "DAI").available, 0);
+        assert_eq!(ledger.entries_for_owner("buyer").len(), 3);
+
+        // A split settlement empties the hold into several accounts in one entry
+        ledger.hold_escrow("deal-3", "buyer", USDC, 1000).unwrap();
+        let entry = ledger
+            .settle_escrow_payouts("deal-3", "buyer", USDC, &[("seller", 975), ("treasury", 25)])
+            .unwrap();
+        assert_eq!(entry.postings.len(), 3);
+        assert_eq!(ledger.owner_balances("buyer", USDC).held, 0);
+        assert_eq!(ledger.owner_balances("treasury", USDC).available, 25);
+        assert!(ledger
+            .settle_escrow_payouts("deal-3", "buyer", USDC, &[("seller", 0)])
+            .is_err());
+    }
+
+    #[test]
+    fn test_escrow_release_restores_available() {
+        let ledger = Ledger::new();
+        ledger.deposit("buyer", "DAI", 500, "credit").unwrap();
+        ledger.hold_escrow("deal-2", "buyer", "DAI", 200).unwrap();
+        ledger.release_escrow("deal-2", "buyer", "DAI", 200).unwrap();
+
+        assert_eq!(
+            ledger.owner_balances("buyer", "DAI"),
+            OwnerBalances {
+                available: 500,
+                held: 0,
+                fees: 0
+            }
+        );
+        // External counter-account mirrors the deposit
+        assert_eq!(ledger.owner_balances(EXTERNAL_OWNER, "DAI").available, -500);
+    }
+
+    #[test]
+    fn test_journal_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("ark-ledger-{:016x}", rand::random::<u64>()));
+        let path = dir.join("ledger.jsonl");
+        let ledger = Ledger::load(&path).unwrap();
+        ledger.deposit("buyer", USDC, 1000, "credit").unwrap();
+        ledger.hold_escrow("deal-1", "buyer", USDC, 400).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        let restarted = Ledger::load(&path).unwrap();
+        assert_eq!(restarted.owner_balances("buyer", USDC).available, 600);
+        assert_eq!(restarted.owner_balances("buyer", USDC).held, 400);
+
+        // New entries carry on the numbering and are appended after the old ones
+        let entry = restarted
+            .settle_escrow("deal-1", "buyer", "seller", USDC, 400)
+            .unwrap();
+        assert_eq!(entry.id, 3);
+        assert_eq!(Ledger::load(&path).unwrap().owner_balances("seller", USDC).available, 400);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
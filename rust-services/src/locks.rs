@@ -0,0 +1,212 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ark_client::ArkError;
+use crate::config::LocksConfig;
+use crate::nft::NftRef;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockStage {
+    /// Verifiers are voting on the deal
+    Consensus,
+    /// The deal's escrow or swap is being submitted
+    Escrow,
+}
+
+/// Exclusive claim of one deal on an NFT
+#[derive(Serialize, Debug, Clone)]
+pub struct NftLock {
+    pub nft: NftRef,
+    pub deal_id: String,
+    pub stage: LockStage,
+    pub locked_at: i64,
+    pub expires_at: i64,
+}
+
+/// Locks that keep two deals from trading the same NFT at once.
+///
+/// An NFT is locked for a deal from consensus until its escrow completes or
+/// fails; a deal that never reaches escrow loses its locks when they expire.
+/// Editions are locked as a whole, whatever quantity the deal trades.
+pub struct NftLocks {
+    /// Locks by `NftRef::token_key`
+    locks: Mutex<HashMap<String, NftLock>>,
+    ttl: Duration,
+}
+
+impl NftLocks {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            locks: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Locks expire `locks.ttl_secs` after the deal last took them
+    pub fn from_config(config: &LocksConfig) -> Self {
+        Self::new(config.ttl())
+    }
+
+    /// Lock every NFT of a deal, or none of them if another deal holds one.
+    ///
+    /// Locks the deal already holds are moved to `stage` and their expiry renewed.
+    pub fn acquire(
+        &self,
+        deal_id: &str,
+        nfts: &[NftRef],
+        stage: LockStage,
+    ) -> Result<Vec<NftLock>, ArkError> {
+        let now = chrono::Utc::now().timestamp();
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| lock.expires_at > now);
+
+        if let Some(lock) = nfts
+            .iter()
+            .filter_map(|nft| locks.get(&nft.token_key()))
+            .find(|lock| lock.deal_id != deal_id)
+        {
+            return Err(ArkError::NftLocked {
+                nft: lock.nft.to_string(),
+                deal_id: lock.deal_id.clone(),
+            });
+        }
+
+        let acquired: Vec<NftLock> = nfts
+            .iter()
+            .map(|nft| NftLock {
+                nft: nft.clone(),
+                deal_id: deal_id.to_string(),
+                stage,
+                locked_at: locks
+                    .get(&nft.token_key())
+                    .map_or(now, |lock| lock.locked_at),
+                expires_at: now + self.ttl.as_secs() as i64,
+            })
+            .collect();
+        for lock in &acquired {
+            locks.insert(lock.nft.token_key(), lock.clone());
+        }
+        Ok(acquired)
+    }
+
+    /// Lock currently held on an NFT
+    pub fn get(&self, nft: &NftRef) -> Option<NftLock> {
+        let now = chrono::Utc::now().timestamp();
+        self.locks
+            .lock()
+            .unwrap()
+            .get(&nft.token_key())
+            .filter(|lock| lock.expires_at > now)
+            .cloned()
+    }
+
+    /// Locks currently held by a deal
+    pub fn deal_locks(&self, deal_id: &str) -> Vec<NftLock> {
+        let now = chrono::Utc::now().timestamp();
+        self.locks
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|lock| lock.deal_id == deal_id && lock.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// Keep the deal's current locks until they are released, however long that takes,
+    /// e.g. while its transaction may still be mined
+    pub fn pin(&self, deal_id: &str) -> Vec<NftLock> {
+        let now = chrono::Utc::now().timestamp();
+        self.locks
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter(|lock| lock.deal_id == deal_id && lock.expires_at > now)
+            .map(|lock| {
+                lock.expires_at = i64::MAX;
+                lock.clone()
+            })
+            .collect()
+    }
+
+    /// Unlock every NFT of a deal, returning the locks it held
+    pub fn release(&self, deal_id: &str) -> Vec<NftLock> {
+        let mut released = Vec::new();
+        self.locks.lock().unwrap().retain(|_, lock| {
+            if lock.deal_id == deal_id {
+                released.push(lock.clone());
+                false
+            } else {
+                true
+            }
+        });
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deals_cannot_lock_the_same_nft() {
+        let locks = NftLocks::new(Duration::from_secs(60));
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap(), "BAYC#2".parse().unwrap()];
+
+        locks
+            .acquire("deal-1", &nfts[..1], LockStage::Consensus)
+            .unwrap();
+        // Retaking its own lock moves the deal on to escrow
+        let renewed = locks
+            .acquire("deal-1", &nfts[..1], LockStage::Escrow)
+            .unwrap();
+        assert_eq!(renewed[0].stage, LockStage::Escrow);
+
+        // A bundle overlapping a locked NFT locks nothing
+        let conflict = locks.acquire(
+            "deal-2",
+            &[nfts[1].clone(), "ark:bayc#1".parse().unwrap()],
+            LockStage::Consensus,
+        );
+        assert!(matches!(
+            conflict,
+            Err(ArkError::NftLocked { ref deal_id, .. }) if deal_id == "deal-1"
+        ));
+        assert!(locks.get(&nfts[1]).is_none());
+
+        assert_eq!(locks.release("deal-1").len(), 1);
+        locks
+            .acquire("deal-2", &nfts, LockStage::Consensus)
+            .unwrap();
+        assert_eq!(locks.deal_locks("deal-2").len(), 2);
+    }
+
+    #[test]
+    fn test_expired_locks_are_free() {
+        let locks = NftLocks::new(Duration::ZERO);
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap()];
+
+        locks
+            .acquire("deal-1", &nfts, LockStage::Consensus)
+            .unwrap();
+        assert!(locks.get(&nfts[0]).is_none());
+        assert!(locks.acquire("deal-2", &nfts, LockStage::Consensus).is_ok());
+    }
+
+    #[test]
+    fn test_pinned_locks_outlive_their_ttl() {
+        let locks = NftLocks::new(Duration::from_secs(1));
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap()];
+
+        locks.acquire("deal-1", &nfts, LockStage::Escrow).unwrap();
+        assert_eq!(locks.pin("deal-1").len(), 1);
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(locks.get(&nfts[0]).unwrap().deal_id, "deal-1");
+        assert!(locks.acquire("deal-2", &nfts, LockStage::Consensus).is_err());
+
+        locks.release("deal-1");
+        assert!(locks.acquire("deal-2", &nfts, LockStage::Consensus).is_ok());
+    }
+}
@@ -1,31 +1,148 @@
 use actix_web::{web, App, HttpServer};
+use std::fs;
 use std::io;
 
 mod ark_client;
+mod cache;
+mod chains;
+mod config;
+mod deals;
+mod fees;
 mod handlers;
+mod holds;
+mod indexer;
+mod ledger;
+mod locks;
+mod mandates;
 mod models;
+mod nft;
+mod policy;
+mod reconciliation;
+mod records;
+mod settlement;
+mod simulator;
+mod state;
+mod tokens;
+mod transactions;
+mod wallet;
 
 use handlers::{
-    execute_escrow, health_check, query_nft_ownership, query_usdc_balance, run_consensus,
-    verify_signature,
+    address_history, cache_stats, cancel_transaction, deal_locks, deal_timeline,
+    delete_spending_policy, execute_escrow, execute_swap, get_hold, get_mandate,
+    get_spending_policy, health_check, indexed_address, indexer_status, ledger_balances,
+    ledger_deposit, ledger_entries, list_chains, list_tokens, pending_transactions, place_hold,
+    query_nft_balance, query_nft_ownership, query_nft_ownership_batch, query_token_balance,
+    query_token_balance_batch, reconciliation_report, register_mandate, release_deal_locks,
+    release_hold, revoke_mandate, run_consensus, run_reconciliation, set_spending_policy,
+    simulate_base_fee, simulate_nft_balance, simulate_reorg, simulate_token_balance,
+    speed_up_transaction,
+    transaction_status, verify_signature, wallet_portfolio,
 };
+use config::Config;
+use state::AppState;
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    log::info!("Starting Agentic Payments Rust Service on 0.0.0.0:8080");
+    let config_error = |e: ark_client::ArkError| {
+        log::error!("Failed to load configuration: {}", e);
+        io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+    };
+    let config = Config::load().map_err(config_error)?;
+    let bind_address = config.server.bind_address.clone();
+    let tls = config
+        .server
+        .tls
+        .as_ref()
+        .map(|tls| tls.server_config())
+        .transpose()
+        .map_err(config_error)?;
+    let simulator_endpoints = config.simulation.control_endpoints;
+    fs::create_dir_all(&config.storage.data_dir)?;
 
-    HttpServer::new(|| {
+    log::info!(
+        "Starting Agentic Payments Rust Service on {}://{}",
+        if tls.is_some() { "https" } else { "http" },
+        bind_address
+    );
+
+    let indexer_interval = config.indexer.enabled.then(|| config.indexer.poll_interval());
+    let reconciliation_interval = config.reconciliation.interval();
+    let stuck_tx_check_interval = config.transactions.check_interval();
+    let state = web::Data::new(AppState::new(config).map_err(config_error)?);
+
+    if let Some(interval) = indexer_interval {
+        actix_web::rt::spawn(indexer::run_indexer(state.clone(), interval));
+    }
+    if let Some(interval) = reconciliation_interval {
+        actix_web::rt::spawn(reconciliation::run_periodically(state.clone(), interval));
+    }
+    if let Some(interval) = stuck_tx_check_interval {
+        actix_web::rt::spawn(transactions::run_stuck_transaction_monitor(
+            state.clone(),
+            interval,
+        ));
+    }
+
+    let server = HttpServer::new(move || {
         App::new()
+            .app_data(state.clone())
             .route("/health", web::get().to(health_check))
             .route("/verify-signature", web::post().to(verify_signature))
             .route("/run-consensus", web::post().to(run_consensus))
             .route("/execute-escrow", web::post().to(execute_escrow))
+            .route("/execute-swap", web::post().to(execute_swap))
             .route("/query-nft-ownership", web::post().to(query_nft_ownership))
-            .route("/query-usdc-balance", web::post().to(query_usdc_balance))
-    })
-    .bind("0.0.0.0:8080")?
-    .run()
-    .await
+            .route("/query-nft-ownership/batch", web::post().to(query_nft_ownership_batch))
+            .route("/query-nft-balance", web::post().to(query_nft_balance))
+            .route("/query-usdc-balance", web::post().to(query_token_balance))
+            .route("/query-token-balance", web::post().to(query_token_balance))
+            .route("/query-usdc-balance/batch", web::post().to(query_token_balance_batch))
+            .route("/query-token-balance/batch", web::post().to(query_token_balance_batch))
+            .route("/tokens", web::get().to(list_tokens))
+            .route("/chains", web::get().to(list_chains))
+            .route("/portfolio/{address}", web::get().to(wallet_portfolio))
+            .route("/cache/stats", web::get().to(cache_stats))
+            .route("/indexer/status", web::get().to(indexer_status))
+            .route("/indexer/addresses/{address}", web::get().to(indexed_address))
+            .route("/history/{address}", web::get().to(address_history))
+            .route("/deals/{deal_id}/timeline", web::get().to(deal_timeline))
+            .route("/policies/{kind}/{id}", web::get().to(get_spending_policy))
+            .route("/policies/{kind}/{id}", web::put().to(set_spending_policy))
+            .route("/policies/{kind}/{id}", web::delete().to(delete_spending_policy))
+            .route("/mandates", web::post().to(register_mandate))
+            .route("/mandates/{mandate_id}", web::get().to(get_mandate))
+            .route("/mandates/{mandate_id}/revoke", web::post().to(revoke_mandate))
+            .route("/holds", web::post().to(place_hold))
+            .route("/holds/{deal_id}", web::get().to(get_hold))
+            .route("/holds/{deal_id}/release", web::post().to(release_hold))
+            .route("/locks/{deal_id}", web::get().to(deal_locks))
+            .route("/locks/{deal_id}/release", web::post().to(release_deal_locks))
+            .route("/ledger/balances/{owner}", web::get().to(ledger_balances))
+            .route("/ledger/entries/{owner}", web::get().to(ledger_entries))
+            .route("/reconciliation/report", web::get().to(reconciliation_report))
+            .route("/reconciliation/run", web::post().to(run_reconciliation))
+            .route("/transactions/pending", web::get().to(pending_transactions))
+            .route("/transactions/{tx_hash}", web::get().to(transaction_status))
+            .route("/transactions/{tx_hash}/speed-up", web::post().to(speed_up_transaction))
+            .route("/transactions/{tx_hash}/cancel", web::post().to(cancel_transaction))
+            .configure(|cfg| {
+                if simulator_endpoints {
+                    cfg.route("/ledger/deposit", web::post().to(ledger_deposit))
+                        .route("/simulator/reorg", web::post().to(simulate_reorg))
+                        .route("/simulator/base-fee", web::post().to(simulate_base_fee))
+                        .route("/simulator/nft-balance", web::post().to(simulate_nft_balance))
+                        .route(
+                            "/simulator/token-balance",
+                            web::post().to(simulate_token_balance),
+                        );
+                }
+            })
+    });
+    let server = match tls {
+        Some(tls) => server.bind_rustls_0_23(&bind_address, tls)?,
+        None => server.bind(&bind_address)?,
+    };
+    server.run().await
 }
@@ -0,0 +1,667 @@
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ark_client::ArkError;
+use crate::config::MandatesConfig;
+use crate::ledger::{from_units, to_units};
+use crate::nft::NftRef;
+use crate::records::{read_json, write_json};
+use crate::tokens::TokenAmount;
+use crate::wallet::address_from_public_key;
+
+/// What a wallet owner authorizes one agent to spend on their behalf
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mandate {
+    /// Hex Ed25519 key of the owner's wallet, which pays for every deal under the mandate
+    pub owner_public_key: String,
+    /// Hex Ed25519 key the agent signs its deals with
+    pub agent_public_key: String,
+    /// Total the agent may spend under the mandate
+    pub budget: TokenAmount,
+    /// Most the agent may pay in one deal, in the budget's token
+    pub max_price: f64,
+    /// Unix timestamp after which the mandate is void
+    pub expires_at: i64,
+    /// Collection slugs or contract addresses the agent may buy from; any when empty
+    #[serde(default)]
+    pub allowed_collections: Vec<String>,
+    /// Lets an owner issue several otherwise identical mandates
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+impl Mandate {
+    /// Canonical encoding, one field per line in a fixed order. Amounts are in ledger
+    /// units and the collections are lowercased and sorted, so that every client
+    /// encodes a mandate alike.
+    pub fn message(&self) -> Result<String, ArkError> {
+        let units = |amount: f64| {
+            to_units(amount).map_err(|_| {
+                ArkError::InvalidMandate(format!("{} is not a valid amount", amount))
+            })
+        };
+        let mut collections: Vec<String> = self
+            .allowed_collections
+            .iter()
+            .map(|c| c.to_lowercase())
+            .collect();
+        collections.sort();
+        let token = self.budget.token.to_uppercase();
+        Ok(format!(
+            "spending mandate\nowner {}\nagent {}\nbudget {} {}\nmax_price {} {}\nexpires_at {}\ncollections {}\nnonce {}",
+            self.owner_public_key.to_lowercase(),
+            self.agent_public_key.to_lowercase(),
+            units(self.budget.amount)?,
+            token,
+            units(self.max_price)?,
+            token,
+            self.expires_at,
+            collections.join(","),
+            self.nonce
+        ))
+    }
+
+    /// Hash of the canonical encoding; this is what the owner signs and the mandate id
+    pub fn hash(&self) -> Result<[u8; 32], ArkError> {
+        Ok(Sha256::digest(self.message()?.as_bytes()).into())
+    }
+
+    pub fn id(&self) -> Result<String, ArkError> {
+        Ok(format!("0x{}", hex::encode(self.hash()?)))
+    }
+}
+
+/// A mandate with the owner's signature over its hash
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedMandate {
+    pub mandate: Mandate,
+    pub signature: String,
+}
+
+impl SignedMandate {
+    /// Check the owner's signature and the limits; returns the owner's address
+    pub fn verify(&self) -> Result<String, ArkError> {
+        let mandate = &self.mandate;
+        let owner = verify_signature(&mandate.owner_public_key, &mandate.hash()?, &self.signature)
+            .map_err(|e| ArkError::InvalidMandate(format!("owner signature: {}", e)))?;
+        parse_public_key(&mandate.agent_public_key)
+            .map_err(|e| ArkError::InvalidMandate(format!("agent key: {}", e)))?;
+
+        let positive = |amount: f64| amount.is_finite() && amount > 0.0;
+        if !positive(mandate.budget.amount) || !positive(mandate.max_price) {
+            return Err(ArkError::InvalidMandate(
+                "budget and max price must be positive".to_string(),
+            ));
+        }
+        if mandate.expires_at <= chrono::Utc::now().timestamp() {
+            return Err(ArkError::InvalidMandate(
+                "mandate has already expired".to_string(),
+            ));
+        }
+        Ok(address_from_public_key(&owner))
+    }
+}
+
+/// Message an owner signs to revoke a mandate
+pub fn revocation_message(mandate_id: &str) -> String {
+    format!("revoke mandate {}", mandate_id)
+}
+
+/// Message an agent signs to spend under a mandate in a deal: one line each for the
+/// deal, the mandate and the seller, then one per NFT bought and one per amount paid.
+/// Amounts are in ledger units (millionths of a token) and NFTs and amounts are
+/// sorted, so that every client encodes the deal alike.
+pub fn deal_message(
+    mandate_id: &str,
+    deal_id: &str,
+    seller: &str,
+    nfts: &[NftRef],
+    amounts: &[TokenAmount],
+) -> Result<String, ArkError> {
+    let mut nft_lines: Vec<String> = nfts
+        .iter()
+        .map(|nft| format!("nft {} {}", nft.token_key(), nft.amount))
+        .collect();
+    nft_lines.sort();
+    let mut amount_lines = amounts
+        .iter()
+        .map(|amount| {
+            Ok(format!(
+                "pay {} {}",
+                to_units(amount.amount)?,
+                amount.token.to_uppercase()
+            ))
+        })
+        .collect::<Result<Vec<String>, ArkError>>()?;
+    amount_lines.sort();
+
+    let mut lines = vec![
+        format!("deal {}", deal_id),
+        format!("mandate {}", mandate_id),
+        format!("seller {}", seller.to_lowercase()),
+    ];
+    lines.extend(nft_lines);
+    lines.extend(amount_lines);
+    Ok(lines.join("\n"))
+}
+
+fn parse_public_key(public_key: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = hex::decode(public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("malformed public key")?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("invalid public key: {}", e))
+}
+
+fn verify_signature(
+    public_key: &str,
+    message: &[u8],
+    signature: &str,
+) -> Result<VerifyingKey, String> {
+    let key = parse_public_key(public_key)?;
+    let signature: [u8; 64] = hex::decode(signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("malformed signature")?;
+    key.verify(message, &Signature::from_bytes(&signature))
+        .map_err(|_| "invalid signature".to_string())?;
+    Ok(key)
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MandateStatus {
+    Active,
+    Expired,
+    Revoked,
+}
+
+/// A registered mandate and how much of its budget is used
+#[derive(Serialize, Debug, Clone)]
+pub struct MandateView {
+    pub mandate_id: String,
+    pub owner_address: String,
+    pub status: MandateStatus,
+    pub mandate: Mandate,
+    /// Spent by deals that went through or are in flight
+    pub spent: f64,
+    pub remaining: f64,
+    pub revoked_at: Option<i64>,
+}
+
+/// An agent's claim to spend under a mandate in one deal
+pub struct MandateUse<'a> {
+    pub mandate_id: &'a str,
+    /// Agent's signature over `deal_message`
+    pub agent_signature: &'a str,
+    pub deal_id: &'a str,
+    /// Address paying for the deal, if already known; must be the owner's
+    pub buyer_address: Option<&'a str>,
+    /// Address the owner's funds go to in exchange for the NFTs
+    pub seller: &'a str,
+    /// Every amount the owner pays in the deal
+    pub amounts: &'a [TokenAmount],
+    pub nfts: &'a [NftRef],
+}
+
+/// A deal's commitment against a mandate's budget, which only the call that made it
+/// may release
+#[must_use = "a reservation must be released if its deal does not go through"]
+#[derive(Debug, PartialEq, Eq)]
+pub struct MandateReservation {
+    mandate_id: String,
+    id: u64,
+}
+
+/// Units a deal committed against a mandate, and the reservation that committed them
+#[derive(Serialize, Deserialize)]
+struct Commitment {
+    reservation: u64,
+    units: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MandateEntry {
+    signed: SignedMandate,
+    owner_address: String,
+    /// Units committed per deal
+    spent: HashMap<String, Commitment>,
+    revoked_at: Option<i64>,
+}
+
+impl MandateEntry {
+    fn view(&self, mandate_id: &str) -> MandateView {
+        let spent: i64 = self.spent.values().map(|c| c.units).sum();
+        let budget = to_units(self.signed.mandate.budget.amount).unwrap_or(0);
+        let status = if self.revoked_at.is_some() {
+            MandateStatus::Revoked
+        } else if self.signed.mandate.expires_at <= chrono::Utc::now().timestamp() {
+            MandateStatus::Expired
+        } else {
+            MandateStatus::Active
+        };
+        MandateView {
+            mandate_id: mandate_id.to_string(),
+            owner_address: self.owner_address.clone(),
+            status,
+            mandate: self.signed.mandate.clone(),
+            spent: from_units(spent),
+            remaining: from_units((budget - spent).max(0)),
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct MandateState {
+    entries: HashMap<String, MandateEntry>,
+    /// Ids of every mandate ever revoked, which can never be registered again
+    revoked: HashSet<String>,
+}
+
+/// Mandates registered with the service, by id, saved to their file after every
+/// change so a restart neither resets a budget nor lifts a revocation
+pub struct Mandates {
+    state: Mutex<MandateState>,
+    path: PathBuf,
+    /// Refuse escrows that are not made under a mandate
+    required: bool,
+}
+
+impl Mandates {
+    /// Mandates saved to `path`, restored from it where present
+    pub fn load(path: &Path, required: bool) -> Result<Self, ArkError> {
+        Ok(Self {
+            state: Mutex::new(read_json(path)?),
+            path: path.to_path_buf(),
+            required,
+        })
+    }
+
+    /// Mandates are optional unless `mandates.required` is set
+    pub fn from_config(config: &MandatesConfig, path: &Path) -> Result<Self, ArkError> {
+        Self::load(path, config.required)
+    }
+
+    fn save(&self, state: &MandateState) {
+        if let Err(e) = write_json(&self.path, state) {
+            log::error!("{}", e);
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Verify and store a mandate; registering it again returns the stored one, and a
+    /// revoked mandate cannot be registered again
+    pub fn register(&self, signed: SignedMandate) -> Result<MandateView, ArkError> {
+        let owner_address = signed.verify()?;
+        let mandate_id = signed.mandate.id()?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.revoked.contains(&mandate_id) {
+            return Err(ArkError::InvalidMandate(format!(
+                "mandate {} was revoked",
+                mandate_id
+            )));
+        }
+        if let Some(entry) = state.entries.get(&mandate_id) {
+            return Ok(entry.view(&mandate_id));
+        }
+        let entry = MandateEntry {
+            signed,
+            owner_address,
+            spent: HashMap::new(),
+            revoked_at: None,
+        };
+        let view = entry.view(&mandate_id);
+        state.entries.insert(mandate_id, entry);
+        self.save(&state);
+        Ok(view)
+    }
+
+    pub fn get(&self, mandate_id: &str) -> Option<MandateView> {
+        let state = self.state.lock().unwrap();
+        state.entries.get(mandate_id).map(|entry| entry.view(mandate_id))
+    }
+
+    /// Revoke a mandate with the owner's signature over `revocation_message`; takes
+    /// effect for every deal not yet submitted
+    pub fn revoke(&self, mandate_id: &str, signature: &str) -> Result<MandateView, ArkError> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state
+            .entries
+            .get_mut(mandate_id)
+            .ok_or_else(|| ArkError::InvalidMandate(format!("unknown mandate {}", mandate_id)))?;
+        verify_signature(
+            &entry.signed.mandate.owner_public_key,
+            revocation_message(mandate_id).as_bytes(),
+            signature,
+        )
+        .map_err(|e| ArkError::InvalidMandate(format!("owner signature: {}", e)))?;
+
+        entry
+            .revoked_at
+            .get_or_insert(chrono::Utc::now().timestamp());
+        let view = entry.view(mandate_id);
+        state.revoked.insert(mandate_id.to_string());
+        self.save(&state);
+        Ok(view)
+    }
+
+    /// Check that the agent may spend in the deal under the mandate, without
+    /// committing any budget
+    pub fn check(&self, spend: &MandateUse) -> Result<(), ArkError> {
+        let state = self.state.lock().unwrap();
+        authorize(&state.entries, spend).map(|_| ())
+    }
+
+    /// Check the deal and commit its amounts against the budget until its reservation
+    /// is released.
+    ///
+    /// A deal that already committed is refused, so resubmitting it can neither spend
+    /// the owner's funds again nor take over its commitment.
+    pub fn reserve(&self, spend: &MandateUse) -> Result<MandateReservation, ArkError> {
+        let mut state = self.state.lock().unwrap();
+        let committed = state
+            .entries
+            .get(spend.mandate_id)
+            .is_some_and(|entry| entry.spent.contains_key(spend.deal_id));
+        if committed {
+            return Err(ArkError::DealConflict(format!(
+                "deal {} is already committed against mandate {}",
+                spend.deal_id, spend.mandate_id
+            )));
+        }
+
+        let units = authorize(&state.entries, spend)?;
+        let reservation = MandateReservation {
+            mandate_id: spend.mandate_id.to_string(),
+            id: rand::random(),
+        };
+        if let Some(entry) = state.entries.get_mut(spend.mandate_id) {
+            entry.spent.insert(
+                spend.deal_id.to_string(),
+                Commitment {
+                    reservation: reservation.id,
+                    units,
+                },
+            );
+        }
+        self.save(&state);
+        Ok(reservation)
+    }
+
+    /// Return the budget a deal that did not go through had committed
+    pub fn release(&self, reservation: MandateReservation) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.get_mut(&reservation.mandate_id) {
+            entry.spent.retain(|_, c| c.reservation != reservation.id);
+        }
+        self.save(&state);
+    }
+}
+
+/// Every check a deal under a mandate must pass; returns the units it spends
+fn authorize(entries: &HashMap<String, MandateEntry>, spend: &MandateUse) -> Result<i64, ArkError> {
+    let rejected = |reason: String| {
+        Err(ArkError::InvalidMandate(format!(
+            "mandate {} does not cover deal {}: {}",
+            spend.mandate_id, spend.deal_id, reason
+        )))
+    };
+    let Some(entry) = entries.get(spend.mandate_id) else {
+        return rejected("mandate is not registered".to_string());
+    };
+    let mandate = &entry.signed.mandate;
+
+    if entry.revoked_at.is_some() {
+        return rejected("mandate was revoked".to_string());
+    }
+    if mandate.expires_at <= chrono::Utc::now().timestamp() {
+        return rejected("mandate has expired".to_string());
+    }
+
+    let message = deal_message(
+        spend.mandate_id,
+        spend.deal_id,
+        spend.seller,
+        spend.nfts,
+        spend.amounts,
+    )?;
+    if let Err(e) = verify_signature(
+        &mandate.agent_public_key,
+        message.as_bytes(),
+        spend.agent_signature,
+    ) {
+        return rejected(format!("agent signature: {}", e));
+    }
+
+    if let Some(buyer) = spend.buyer_address {
+        if !buyer.eq_ignore_ascii_case(&entry.owner_address) {
+            return rejected(format!("{} is not the owner's wallet", buyer));
+        }
+    }
+    let mut units = 0;
+    for amount in spend.amounts {
+        if !amount.token.eq_ignore_ascii_case(&mandate.budget.token) {
+            return rejected(format!(
+                "the budget is in {}, not {}",
+                mandate.budget.token, amount.token
+            ));
+        }
+        units += to_units(amount.amount)?;
+    }
+    let price = TokenAmount::new(&mandate.budget.token, from_units(units));
+    if units > to_units(mandate.max_price)? {
+        return rejected(format!(
+            "{} is above the max price of {} {}",
+            price, mandate.max_price, mandate.budget.token
+        ));
+    }
+    let spent: i64 = entry
+        .spent
+        .iter()
+        .filter(|(deal_id, _)| deal_id.as_str() != spend.deal_id)
+        .map(|(_, c)| c.units)
+        .sum();
+    if spent + units > to_units(mandate.budget.amount)? {
+        return rejected(format!(
+            "{} after {} spent exceeds the budget of {}",
+            price,
+            from_units(spent),
+            mandate.budget
+        ));
+    }
+    if !mandate.allowed_collections.is_empty() {
+        for nft in spend.nfts {
+            let allowed = mandate
+                .allowed_collections
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(&nft.collection));
+            if !allowed {
+                return rejected(format!("collection {} is not allowed", nft.collection));
+            }
+        }
+    }
+    Ok(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::USDC;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const SELLER: &str = "0x00000000000000000000000000000000000a11ce";
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&rand::random::<[u8; 32]>())
+    }
+
+    fn signed_mandate(owner: &SigningKey, agent: &SigningKey) -> SignedMandate {
+        let mandate = Mandate {
+            owner_public_key: hex::encode(owner.verifying_key().as_bytes()),
+            agent_public_key: hex::encode(agent.verifying_key().as_bytes()),
+            budget: TokenAmount::new(USDC, 1000.0),
+            max_price: 600.0,
+            expires_at: chrono::Utc::now().timestamp() + 3600,
+            allowed_collections: vec!["BAYC".to_string()],
+            nonce: 0,
+        };
+        SignedMandate {
+            signature: hex::encode(owner.sign(&mandate.hash().unwrap()).to_bytes()),
+            mandate,
+        }
+    }
+
+    #[test]
+    fn test_mandate_limits_agent_spending_until_revoked() {
+        let (owner, agent) = (key(), key());
+        let path = std::env::temp_dir()
+            .join(format!("ark-mandates-{:016x}", rand::random::<u64>()))
+            .join("mandates.json");
+        let mandates = Mandates::load(&path, true).unwrap();
+        let view = mandates.register(signed_mandate(&owner, &agent)).unwrap();
+        let id = view.mandate_id.clone();
+        assert_eq!(view.status, MandateStatus::Active);
+        assert_eq!(
+            view.owner_address,
+            address_from_public_key(&owner.verifying_key())
+        );
+
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap()];
+        let price = [TokenAmount::new(USDC, 500.0)];
+        let sign = |deal_id: &str, signer: &SigningKey| {
+            let message = deal_message(&id, deal_id, SELLER, &nfts, &price).unwrap();
+            hex::encode(signer.sign(message.as_bytes()).to_bytes())
+        };
+
+        let signature = sign("deal-1", &agent);
+        let use_1 = MandateUse {
+            mandate_id: &id,
+            agent_signature: &signature,
+            deal_id: "deal-1",
+            buyer_address: Some(&view.owner_address),
+            seller: SELLER,
+            amounts: &price,
+            nfts: &nfts,
+        };
+        let deal_1 = mandates.reserve(&use_1).unwrap();
+        // Signatures are bound to the deal and its seller, and only the agent's key counts
+        assert!(mandates
+            .check(&MandateUse {
+                seller: "0x000000000000000000000000000000000000beef",
+                ..use_1
+            })
+            .is_err());
+        let replayed = MandateUse {
+            deal_id: "deal-2",
+            ..use_1
+        };
+        assert!(mandates.check(&replayed).is_err());
+        let forged = sign("deal-2", &owner);
+        assert!(mandates
+            .check(&MandateUse {
+                agent_signature: &forged,
+                ..replayed
+            })
+            .is_err());
+
+        // A resubmitted deal is refused and keeps its commitment
+        assert!(matches!(
+            mandates.reserve(&use_1),
+            Err(ArkError::DealConflict(_))
+        ));
+        assert_eq!(mandates.get(&id).unwrap().spent, 500.0);
+
+        // A second deal of 500 would exceed the budget of 1000 only after the first
+        let signature = sign("deal-2", &agent);
+        let use_2 = MandateUse {
+            agent_signature: &signature,
+            ..replayed
+        };
+        let _deal_2 = mandates.reserve(&use_2).unwrap();
+        let signature = sign("deal-3", &agent);
+        let use_3 = MandateUse {
+            agent_signature: &signature,
+            deal_id: "deal-3",
+            ..use_2
+        };
+        assert!(mandates.check(&use_3).is_err());
+        mandates.release(deal_1);
+        assert!(mandates.check(&use_3).is_ok());
+
+        // Only the owner can revoke, and revocation applies at once
+        let bad = hex::encode(agent.sign(revocation_message(&id).as_bytes()).to_bytes());
+        assert!(mandates.revoke(&id, &bad).is_err());
+        let good = hex::encode(owner.sign(revocation_message(&id).as_bytes()).to_bytes());
+        assert_eq!(
+            mandates.revoke(&id, &good).unwrap().status,
+            MandateStatus::Revoked
+        );
+        assert!(mandates.check(&use_3).is_err());
+
+        // A restart keeps what was spent and the revocation, and a revoked mandate
+        // cannot be registered again
+        let restarted = Mandates::load(&path, true).unwrap();
+        let view = restarted.get(&id).unwrap();
+        assert_eq!(view.status, MandateStatus::Revoked);
+        assert_eq!(view.spent, 500.0);
+        assert!(restarted.check(&use_3).is_err());
+        assert!(matches!(
+            restarted.register(signed_mandate(&owner, &agent)),
+            Err(ArkError::InvalidMandate(_))
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_messages_are_canonical() {
+        let (owner, agent) = (key(), key());
+        let mandate = signed_mandate(&owner, &agent).mandate;
+        assert_eq!(
+            mandate.message().unwrap(),
+            format!(
+                "spending mandate\nowner {}\nagent {}\nbudget 1000000000 USDC\nmax_price 600000000 USDC\nexpires_at {}\ncollections bayc\nnonce 0",
+                mandate.owner_public_key, mandate.agent_public_key, mandate.expires_at
+            )
+        );
+
+        // Collections are compared without case and in any order
+        let reordered = Mandate {
+            allowed_collections: vec!["mayc".to_string(), "BAYC".to_string()],
+            ..mandate.clone()
+        };
+        let sorted = Mandate {
+            allowed_collections: vec!["bayc".to_string(), "MAYC".to_string()],
+            ..mandate
+        };
+        assert_eq!(reordered.id().unwrap(), sorted.id().unwrap());
+
+        let nfts: Vec<NftRef> = vec!["BAYC#2".parse().unwrap(), "ark:bayc#1".parse().unwrap()];
+        let amounts = [TokenAmount::new("dai", 1.5), TokenAmount::new(USDC, 2.0)];
+        assert_eq!(
+            deal_message("0xmandate", "deal-1", "0xSELLER", &nfts, &amounts).unwrap(),
+            "deal deal-1\nmandate 0xmandate\nseller 0xseller\nnft ark:bayc#1 1\nnft ark:bayc#2 1\npay 1500000 DAI\npay 2000000 USDC"
+        );
+    }
+
+    #[test]
+    fn test_rejects_tampered_and_expired_mandates() {
+        let (owner, agent) = (key(), key());
+        let mut signed = signed_mandate(&owner, &agent);
+        signed.mandate.budget.amount = 1_000_000.0;
+        assert!(signed.verify().is_err());
+
+        let mut signed = signed_mandate(&owner, &agent);
+        signed.mandate.expires_at = 0;
+        signed.signature = hex::encode(owner.sign(&signed.mandate.hash().unwrap()).to_bytes());
+        assert!(matches!(signed.verify(), Err(ArkError::InvalidMandate(_))));
+    }
+}
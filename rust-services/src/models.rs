@@ -1,5 +1,34 @@
 use serde::{Deserialize, Serialize};
 
+use crate::ark_client::{TransactionLog, TransactionReceipt, TxStatus};
+use crate::chains::DEFAULT_CHAIN;
+use crate::indexer::{AssetFlow, IndexStatus, IndexedOwner};
+use crate::ledger::JournalEntry;
+use crate::nft::{NftRef, TokenStandard};
+use crate::policy::{PolicySubject, PolicyViolation, SpendingPolicy};
+use crate::records::{EscrowRecord, TimelineEntry};
+use crate::settlement::Payout;
+use crate::tokens::{TokenAmount, TokenInfo, USDC};
+
+fn default_token() -> String {
+    USDC.to_string()
+}
+
+fn default_chain() -> String {
+    DEFAULT_CHAIN.to_string()
+}
+
+fn default_page_size() -> usize {
+    50
+}
+
+/// Chain a request targets, for endpoints addressed by path
+#[derive(Deserialize)]
+pub struct ChainQuery {
+    #[serde(default = "default_chain")]
+    pub chain: String,
+}
+
 // Health Check Response
 #[derive(Serialize)]
 pub struct HealthResponse {
@@ -14,6 +43,9 @@ pub struct VerifySignatureRequest {
     pub message: String,
     pub signature: String,
     pub public_key: String,
+    /// Deal the signature belongs to, to record the check in the deal's timeline
+    #[serde(default)]
+    pub deal_id: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -27,9 +59,41 @@ pub struct VerifySignatureResponse {
 #[derive(Deserialize)]
 pub struct ConsensusRequest {
     pub deal_id: String,
-    pub nft_ownership: bool,
+    /// Chain the deal runs on; every NFT must live there
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    #[serde(default)]
+    pub nft_standard: TokenStandard,
+    /// Units being sold, 1 unless the NFT is an ERC-1155 edition
+    pub nft_amount: Option<u64>,
+    /// Items of a bundle deal; replaces the single-NFT fields
+    #[serde(default)]
+    pub bundle: Vec<EscrowItem>,
     pub buyer_balance: f64,
+    /// Buyer wallet, whose funds held by other deals cannot pay for this one
+    pub buyer_address: Option<String>,
     pub signatures: Vec<String>,
+    #[serde(flatten)]
+    pub spender: Spender,
+    /// Price the buyer agent offers, checked against its spending limits
+    pub price: Option<f64>,
+    #[serde(default = "default_token")]
+    pub token: String,
+    /// Seller wallet, whose holdings of every NFT the verifiers check on chain
+    pub seller_address: String,
+    /// `[chain:]collection#token_id` of a single-NFT deal
+    pub nft_id: Option<String>,
+}
+
+/// Agent acting as the buyer of a deal, and the user who spawned it
+#[derive(Deserialize, Default)]
+pub struct Spender {
+    pub agent_id: Option<String>,
+    pub owner_id: Option<String>,
+    /// Mandate the buyer wallet's owner signed for the agent
+    pub mandate_id: Option<String>,
+    /// Agent's signature over the mandate's deal message
+    pub agent_signature: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -60,17 +124,77 @@ pub struct ConsensusResponse {
 #[derive(Deserialize)]
 pub struct EscrowRequest {
     pub deal_id: String,
+    /// Chain the escrow runs on; every NFT must live there
+    #[serde(default = "default_chain")]
+    pub chain: String,
     pub buyer_address: String,
     pub seller_address: String,
-    pub nft_id: String,
+    /// `[chain:]collection#token_id` of a single-NFT deal
+    pub nft_id: Option<String>,
+    #[serde(default)]
+    pub nft_standard: TokenStandard,
+    /// Units to transfer for ERC-1155 tokens
+    pub nft_amount: Option<u64>,
+    /// NFTs of a bundle deal, moved atomically against one payment (instead of `nft_id`)
+    #[serde(default)]
+    pub bundle: Vec<EscrowItem>,
     pub price: f64,
+    /// Symbol of the token the price is paid in
+    #[serde(default = "default_token")]
+    pub token: String,
+    #[serde(flatten)]
+    pub spender: Spender,
+}
+
+#[derive(Deserialize)]
+pub struct EscrowItem {
+    pub nft_id: String,
+    #[serde(default)]
+    pub nft_standard: TokenStandard,
+    pub nft_amount: Option<u64>,
+}
+
+// Swap Execution
+#[derive(Deserialize)]
+pub struct SwapRequest {
+    pub deal_id: String,
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    pub maker: SwapSideRequest,
+    pub taker: SwapSideRequest,
+}
+
+/// What one party hands over in a swap
+#[derive(Deserialize)]
+pub struct SwapSideRequest {
+    pub address: String,
+    #[serde(default)]
+    pub nfts: Vec<EscrowItem>,
+    #[serde(default)]
+    pub tokens: Vec<TokenAmount>,
+    /// Agent trading on this party's behalf, held to its spending limits
+    #[serde(flatten)]
+    pub spender: Spender,
 }
 
 #[derive(Serialize)]
 pub struct EscrowResponse {
     pub success: bool,
+    /// How the price was split between seller, treasury and creators
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub payouts: Vec<Payout>,
+    #[serde(flatten)]
+    pub receipt: TransactionReceipt,
+}
+
+/// Escrow or swap whose transaction was submitted but not confirmed in time; it is
+/// settled or rolled back once the transaction resolves
+#[derive(Serialize)]
+pub struct PendingDealResponse {
+    pub deal_id: String,
+    pub chain: String,
+    pub status: TxStatus,
     pub tx_hash: String,
-    pub block_number: u64,
 }
 
 // Error Response
@@ -80,30 +204,367 @@ pub struct ErrorResponse {
     pub message: String,
 }
 
+/// Rejection of a deal that breaks the buyer agent's or its owner's spending policy
+#[derive(Serialize)]
+pub struct PolicyViolationResponse {
+    pub error: String,
+    pub message: String,
+    pub violations: Vec<PolicyViolation>,
+}
+
+// Spending Policies
+#[derive(Serialize)]
+pub struct SpendingPolicyResponse {
+    pub subject: PolicySubject,
+    #[serde(flatten)]
+    pub policy: SpendingPolicy,
+    /// Amount of the policy's token reserved by today's deals
+    pub spent_today: f64,
+}
+
+// Spending Mandates
+#[derive(Deserialize)]
+pub struct RevokeMandateRequest {
+    /// Owner's signature over `revoke mandate <mandate_id>`
+    pub signature: String,
+}
+
 // ARK Network NFT Ownership Query
 #[derive(Deserialize)]
 pub struct NftOwnershipRequest {
-    pub collection: String,
-    pub token_id: String,
+    #[serde(flatten)]
+    pub nft: NftRef,
     pub owner_address: String,
 }
 
 #[derive(Serialize)]
 pub struct NftOwnershipResponse {
     pub owned: bool,
-    pub collection: String,
-    pub token_id: String,
+    #[serde(flatten)]
+    pub nft: NftRef,
+    pub owner: String,
+    /// Deal currently holding the NFT's lock, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_by: Option<String>,
+    /// Answered from the query cache rather than the chain
+    pub cached: bool,
+    pub source: BalanceSource,
+}
+
+/// Where an NFT or token balance was answered from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceSource {
+    Chain,
+    /// The query cache, as of when the chain last answered
+    Cache,
+    /// The chain index, as of its last confirmed block and checked against newer blocks
+    Index,
+}
+
+// ARK Network NFT Balance Query
+#[derive(Serialize)]
+pub struct NftBalanceResponse {
+    #[serde(flatten)]
+    pub nft: NftRef,
     pub owner: String,
+    pub balance: u64,
+    pub cached: bool,
+    pub source: BalanceSource,
 }
 
-// ARK Network USDC Balance Query
+// ARK Network Token Balance Query
 #[derive(Deserialize)]
 pub struct BalanceRequest {
     pub address: String,
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    #[serde(default = "default_token")]
+    pub token: String,
 }
 
 #[derive(Serialize)]
 pub struct BalanceResponse {
     pub address: String,
+    pub chain: String,
+    pub token: String,
+    /// On-chain balance minus the funds held for pending deals
+    pub balance: f64,
+    pub held: f64,
+    /// The on-chain balance came from the query cache rather than the chain
+    pub cached: bool,
+    pub source: BalanceSource,
+}
+
+// Batch Queries
+#[derive(Deserialize)]
+pub struct BatchRequest<T> {
+    pub items: Vec<T>,
+}
+
+/// Outcome of one item of a batch, at its position in the request
+#[derive(Serialize)]
+pub struct BatchItemResult<T> {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse<T> {
+    pub results: Vec<BatchItemResult<T>>,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+// Wallet Portfolio
+#[derive(Deserialize)]
+pub struct PortfolioQuery {
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    /// NFTs to skip, for the pages after the first
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_page_size")]
+    pub limit: usize,
+}
+
+#[derive(Serialize)]
+pub struct PortfolioNft {
+    /// The NFT, with the units held as its amount
+    #[serde(flatten)]
+    pub nft: NftRef,
+    /// Deal currently holding the NFT's lock, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_by: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PortfolioToken {
+    pub token: String,
+    /// On-chain balance minus the funds held for pending deals
+    pub balance: f64,
+    pub held: f64,
+}
+
+#[derive(Serialize)]
+pub struct PortfolioResponse {
+    pub address: String,
+    pub chain: String,
+    /// Collections whose NFTs are listed
+    pub collections: Vec<String>,
+    /// One page of the NFTs, by collection and token id
+    pub nfts: Vec<PortfolioNft>,
+    pub total_nfts: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// Offset of the next page, if there is one
+    pub next_offset: Option<usize>,
+    /// Balance of every token registered on the chain
+    pub tokens: Vec<PortfolioToken>,
+}
+
+// Chain Index
+#[derive(Serialize)]
+pub struct IndexerStatusResponse {
+    pub chains: Vec<IndexStatus>,
+}
+
+#[derive(Serialize)]
+pub struct IndexedAddressResponse {
+    pub address: String,
+    pub chain: String,
+    /// ERC-721 tokens whose last indexed transfer went to the address
+    pub nfts: Vec<IndexedOwner>,
+    /// Tokens and NFTs received and sent since the chain's index started
+    pub flows: Vec<AssetFlow>,
+}
+
+// Transaction History
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Only deals on this chain; every chain when absent
+    pub chain: Option<String>,
+    /// Deals to skip, for the pages after the first
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_page_size")]
+    pub limit: usize,
+}
+
+/// One deal as seen from an address of the history
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub deal_id: String,
+    pub chain: String,
+    pub tx_hash: Option<String>,
+    pub block_number: u64,
+    pub status: TxStatus,
+    /// Why the transaction returned no receipt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    /// Other party of the deal, unless the address was only paid by it
+    pub counterparty: Option<String>,
+    /// NFTs and tokens the address sent in the deal
+    pub sent: Vec<TransactionLog>,
+    /// NFTs and tokens the address received in the deal
+    pub received: Vec<TransactionLog>,
+    pub recorded_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub address: String,
+    /// One page of the deals, most recent first
+    pub deals: Vec<HistoryEntry>,
+    pub total_deals: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// Offset of the next page, if there is one
+    pub next_offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct DealTimelineResponse {
+    pub deal_id: String,
+    /// Steps of the deal in the order they happened
+    pub events: Vec<TimelineEntry>,
+    /// What was recorded of the deal's escrow or swap, once it was mined
+    pub record: Option<EscrowRecord>,
+}
+
+// Fund Holds
+#[derive(Deserialize)]
+pub struct PlaceHoldRequest {
+    pub deal_id: String,
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    pub address: String,
+    #[serde(default = "default_token")]
+    pub token: String,
+    pub amount: f64,
+    /// Seconds until the hold expires if not captured; the service default otherwise
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct TokensResponse {
+    pub chain: String,
+    pub tokens: Vec<TokenInfo>,
+}
+
+// Chain Registry
+#[derive(Serialize)]
+pub struct ChainView {
+    pub name: String,
+    pub chain_id: u64,
+    pub confirmations: u32,
+    pub block_time_ms: u64,
+    pub escrow_contract: String,
+    pub tokens: Vec<TokenInfo>,
+    pub collections: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ChainsResponse {
+    pub chains: Vec<ChainView>,
+}
+
+// Internal Ledger
+#[derive(Deserialize)]
+pub struct LedgerDepositRequest {
+    pub owner: String,
+    #[serde(default = "default_token")]
+    pub token: String,
+    pub amount: f64,
+    pub memo: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    #[serde(default = "default_token")]
+    pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct LedgerBalanceResponse {
+    pub owner: String,
+    pub token: String,
+    pub available: f64,
+    pub held: f64,
+    pub fees: f64,
+}
+
+#[derive(Serialize)]
+pub struct LedgerEntriesResponse {
+    pub owner: String,
+    pub entries: Vec<JournalEntry>,
+}
+
+// Simulated Testnet Controls
+#[derive(Deserialize)]
+pub struct SimulateReorgRequest {
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    pub depth: u64,
+    #[serde(default)]
+    pub reinclude: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SimulateBaseFeeRequest {
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    pub base_fee_gwei: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SimulateNftBalanceRequest {
+    #[serde(flatten)]
+    pub nft: NftRef,
+    pub owner_address: String,
+    pub balance: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SimulateTokenBalanceRequest {
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    #[serde(default = "default_token")]
+    pub token: String,
+    pub owner_address: String,
     pub balance: f64,
 }
+
+#[derive(Serialize)]
+pub struct SimulateReorgResponse {
+    pub depth: u64,
+    pub orphaned_transactions: Vec<String>,
+}
+
+// Pending Operator Transactions
+#[derive(Serialize)]
+pub struct PendingTransactionView {
+    pub nonce: u64,
+    pub tx_hash: String,
+    pub original_tx_hash: String,
+    pub replaced_tx_hashes: Vec<String>,
+    pub max_fee_per_gas_gwei: u64,
+    pub cancelled: bool,
+    pub age_secs: i64,
+}
+
+#[derive(Serialize)]
+pub struct PendingTransactionsResponse {
+    pub chain: String,
+    pub transactions: Vec<PendingTransactionView>,
+}
+
+#[derive(Serialize)]
+pub struct TransactionStatusResponse {
+    pub tx_hash: String,
+    pub status: TxStatus,
+    pub receipt: Option<TransactionReceipt>,
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ark_client::ArkError;
+use crate::chains::DEFAULT_CHAIN;
+use crate::wallet::validate_address;
+
+/// Longest collection slug the backend stores
+const MAX_COLLECTION_LEN: usize = 50;
+/// Decimal digits of the largest uint256
+const MAX_TOKEN_ID_DIGITS: usize = 78;
+/// Most NFTs one escrow moves; each transfer adds gas
+pub const MAX_BUNDLE_ITEMS: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenStandard {
+    #[default]
+    Erc721,
+    Erc1155,
+}
+
+/// A specific NFT (or quantity of an ERC-1155 token) on a specific chain.
+///
+/// The collection is either a contract address or the backend's collection slug
+/// (e.g. `BAYC`). The string form is `[chain:]collection#token_id`, with
+/// `chain:collection:token_id` also accepted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NftRef {
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    pub collection: String,
+    pub token_id: String,
+    #[serde(default)]
+    pub standard: TokenStandard,
+    /// Units transferred; always 1 for ERC-721
+    #[serde(default = "default_amount")]
+    pub amount: u64,
+}
+
+fn default_chain() -> String {
+    DEFAULT_CHAIN.to_string()
+}
+
+fn default_amount() -> u64 {
+    1
+}
+
+impl NftRef {
+    /// Switch to an ERC-1155 reference for `amount` units
+    pub fn with_erc1155_amount(mut self, amount: u64) -> Result<Self, ArkError> {
+        self.standard = TokenStandard::Erc1155;
+        self.amount = amount;
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Identifies the token itself, regardless of standard and quantity
+    pub fn token_key(&self) -> String {
+        format!("{}:{}#{}", self.chain, self.collection.to_lowercase(), self.token_id)
+    }
+
+    /// Check every part of the reference; deserialized values are not validated
+    pub fn validate(&self) -> Result<(), ArkError> {
+        let invalid = |reason: String| Err(ArkError::InvalidNft(format!("{}: {}", self, reason)));
+
+        if self.chain.is_empty()
+            || !self
+                .chain
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return invalid(format!("invalid chain name '{}'", self.chain));
+        }
+
+        if self.collection.starts_with("0x") {
+            if let Err(e) = validate_address(&self.collection) {
+                return invalid(e.to_string());
+            }
+        } else if self.collection.is_empty()
+            || self.collection.len() > MAX_COLLECTION_LEN
+            || !self
+                .collection
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return invalid(format!("invalid collection '{}'", self.collection));
+        }
+
+        if self.token_id.is_empty()
+            || self.token_id.len() > MAX_TOKEN_ID_DIGITS
+            || !self.token_id.chars().all(|c| c.is_ascii_digit())
+        {
+            return invalid(format!("token id '{}' is not a uint256", self.token_id));
+        }
+
+        match (self.standard, self.amount) {
+            (_, 0) => invalid("amount must be at least 1".to_string()),
+            (TokenStandard::Erc721, amount) if amount != 1 => {
+                invalid(format!("ERC-721 tokens are unique, got amount {}", amount))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Check the items of an escrow: at least one, at most `MAX_BUNDLE_ITEMS`, each
+/// valid and none listed twice
+pub fn validate_bundle(nfts: &[NftRef]) -> Result<(), ArkError> {
+    if nfts.is_empty() || nfts.len() > MAX_BUNDLE_ITEMS {
+        return Err(ArkError::InvalidNft(format!(
+            "an escrow moves 1 to {} NFTs, got {}",
+            MAX_BUNDLE_ITEMS,
+            nfts.len()
+        )));
+    }
+    for (i, nft) in nfts.iter().enumerate() {
+        nft.validate()?;
+        if nfts[..i].iter().any(|other| other.token_key() == nft.token_key()) {
+            return Err(ArkError::InvalidNft(format!("{} is listed twice", nft)));
+        }
+    }
+    Ok(())
+}
+
+impl NftRef {
+    /// Parse a reference, placing it on `default_chain` if it does not name a chain
+    pub fn parse_on_chain(s: &str, default_chain: &str) -> Result<Self, ArkError> {
+        let s = s.trim();
+        let (chain, rest) = match s.split_once(':') {
+            Some((chain, rest)) => (chain, rest),
+            None => (default_chain, s),
+        };
+        let (collection, token_id) = rest
+            .rsplit_once('#')
+            .or_else(|| rest.rsplit_once(':'))
+            .ok_or_else(|| {
+                ArkError::InvalidNft(format!(
+                    "'{}' is not of the form [chain:]collection#token_id",
+                    s
+                ))
+            })?;
+
+        let nft = NftRef {
+            chain: chain.to_string(),
+            collection: collection.to_string(),
+            token_id: token_id.to_string(),
+            standard: TokenStandard::Erc721,
+            amount: 1,
+        };
+        nft.validate()?;
+        Ok(nft)
+    }
+}
+
+impl FromStr for NftRef {
+    type Err = ArkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_on_chain(s, DEFAULT_CHAIN)
+    }
+}
+
+impl fmt::Display for NftRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}#{}", self.chain, self.collection, self.token_id)?;
+        if self.standard == TokenStandard::Erc1155 {
+            write!(f, " x{}", self.amount)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_supported_forms() {
+        let nft: NftRef = "BAYC#1234".parse().unwrap();
+        assert_eq!(nft.chain, DEFAULT_CHAIN);
+        assert_eq!(nft.collection, "BAYC");
+        assert_eq!(nft.token_id, "1234");
+        assert_eq!(nft.standard, TokenStandard::Erc721);
+
+        let contract = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d";
+        let nft: NftRef = format!("ethereum:{}:7", contract).parse().unwrap();
+        assert_eq!(nft.chain, "ethereum");
+        assert_eq!(nft.collection, contract);
+        assert_eq!(nft.token_id, "7");
+        assert_eq!(nft.to_string().parse::<NftRef>().unwrap(), nft);
+        assert_eq!(NftRef::parse_on_chain("BAYC#1", "sepolia").unwrap().chain, "sepolia");
+    }
+
+    #[test]
+    fn test_rejects_malformed_references() {
+        for bad in [
+            "8a4c1f3e-55b2-4c1d-9f0e-0d7f3b2a1c9e",
+            "BAYC#",
+            "BAYC#abc",
+            "ark:0x1234#1",
+            "Ark:BAYC#1",
+            "BAYC!#1",
+        ] {
+            assert!(
+                matches!(bad.parse::<NftRef>(), Err(ArkError::InvalidNft(_))),
+                "{} should be rejected",
+                bad
+            );
+        }
+
+        let nft: NftRef = "BAYC#1".parse().unwrap();
+        assert!(validate_bundle(&[]).is_err());
+        assert!(validate_bundle(&[nft.clone(), "ark:bayc#1".parse().unwrap()]).is_err());
+        assert!(validate_bundle(&[nft.clone(), "BAYC#2".parse().unwrap()]).is_ok());
+        assert!(nft.clone().with_erc1155_amount(0).is_err());
+        let mut erc721 = nft;
+        erc721.amount = 2;
+        assert!(erc721.validate().is_err());
+    }
+}
@@ -0,0 +1,570 @@
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ark_client::ArkError;
+use crate::ledger::{from_units, to_units};
+use crate::nft::NftRef;
+use crate::records::{read_json, write_json};
+use crate::tokens::{TokenAmount, USDC};
+use crate::wallet::validate_address;
+
+fn default_token() -> String {
+    USDC.to_string()
+}
+
+/// Limits on what one agent, all agents of one owner, or one buyer wallet may spend.
+///
+/// Every limit is optional; a policy without limits allows everything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpendingPolicy {
+    /// Token the amount limits are in; deals in other tokens are refused while one is set
+    #[serde(default = "default_token")]
+    pub token: String,
+    pub max_per_deal: Option<f64>,
+    /// Cap on the deals entered per UTC day
+    pub max_per_day: Option<f64>,
+    /// Collection slugs or contract addresses NFTs may be bought from
+    pub allowed_collections: Option<Vec<String>>,
+    /// Addresses deals may be made with
+    pub allowed_counterparties: Option<Vec<String>>,
+}
+
+impl SpendingPolicy {
+    pub fn validate(&self) -> Result<(), ArkError> {
+        for (name, limit) in [
+            ("max_per_deal", self.max_per_deal),
+            ("max_per_day", self.max_per_day),
+        ] {
+            if let Some(limit) = limit {
+                if !limit.is_finite() || limit < 0.0 {
+                    return Err(ArkError::InvalidPolicy(format!(
+                        "{} must be a non-negative amount, got {}",
+                        name, limit
+                    )));
+                }
+            }
+        }
+        if self.token.is_empty() {
+            return Err(ArkError::InvalidPolicy("token must be set".to_string()));
+        }
+        Ok(())
+    }
+
+    fn limits_amounts(&self) -> bool {
+        self.max_per_deal.is_some() || self.max_per_day.is_some()
+    }
+}
+
+/// Who a policy applies to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum PolicySubject {
+    Agent(String),
+    /// The user who spawned the agents, covering all of them together
+    Owner(String),
+    /// The buyer wallet, lowercase; every deal names it, so its policy applies whether
+    /// or not the request says which agent and owner it comes from
+    Wallet(String),
+}
+
+impl PolicySubject {
+    /// Subject named by a `/policies/{kind}/{id}` path
+    pub fn from_path(kind: &str, id: &str) -> Result<Self, ArkError> {
+        match kind {
+            "agents" => Ok(Self::Agent(id.to_string())),
+            "owners" => Ok(Self::Owner(id.to_string())),
+            "wallets" => {
+                validate_address(id).map_err(|e| ArkError::InvalidPolicy(e.to_string()))?;
+                Ok(Self::Wallet(id.to_lowercase()))
+            }
+            _ => Err(ArkError::InvalidPolicy(format!(
+                "policies apply to agents, owners or wallets, not {}",
+                kind
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PolicySubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Agent(id) => write!(f, "agent {}", id),
+            Self::Owner(id) => write!(f, "owner {}", id),
+            Self::Wallet(address) => write!(f, "wallet {}", address),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    Token,
+    MaxPerDeal,
+    MaxPerDay,
+    AllowedCollections,
+    AllowedCounterparties,
+}
+
+/// One rule of one policy a deal breaks
+#[derive(Serialize, Debug, Clone)]
+pub struct PolicyViolation {
+    pub subject: PolicySubject,
+    pub rule: PolicyRule,
+    pub message: String,
+}
+
+/// A deal an agent wants to enter, as far as spending policies are concerned
+pub struct Spend<'a> {
+    pub deal_id: &'a str,
+    /// Wallet that pays for the deal
+    pub wallet: Option<&'a str>,
+    pub agent_id: Option<&'a str>,
+    pub owner_id: Option<&'a str>,
+    /// What the agent pays; amount limits are skipped when not known yet
+    pub amount: Option<&'a TokenAmount>,
+    pub nfts: &'a [NftRef],
+    pub counterparty: Option<&'a str>,
+}
+
+impl Spend<'_> {
+    fn subjects(&self) -> Vec<PolicySubject> {
+        let agent = self.agent_id.map(|id| PolicySubject::Agent(id.to_string()));
+        let owner = self.owner_id.map(|id| PolicySubject::Owner(id.to_string()));
+        let wallet = self.wallet.map(|address| PolicySubject::Wallet(address.to_lowercase()));
+        agent.into_iter().chain(owner).chain(wallet).collect()
+    }
+}
+
+/// A deal's claim on daily limits, which only the call that reserved it may release
+#[must_use = "a reservation must be released if its deal does not go through"]
+#[derive(Debug, PartialEq, Eq)]
+pub struct PolicyReservation {
+    id: u64,
+}
+
+/// Amount reserved against daily limits by a deal
+#[derive(Serialize, Deserialize, Clone)]
+struct Reservation {
+    id: u64,
+    deal_id: String,
+    subjects: Vec<PolicySubject>,
+    token: String,
+    units: i64,
+    day: NaiveDate,
+}
+
+#[derive(Default)]
+struct PolicyState {
+    policies: HashMap<PolicySubject, SpendingPolicy>,
+    reservations: Vec<Reservation>,
+}
+
+/// Policies and reservations as saved to the store file
+#[derive(Serialize, Deserialize, Default)]
+struct StoredPolicies {
+    policies: Vec<(PolicySubject, SpendingPolicy)>,
+    reservations: Vec<Reservation>,
+}
+
+/// Spending policies and what each subject spent today, saved to their file after
+/// every change so a restart neither drops a policy nor resets a daily limit
+pub struct SpendingPolicies {
+    state: Mutex<PolicyState>,
+    path: PathBuf,
+}
+
+impl SpendingPolicies {
+    /// Policies saved to `path`, restored from it where present
+    pub fn load(path: &Path) -> Result<Self, ArkError> {
+        let stored: StoredPolicies = read_json(path)?;
+        Ok(Self {
+            state: Mutex::new(PolicyState {
+                policies: stored.policies.into_iter().collect(),
+                reservations: stored.reservations,
+            }),
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn save(&self, state: &PolicyState) {
+        let stored = StoredPolicies {
+            policies: state
+                .policies
+                .iter()
+                .map(|(subject, policy)| (subject.clone(), policy.clone()))
+                .collect(),
+            reservations: state.reservations.clone(),
+        };
+        if let Err(e) = write_json(&self.path, &stored) {
+            log::error!("{}", e);
+        }
+    }
+
+    /// Set a subject's policy, replacing the previous one
+    pub fn set(&self, subject: PolicySubject, policy: SpendingPolicy) -> Result<(), ArkError> {
+        policy.validate()?;
+        let mut state = self.state.lock().unwrap();
+        state.policies.insert(subject, policy);
+        self.save(&state);
+        Ok(())
+    }
+
+    pub fn get(&self, subject: &PolicySubject) -> Option<SpendingPolicy> {
+        self.state.lock().unwrap().policies.get(subject).cloned()
+    }
+
+    /// Remove a subject's policy; returns whether it had one
+    pub fn remove(&self, subject: &PolicySubject) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.policies.remove(subject).is_some();
+        self.save(&state);
+        removed
+    }
+
+    /// Amount of `token` the subject's deals reserved today
+    pub fn spent_today(&self, subject: &PolicySubject, token: &str) -> f64 {
+        let state = self.state.lock().unwrap();
+        from_units(spent_units(&state, subject, token, None))
+    }
+
+    /// Rules of the subjects' policies the deal would break
+    pub fn check(&self, spend: &Spend) -> Result<Vec<PolicyViolation>, ArkError> {
+        evaluate(&self.state.lock().unwrap(), spend)
+    }
+
+    /// Check the deal and, if it breaks no rule, count its amount against today's
+    /// limits until its reservation is released. Checking and reserving under one lock
+    /// keeps concurrent deals from overrunning a daily cap together.
+    ///
+    /// A deal that already reserved is refused, so resubmitting it can neither count
+    /// it twice nor take over its reservation.
+    pub fn reserve(
+        &self,
+        spend: &Spend,
+    ) -> Result<Result<PolicyReservation, Vec<PolicyViolation>>, ArkError> {
+        let mut state = self.state.lock().unwrap();
+        let today = Utc::now().date_naive();
+        state.reservations.retain(|r| r.day == today);
+        if state.reservations.iter().any(|r| r.deal_id == spend.deal_id) {
+            return Err(ArkError::DealConflict(format!(
+                "deal {} is already counted against the spending limits",
+                spend.deal_id
+            )));
+        }
+
+        let violations = evaluate(&state, spend)?;
+        if !violations.is_empty() {
+            return Ok(Err(violations));
+        }
+
+        let reservation = PolicyReservation {
+            id: rand::random(),
+        };
+        if let Some(amount) = spend.amount {
+            state.reservations.push(Reservation {
+                id: reservation.id,
+                deal_id: spend.deal_id.to_string(),
+                subjects: spend.subjects(),
+                token: amount.token.clone(),
+                units: to_units(amount.amount)?,
+                day: today,
+            });
+            self.save(&state);
+        }
+        Ok(Ok(reservation))
+    }
+
+    /// Stop counting a deal that did not go through against daily limits
+    pub fn release(&self, reservation: PolicyReservation) {
+        let mut state = self.state.lock().unwrap();
+        state.reservations.retain(|r| r.id != reservation.id);
+        self.save(&state);
+    }
+}
+
+/// Units of `token` reserved today by the subject, not counting deal `except`
+fn spent_units(
+    state: &PolicyState,
+    subject: &PolicySubject,
+    token: &str,
+    except: Option<&str>,
+) -> i64 {
+    let today = Utc::now().date_naive();
+    state
+        .reservations
+        .iter()
+        .filter(|r| r.day == today && Some(r.deal_id.as_str()) != except)
+        .filter(|r| r.token.eq_ignore_ascii_case(token) && r.subjects.contains(subject))
+        .map(|r| r.units)
+        .sum()
+}
+
+fn evaluate(state: &PolicyState, spend: &Spend) -> Result<Vec<PolicyViolation>, ArkError> {
+    let mut violations = Vec::new();
+
+    for subject in spend.subjects() {
+        let Some(policy) = state.policies.get(&subject) else {
+            continue;
+        };
+        let mut violate = |rule, message: String| {
+            violations.push(PolicyViolation {
+                subject: subject.clone(),
+                rule,
+                message,
+            })
+        };
+
+        let amount = spend.amount.filter(|_| policy.limits_amounts());
+        if let Some(amount) = amount {
+            if !amount.token.eq_ignore_ascii_case(&policy.token) {
+                violate(
+                    PolicyRule::Token,
+                    format!(
+                        "{} may only spend {}, not {}",
+                        subject, policy.token, amount.token
+                    ),
+                );
+            } else {
+                let units = to_units(amount.amount)?;
+                if let Some(max) = policy.max_per_deal {
+                    if units > to_units(max)? {
+                        violate(
+                            PolicyRule::MaxPerDeal,
+                            format!(
+                                "{} exceeds the per-deal limit of {} {} for {}",
+                                amount, max, policy.token, subject
+                            ),
+                        );
+                    }
+                }
+                if let Some(max) = policy.max_per_day {
+                    let spent = spent_units(state, &subject, &policy.token, Some(spend.deal_id));
+                    if spent + units > to_units(max)? {
+                        violate(
+                            PolicyRule::MaxPerDay,
+                            format!(
+                                "{} after {} {} today exceeds the daily limit of {} {} for {}",
+                                amount,
+                                from_units(spent),
+                                policy.token,
+                                max,
+                                policy.token,
+                                subject
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(allowed) = &policy.allowed_collections {
+            for nft in spend.nfts {
+                let listed = allowed
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&nft.collection));
+                if !listed {
+                    violate(
+                        PolicyRule::AllowedCollections,
+                        format!("{} may not buy from collection {}", subject, nft.collection),
+                    );
+                }
+            }
+        }
+
+        if let (Some(allowed), Some(counterparty)) =
+            (&policy.allowed_counterparties, spend.counterparty)
+        {
+            if !allowed.iter().any(|a| a.eq_ignore_ascii_case(counterparty)) {
+                violate(
+                    PolicyRule::AllowedCounterparties,
+                    format!("{} may not deal with {}", subject, counterparty),
+                );
+            }
+        }
+    }
+
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("ark-policies-{:016x}", rand::random::<u64>()))
+            .join("policies.json")
+    }
+
+    fn spend<'a>(deal_id: &'a str, amount: &'a TokenAmount, nfts: &'a [NftRef]) -> Spend<'a> {
+        Spend {
+            deal_id,
+            wallet: Some("0xBuyer"),
+            agent_id: Some("agent-1"),
+            owner_id: Some("user-1"),
+            amount: Some(amount),
+            nfts,
+            counterparty: Some("0xseller"),
+        }
+    }
+
+    #[test]
+    fn test_daily_limit_counts_reserved_deals_of_all_agents() {
+        let path = temp_file();
+        let policies = SpendingPolicies::load(&path).unwrap();
+        let owner = PolicySubject::Owner("user-1".to_string());
+        policies
+            .set(
+                owner.clone(),
+                SpendingPolicy {
+                    token: USDC.to_string(),
+                    max_per_deal: Some(600.0),
+                    max_per_day: Some(1000.0),
+                    allowed_collections: None,
+                    allowed_counterparties: None,
+                },
+            )
+            .unwrap();
+
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap()];
+        let price = TokenAmount::new(USDC, 500.0);
+        let reserve = |deal_id| policies.reserve(&spend(deal_id, &price, &nfts)).unwrap();
+        let deal_1 = reserve("deal-1").unwrap();
+        let _deal_2 = reserve("deal-2").unwrap();
+        assert_eq!(policies.spent_today(&owner, USDC), 1000.0);
+
+        let violations = reserve("deal-3").unwrap_err();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule, PolicyRule::MaxPerDay);
+        assert_eq!(violations[0].subject, owner);
+
+        // A resubmitted deal is refused rather than counted once or replaced
+        assert!(matches!(
+            policies.reserve(&spend("deal-2", &price, &nfts)),
+            Err(ArkError::DealConflict(_))
+        ));
+        assert_eq!(policies.spent_today(&owner, USDC), 1000.0);
+
+        // A failed deal frees its share of the daily limit
+        policies.release(deal_1);
+        let _deal_3 = reserve("deal-3").unwrap();
+
+        let too_much = TokenAmount::new(USDC, 700.0);
+        let rules: Vec<PolicyRule> = policies
+            .check(&spend("deal-4", &too_much, &nfts))
+            .unwrap()
+            .iter()
+            .map(|v| v.rule)
+            .collect();
+        assert_eq!(rules, vec![PolicyRule::MaxPerDeal, PolicyRule::MaxPerDay]);
+
+        // A restart keeps both the policy and what was spent against it today
+        let restarted = SpendingPolicies::load(&path).unwrap();
+        assert!(restarted.get(&owner).is_some());
+        assert_eq!(restarted.spent_today(&owner, USDC), 1000.0);
+        assert_eq!(
+            restarted.check(&spend("deal-4", &price, &nfts)).unwrap()[0].rule,
+            PolicyRule::MaxPerDay
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_collection_counterparty_and_token_rules() {
+        let path = temp_file();
+        let policies = SpendingPolicies::load(&path).unwrap();
+        policies
+            .set(
+                PolicySubject::Agent("agent-1".to_string()),
+                SpendingPolicy {
+                    token: USDC.to_string(),
+                    max_per_deal: Some(100.0),
+                    max_per_day: None,
+                    allowed_collections: Some(vec!["bayc".to_string()]),
+                    allowed_counterparties: Some(vec!["0xSELLER".to_string()]),
+                },
+            )
+            .unwrap();
+
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap()];
+        let price = TokenAmount::new(USDC, 50.0);
+        assert!(policies
+            .check(&spend("deal-1", &price, &nfts))
+            .unwrap()
+            .is_empty());
+
+        let punks: Vec<NftRef> = vec!["PUNKS#1".parse().unwrap()];
+        let dai = TokenAmount::new("DAI", 50.0);
+        let mut other = spend("deal-2", &dai, &punks);
+        other.counterparty = Some("0xstranger");
+        let rules: Vec<PolicyRule> = policies
+            .check(&other)
+            .unwrap()
+            .iter()
+            .map(|v| v.rule)
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                PolicyRule::Token,
+                PolicyRule::AllowedCollections,
+                PolicyRule::AllowedCounterparties
+            ]
+        );
+
+        assert!(policies
+            .set(
+                PolicySubject::Agent("agent-2".to_string()),
+                SpendingPolicy {
+                    token: USDC.to_string(),
+                    max_per_deal: Some(-1.0),
+                    max_per_day: None,
+                    allowed_collections: None,
+                    allowed_counterparties: None,
+                },
+            )
+            .is_err());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_wallet_policy_binds_deals_that_name_no_agent() {
+        let path = temp_file();
+        let policies = SpendingPolicies::load(&path).unwrap();
+        let wallet = PolicySubject::from_path(
+            "wallets",
+            "0x00000000000000000000000000000000000B0B0B",
+        )
+        .unwrap();
+        policies
+            .set(
+                wallet.clone(),
+                SpendingPolicy {
+                    token: USDC.to_string(),
+                    max_per_deal: Some(100.0),
+                    max_per_day: None,
+                    allowed_collections: None,
+                    allowed_counterparties: None,
+                },
+            )
+            .unwrap();
+
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap()];
+        let price = TokenAmount::new(USDC, 500.0);
+        let anonymous = Spend {
+            wallet: Some("0x00000000000000000000000000000000000b0b0b"),
+            agent_id: None,
+            owner_id: None,
+            ..spend("deal-1", &price, &nfts)
+        };
+        let violations = policies.check(&anonymous).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].subject, wallet);
+        assert_eq!(violations[0].rule, PolicyRule::MaxPerDeal);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
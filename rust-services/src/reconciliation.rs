@@ -0,0 +1,334 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ark_client::{ArkClient, ArkError, TxStatus};
+use crate::records::EscrowRecord;
+use crate::state::AppState;
+use crate::tokens::TokenAmount;
+
+/// Amounts closer than this are considered equal (the ledger keeps 6 decimals)
+const AMOUNT_TOLERANCE: f64 = 0.000001;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// The chain has no transaction with the recorded hash
+    MissingTransaction,
+    /// The receipt status differs from the recorded status
+    WrongStatus,
+    /// The transferred tokens differ from the recorded price
+    WrongAmount,
+    /// The transaction is now included in a different block (reorg)
+    ReorgedBlock,
+    /// The receipt could not be fetched
+    QueryFailed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Mismatch {
+    pub deal_id: String,
+    pub tx_hash: String,
+    pub kind: MismatchKind,
+    pub recorded: String,
+    pub on_chain: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReconciliationReport {
+    pub generated_at: i64,
+    pub deals_checked: usize,
+    pub deals_matched: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Holds the most recent reconciliation report
+pub struct ReconciliationReports {
+    latest: Mutex<Option<ReconciliationReport>>,
+}
+
+impl ReconciliationReports {
+    pub fn new() -> Self {
+        Self {
+            latest: Mutex::new(None),
+        }
+    }
+
+    pub fn latest(&self) -> Option<ReconciliationReport> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    pub fn publish(&self, report: ReconciliationReport) {
+        *self.latest.lock().unwrap() = Some(report);
+    }
+}
+
+impl Default for ReconciliationReports {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Re-fetch the receipt of every stored escrow from its chain and compare it with
+/// what was recorded. Escrows recorded as pending are checked too, so one whose
+/// transaction was mined or vanished without being settled is reported.
+pub async fn reconcile(state: &AppState) -> ReconciliationReport {
+    let records: Vec<EscrowRecord> = state
+        .escrows
+        .all()
+        .into_iter()
+        .filter(|r| r.mined() || r.unconfirmed())
+        .collect();
+    let mut mismatches = Vec::new();
+    let mut deals_matched = 0;
+
+    for record in &records {
+        let client = match state.client(&record.chain) {
+            Ok(client) => client,
+            Err(e) => {
+                log::warn!("Cannot reconcile deal {}: {}", record.deal_id, e);
+                mismatches.push(Mismatch {
+                    deal_id: record.deal_id.clone(),
+                    tx_hash: record.tx_hash.clone().unwrap_or_default(),
+                    kind: MismatchKind::QueryFailed,
+                    recorded: record.chain.clone(),
+                    on_chain: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let found = check_record(client, record).await;
+        if found.is_empty() {
+            deals_matched += 1;
+        }
+        mismatches.extend(found);
+    }
+
+    log::info!(
+        "Reconciliation finished: {}/{} deals matched, {} mismatches",
+        deals_matched,
+        records.len(),
+        mismatches.len()
+    );
+
+    ReconciliationReport {
+        generated_at: chrono::Utc::now().timestamp(),
+        deals_checked: records.len(),
+        deals_matched,
+        mismatches,
+    }
+}
+
+async fn check_record(client: &ArkClient, record: &EscrowRecord) -> Vec<Mismatch> {
+    let tx_hash = record.tx_hash.as_deref().unwrap_or_default();
+    let mismatch = |kind: MismatchKind, recorded: String, on_chain: String| Mismatch {
+        deal_id: record.deal_id.clone(),
+        tx_hash: tx_hash.to_string(),
+        kind,
+        recorded,
+        on_chain,
+    };
+
+    let receipt = match client.get_transaction_receipt(tx_hash).await {
+        Ok(receipt) if record.unconfirmed() => {
+            // Mined, but the deal was never settled
+            return vec![mismatch(
+                MismatchKind::WrongStatus,
+                record.status.to_string(),
+                receipt.status.to_string(),
+            )];
+        }
+        Ok(receipt) => receipt,
+        Err(ArkError::TransactionNotFound(_)) if record.unconfirmed() => {
+            return match client.get_transaction_status(tx_hash).await {
+                Ok(TxStatus::Pending) => Vec::new(),
+                Ok(status) => vec![mismatch(
+                    MismatchKind::WrongStatus,
+                    record.status.to_string(),
+                    status.to_string(),
+                )],
+                Err(_) => vec![mismatch(
+                    MismatchKind::MissingTransaction,
+                    tx_hash.to_string(),
+                    "not found".to_string(),
+                )],
+            };
+        }
+        Err(ArkError::TransactionNotFound(_)) => {
+            return vec![mismatch(
+                MismatchKind::MissingTransaction,
+                tx_hash.to_string(),
+                "not found".to_string(),
+            )];
+        }
+        Err(e) => {
+            log::warn!("Failed to fetch receipt for deal {}: {}", record.deal_id, e);
+            return vec![mismatch(
+                MismatchKind::QueryFailed,
+                tx_hash.to_string(),
+                e.to_string(),
+            )];
+        }
+    };
+
+    let mut mismatches = Vec::new();
+    if receipt.status != record.status {
+        mismatches.push(mismatch(
+            MismatchKind::WrongStatus,
+            record.status.to_string(),
+            receipt.status.to_string(),
+        ));
+    }
+    if !same_value(&record.value, &receipt.value) {
+        mismatches.push(mismatch(
+            MismatchKind::WrongAmount,
+            describe_value(&record.value),
+            describe_value(&receipt.value),
+        ));
+    }
+    if receipt.block_number != record.block_number {
+        mismatches.push(mismatch(
+            MismatchKind::ReorgedBlock,
+            record.block_number.to_string(),
+            receipt.block_number.to_string(),
+        ));
+    }
+
+    for m in &mismatches {
+        log::warn!(
+            "Reconciliation mismatch for deal {} ({:?}): recorded={}, on_chain={}",
+            m.deal_id,
+            m.kind,
+            m.recorded,
+            m.on_chain
+        );
+    }
+
+    mismatches
+}
+
+/// Whether two per-token totals move the same amount of every token
+fn same_value(a: &[TokenAmount], b: &[TokenAmount]) -> bool {
+    let amount_of = |value: &[TokenAmount], token: &str| {
+        value.iter().filter(|t| t.token == token).map(|t| t.amount).sum::<f64>()
+    };
+    a.iter()
+        .chain(b)
+        .all(|t| (amount_of(a, &t.token) - amount_of(b, &t.token)).abs() <= AMOUNT_TOLERANCE)
+}
+
+fn describe_value(value: &[TokenAmount]) -> String {
+    if value.is_empty() {
+        return "nothing".to_string();
+    }
+    value
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Background job that reconciles all stored escrows every `interval`
+pub async fn run_periodically(state: actix_web::web::Data<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        state.reconciliation_reports.publish(reconcile(&state).await);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::DEFAULT_CHAIN;
+    use crate::simulator::SimulatedTransaction;
+    use crate::tokens::USDC;
+
+    fn record(deal_id: &str, tx_hash: &str, block_number: u64, price_usdc: f64) -> EscrowRecord {
+        EscrowRecord {
+            deal_id: deal_id.to_string(),
+            chain: DEFAULT_CHAIN.to_string(),
+            tx_hash: Some(tx_hash.to_string()),
+            block_number,
+            status: TxStatus::Success,
+            buyer_address: "0xbuyer".to_string(),
+            seller_address: "0xseller".to_string(),
+            nfts: vec!["BAYC#1234".parse().unwrap()],
+            value: vec![TokenAmount::new(USDC, price_usdc)],
+            transfers: Vec::new(),
+            failure: None,
+            recorded_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconciliation_flags_mismatches() {
+        let mut config = crate::config::Config::default();
+        config.storage.data_dir =
+            std::env::temp_dir().join(format!("ark-recon-{:016x}", rand::random::<u64>()));
+        let state = AppState::new(config).unwrap();
+        let chain = state.client(DEFAULT_CHAIN).unwrap().simulator();
+        chain.record_transaction(SimulatedTransaction {
+            tx_hash: "0xrecon-ok".to_string(),
+            block_number: 100,
+            status: TxStatus::Success,
+            gas_used: 240000,
+            effective_gas_price_gwei: 10,
+            value: vec![TokenAmount::new(USDC, 500.0)],
+            logs: Vec::new(),
+            revert_reason: None,
+        });
+        chain.record_transaction(SimulatedTransaction {
+            tx_hash: "0xrecon-bad".to_string(),
+            block_number: 105,
+            status: TxStatus::Reverted,
+            gas_used: 240000,
+            effective_gas_price_gwei: 10,
+            // Same amount, but paid in another token
+            value: vec![TokenAmount::new("DAI", 500.0)],
+            logs: Vec::new(),
+            revert_reason: None,
+        });
+
+        let store = &state.escrows;
+        store.insert(record("deal-ok", "0xrecon-ok", 100, 500.0));
+        store.insert(record("deal-bad", "0xrecon-bad", 104, 500.0));
+        store.insert(record("deal-missing", "0xrecon-missing", 110, 500.0));
+        store.insert(EscrowRecord {
+            chain: "solana".to_string(),
+            ..record("deal-elsewhere", "0xrecon-ok", 100, 500.0)
+        });
+        // Recorded as pending when the wait timed out: one was mined without being
+        // settled, the other vanished from the chain
+        let timeout = |tx_hash: &str| {
+            Err(ArkError::ConfirmationTimeout {
+                tx_hash: tx_hash.to_string(),
+            })
+        };
+        let pending = record("deal-late", "0xrecon-ok", 0, 500.0);
+        store.insert(pending.with_outcome(&timeout("0xrecon-ok")));
+        let pending = record("deal-unmined", "0xrecon-unmined", 0, 500.0);
+        store.insert(pending.with_outcome(&timeout("0xrecon-unmined")));
+
+        let report = reconcile(&state).await;
+
+        assert_eq!(report.deals_checked, 6);
+        assert_eq!(report.deals_matched, 1);
+
+        let kinds: Vec<MismatchKind> = report.mismatches.iter().map(|m| m.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MismatchKind::WrongStatus,
+                MismatchKind::WrongAmount,
+                MismatchKind::ReorgedBlock,
+                MismatchKind::MissingTransaction,
+                MismatchKind::QueryFailed,
+                MismatchKind::WrongStatus,
+                MismatchKind::MissingTransaction,
+            ]
+        );
+
+        std::fs::remove_dir_all(&state.config.storage.data_dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ark_client::{ArkError, TransactionLog, TransactionReceipt, TxStatus};
+use crate::nft::NftRef;
+use crate::tokens::TokenAmount;
+
+/// What this service recorded about an escrow at execution time
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EscrowRecord {
+    pub deal_id: String,
+    /// Chain the escrow transaction was sent to
+    pub chain: String,
+    /// Hash of the transaction, unless it failed before the chain named one
+    pub tx_hash: Option<String>,
+    /// Block the transaction was mined in, 0 if it never was
+    pub block_number: u64,
+    pub status: TxStatus,
+    pub buyer_address: String,
+    pub seller_address: String,
+    pub nfts: Vec<NftRef>,
+    /// Tokens the deal moves between the parties, per token
+    pub value: Vec<TokenAmount>,
+    /// NFT and token transfers the transaction emitted; empty unless it succeeded
+    #[serde(default)]
+    pub transfers: Vec<TransactionLog>,
+    /// Why the transaction returned no receipt, e.g. it was cancelled or reorged out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    pub recorded_at: i64,
+}
+
+impl EscrowRecord {
+    /// Fill in the outcome of the deal's transaction: its receipt, or as much as the
+    /// error tells of a transaction that never returned one
+    pub fn with_outcome(self, result: &Result<TransactionReceipt, ArkError>) -> Self {
+        let e = match result {
+            Ok(receipt) => {
+                return Self {
+                    tx_hash: Some(receipt.tx_hash.clone()),
+                    block_number: receipt.block_number,
+                    status: receipt.status,
+                    transfers: receipt.logs.clone(),
+                    failure: None,
+                    ..self
+                }
+            }
+            Err(e) => e,
+        };
+        let (status, tx_hash, block_number) = match e {
+            ArkError::TransactionReorged {
+                tx_hash,
+                block_number,
+            } => (TxStatus::Dropped, Some(tx_hash.clone()), *block_number),
+            ArkError::TransactionCancelled(tx_hash) => {
+                (TxStatus::Replaced, Some(tx_hash.clone()), 0)
+            }
+            // Still waiting to be mined when the service stopped waiting
+            ArkError::ConfirmationTimeout { tx_hash } => {
+                (TxStatus::Pending, Some(tx_hash.clone()), 0)
+            }
+            _ => (TxStatus::Dropped, None, 0),
+        };
+        Self {
+            tx_hash,
+            block_number,
+            status,
+            transfers: Vec::new(),
+            failure: Some(e.to_string()),
+            ..self
+        }
+    }
+
+    /// Whether the transaction was mined and its receipt recorded
+    pub fn mined(&self) -> bool {
+        matches!(self.status, TxStatus::Success | TxStatus::Reverted)
+    }
+
+    /// Whether the transaction was broadcast but had no outcome yet when recorded
+    pub fn unconfirmed(&self) -> bool {
+        self.status == TxStatus::Pending && self.tx_hash.is_some()
+    }
+
+    /// Whether the address traded in the deal or was paid by it
+    pub fn involves(&self, address: &str) -> bool {
+        self.buyer_address.eq_ignore_ascii_case(address)
+            || self.seller_address.eq_ignore_ascii_case(address)
+            || self.transfers.iter().any(|log| match log {
+                TransactionLog::NftTransfer { from, to, .. }
+                | TransactionLog::TokenTransfer { from, to, .. } => {
+                    from.eq_ignore_ascii_case(address) || to.eq_ignore_ascii_case(address)
+                }
+            })
+    }
+}
+
+/// Escrows and swaps submitted by this service, in execution order.
+///
+/// Saved to its file after every change, so history and reconciliation survive a
+/// restart.
+pub struct EscrowStore {
+    records: Mutex<Vec<EscrowRecord>>,
+    path: PathBuf,
+}
+
+impl EscrowStore {
+    /// Store saved to `path`, restored from it where present
+    pub fn load(path: &Path) -> Result<Self, ArkError> {
+        Ok(Self {
+            records: Mutex::new(read_json(path)?),
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Store the record of an attempt at a deal; earlier attempts stay on record
+    pub fn insert(&self, record: EscrowRecord) {
+        let mut records = self.records.lock().unwrap();
+        records.push(record);
+        self.save(&records);
+    }
+
+    /// Replace the pending record of the attempt that submitted `tx_hash` with its
+    /// outcome, or store the outcome if no such record is pending
+    pub fn resolve(&self, tx_hash: &str, record: EscrowRecord) {
+        let mut records = self.records.lock().unwrap();
+        let pending = records.iter_mut().find(|r| {
+            r.deal_id == record.deal_id
+                && r.status == TxStatus::Pending
+                && r.tx_hash.as_deref() == Some(tx_hash)
+        });
+        match pending {
+            Some(pending) => *pending = record,
+            None => records.push(record),
+        }
+        self.save(&records);
+    }
+
+    fn save(&self, records: &[EscrowRecord]) {
+        if let Err(e) = write_json(&self.path, records) {
+            log::error!("{}", e);
+        }
+    }
+
+    pub fn all(&self) -> Vec<EscrowRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Record of the latest attempt at a deal
+    pub fn get(&self, deal_id: &str) -> Option<EscrowRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|r| r.deal_id == deal_id)
+            .cloned()
+    }
+
+    /// Record of a deal whose transaction went through or may still be mined, so the
+    /// deal must not be submitted again
+    pub fn outstanding(&self, deal_id: &str) -> Option<EscrowRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| {
+                r.deal_id == deal_id && matches!(r.status, TxStatus::Success | TxStatus::Pending)
+            })
+            .cloned()
+    }
+
+    /// Records of the deals involving an address, most recent first
+    pub fn involving(&self, address: &str, chain: Option<&str>) -> Vec<EscrowRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|r| chain.is_none_or(|chain| r.chain == chain) && r.involves(address))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Step of a deal, from the signatures of its parties to the final receipt
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DealEvent {
+    /// A signature submitted for the deal was checked
+    SignatureVerified { public_key: String, valid: bool },
+    /// The verifiers voted on the deal
+    ConsensusReached {
+        approved: bool,
+        approval_count: usize,
+        verifier_count: usize,
+    },
+    /// The escrow or swap transaction was sent to the chain
+    TransactionSubmitted { chain: String, tx_hash: String },
+    /// The transaction was mined and reached the required confirmations
+    TransactionConfirmed {
+        tx_hash: String,
+        block_number: u64,
+        confirmations: u32,
+    },
+    /// Outcome of the mined transaction
+    Receipt { receipt: TransactionReceipt },
+    /// The transaction never made it into a block, or was reorged out of it
+    TransactionFailed { reason: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimelineEntry {
+    /// Unix timestamp of the step
+    pub at: i64,
+    #[serde(flatten)]
+    pub event: DealEvent,
+}
+
+/// Timeline of every deal this service took part in, saved to its file after every
+/// step
+pub struct DealTimelines {
+    /// Entries by deal id, in the order they were recorded
+    deals: Mutex<HashMap<String, Vec<TimelineEntry>>>,
+    path: PathBuf,
+}
+
+impl DealTimelines {
+    /// Timelines saved to `path`, restored from it where present
+    pub fn load(path: &Path) -> Result<Self, ArkError> {
+        Ok(Self {
+            deals: Mutex::new(read_json(path)?),
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Record a step of a deal that happened now
+    pub fn record(&self, deal_id: &str, event: DealEvent) {
+        self.record_at(deal_id, chrono::Utc::now().timestamp(), event);
+    }
+
+    /// Record a step of a deal that happened at `at`, e.g. a submission only known
+    /// once the transaction returned
+    pub fn record_at(&self, deal_id: &str, at: i64, event: DealEvent) {
+        let mut deals = self.deals.lock().unwrap();
+        deals
+            .entry(deal_id.to_string())
+            .or_default()
+            .push(TimelineEntry { at, event });
+        if let Err(e) = write_json(&self.path, &*deals) {
+            log::error!("{}", e);
+        }
+    }
+
+    /// Steps of a deal in the order they happened, empty for an unknown deal
+    pub fn timeline(&self, deal_id: &str) -> Vec<TimelineEntry> {
+        let mut entries = self
+            .deals
+            .lock()
+            .unwrap()
+            .get(deal_id)
+            .cloned()
+            .unwrap_or_default();
+        entries.sort_by_key(|entry| entry.at);
+        entries
+    }
+}
+
+/// Contents of a store file; a missing file is an empty store
+pub fn read_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, ArkError> {
+    let failed =
+        |e: String| ArkError::StorageError(format!("Cannot read {}: {}", path.display(), e));
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| failed(e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(failed(e.to_string())),
+    }
+}
+
+/// Replace a store file in one step, so a crash leaves the previous one intact
+pub fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), ArkError> {
+    let failed =
+        |e: String| ArkError::StorageError(format!("Cannot save {}: {}", path.display(), e));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| failed(e.to_string()))?;
+    }
+    let text = serde_json::to_string(value).map_err(|e| failed(e.to_string()))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, text)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| failed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUYER: &str = "0x00000000000000000000000000000000000B0B0B";
+    const SELLER: &str = "0x00000000000000000000000000000000000a11ce";
+    const TREASURY: &str = "0x000000000000000000000000000000000000fee5";
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("ark-records-{:016x}", rand::random::<u64>()))
+            .join(name)
+    }
+
+    fn record(deal_id: &str, chain: &str, transfers: Vec<TransactionLog>) -> EscrowRecord {
+        EscrowRecord {
+            deal_id: deal_id.to_string(),
+            chain: chain.to_string(),
+            tx_hash: Some(format!("0x{}", deal_id)),
+            block_number: 1,
+            status: TxStatus::Success,
+            buyer_address: BUYER.to_string(),
+            seller_address: SELLER.to_string(),
+            nfts: Vec::new(),
+            value: Vec::new(),
+            transfers,
+            failure: None,
+            recorded_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_history_lists_deals_of_parties_and_payees() {
+        let path = temp_file("escrows.json");
+        let store = EscrowStore::load(&path).unwrap();
+        let fee = TransactionLog::TokenTransfer {
+            token: "USDC".to_string(),
+            from: BUYER.to_string(),
+            to: TREASURY.to_string(),
+            amount: 2.5,
+        };
+        store.insert(record("deal-1", "ark", vec![fee]));
+        store.insert(record("deal-2", "sepolia", Vec::new()));
+        // Deals whose transaction failed are kept too, with what the error told
+        let cancelled = ArkError::TransactionCancelled("0xdeal-3".to_string());
+        store.insert(record("deal-3", "ark", Vec::new()).with_outcome(&Err(cancelled)));
+
+        let deals = |address: &str, chain| -> Vec<String> {
+            store
+                .involving(address, chain)
+                .into_iter()
+                .map(|r| r.deal_id)
+                .collect()
+        };
+        assert_eq!(
+            deals(&BUYER.to_lowercase(), None),
+            ["deal-3", "deal-2", "deal-1"]
+        );
+        assert_eq!(deals(SELLER, Some("ark")), ["deal-3", "deal-1"]);
+        assert_eq!(deals(TREASURY, None), ["deal-1"]);
+
+        // A restart picks up the saved records
+        let restarted = EscrowStore::load(&path).unwrap();
+        assert!(restarted.get("deal-2").unwrap().mined());
+        let failed = restarted.get("deal-3").unwrap();
+        assert_eq!(failed.status, TxStatus::Replaced);
+        assert!(!failed.mined() && failed.failure.is_some());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_attempts_stay_on_record_and_resolve_in_place() {
+        let path = temp_file("escrows.json");
+        let store = EscrowStore::load(&path).unwrap();
+        let reorged = ArkError::TransactionReorged {
+            tx_hash: "0xfirst".to_string(),
+            block_number: 7,
+        };
+        store.insert(record("deal-1", "ark", Vec::new()).with_outcome(&Err(reorged)));
+        let timeout = ArkError::ConfirmationTimeout {
+            tx_hash: "0xsecond".to_string(),
+        };
+        store.insert(record("deal-1", "ark", Vec::new()).with_outcome(&Err(timeout)));
+        assert_eq!(store.outstanding("deal-1").unwrap().tx_hash.as_deref(), Some("0xsecond"));
+
+        // The second attempt was mined after all, as a sped-up replacement
+        let mined = EscrowRecord {
+            tx_hash: Some("0xsecond-sped-up".to_string()),
+            ..record("deal-1", "ark", Vec::new())
+        };
+        store.resolve("0xsecond", mined);
+
+        let attempts: Vec<(TxStatus, Option<String>)> = EscrowStore::load(&path)
+            .unwrap()
+            .all()
+            .into_iter()
+            .map(|r| (r.status, r.tx_hash))
+            .collect();
+        assert_eq!(
+            attempts,
+            [
+                (TxStatus::Dropped, Some("0xfirst".to_string())),
+                (TxStatus::Success, Some("0xsecond-sped-up".to_string())),
+            ]
+        );
+        assert!(store.get("deal-1").unwrap().mined());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_timeline_is_ordered_by_time() {
+        let path = temp_file("timelines.json");
+        let timelines = DealTimelines::load(&path).unwrap();
+        timelines.record_at(
+            "deal-1",
+            20,
+            DealEvent::TransactionFailed {
+                reason: "dropped".to_string(),
+            },
+        );
+        timelines.record_at(
+            "deal-1",
+            10,
+            DealEvent::TransactionSubmitted {
+                chain: "ark".to_string(),
+                tx_hash: "0x01".to_string(),
+            },
+        );
+
+        let timeline = DealTimelines::load(&path).unwrap().timeline("deal-1");
+        assert_eq!(timeline.iter().map(|e| e.at).collect::<Vec<_>>(), [10, 20]);
+        assert!(matches!(
+            timeline[0].event,
+            DealEvent::TransactionSubmitted { .. }
+        ));
+        assert!(timelines.timeline("deal-2").is_empty());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
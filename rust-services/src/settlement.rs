@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::ark_client::ArkError;
+use crate::config::SettlementConfig;
+use crate::ledger::{from_units, to_units};
+use crate::nft::NftRef;
+use crate::tokens::TokenAmount;
+use crate::wallet::validate_address;
+
+/// Basis points in 100%
+pub const BPS_DENOMINATOR: u32 = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutKind {
+    Seller,
+    PlatformFee,
+    Royalty,
+}
+
+/// Share of an escrow price paid to one recipient
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Payout {
+    pub kind: PayoutKind,
+    pub recipient: String,
+    pub amount: TokenAmount,
+}
+
+/// Creator royalty of a collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Royalty {
+    pub recipient: String,
+    pub bps: u32,
+}
+
+/// How escrow prices are split between the seller, the marketplace and creators
+#[derive(Debug, Clone)]
+pub struct SettlementPolicy {
+    /// Marketplace fee taken from every escrow price
+    pub platform_fee_bps: u32,
+    /// Address the marketplace fee is paid to
+    pub treasury_address: String,
+    /// Royalties by lowercased collection slug or contract address
+    pub royalties: HashMap<String, Royalty>,
+}
+
+impl SettlementPolicy {
+    pub fn from_config(config: &SettlementConfig) -> Result<Self, ArkError> {
+        let mut policy = Self {
+            platform_fee_bps: config.platform_fee_bps,
+            treasury_address: config.treasury_address.clone(),
+            royalties: HashMap::new(),
+        };
+        for entry in config
+            .royalties
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (collection, royalty) = parse_royalty(entry)?;
+            policy.royalties.insert(collection, royalty);
+        }
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Check that the seller always keeps a share, whatever the collection
+    pub fn validate(&self) -> Result<(), ArkError> {
+        validate_address(&self.treasury_address)
+            .map_err(|e| ArkError::ConfigError(format!("Treasury address: {}", e)))?;
+        let max_royalty = self.royalties.values().map(|r| r.bps).max().unwrap_or(0);
+        if self.platform_fee_bps + max_royalty >= BPS_DENOMINATOR {
+            return Err(ArkError::ConfigError(format!(
+                "Platform fee ({} bps) and royalties (up to {} bps) leave nothing to the seller",
+                self.platform_fee_bps, max_royalty
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn royalty(&self, nft: &NftRef) -> Option<&Royalty> {
+        self.royalties.get(&nft.collection.to_lowercase())
+    }
+
+    /// Split `price` between the seller, the treasury and the creators of `nfts`.
+    ///
+    /// Each NFT accounts for an equal share of a bundle's price when applying its
+    /// collection's royalty. Fees round down, so rounding dust goes to the seller.
+    pub fn split(
+        &self,
+        price: &TokenAmount,
+        seller: &str,
+        nfts: &[NftRef],
+    ) -> Result<Vec<Payout>, ArkError> {
+        let total = to_units(price.amount)?;
+        let bps_of = |units: i64, bps: u32| units * bps as i64 / BPS_DENOMINATOR as i64;
+        let payout = |kind, recipient: &str, units| Payout {
+            kind,
+            recipient: recipient.to_string(),
+            amount: TokenAmount::new(&price.token, from_units(units)),
+        };
+
+        let platform_fee = bps_of(total, self.platform_fee_bps);
+        let share = total / nfts.len().max(1) as i64;
+        let mut royalties: Vec<(&str, i64)> = Vec::new();
+        for royalty in nfts.iter().filter_map(|nft| self.royalty(nft)) {
+            let units = bps_of(share, royalty.bps);
+            match royalties.iter_mut().find(|(r, _)| *r == royalty.recipient) {
+                Some((_, total)) => *total += units,
+                None => royalties.push((&royalty.recipient, units)),
+            }
+        }
+        let seller_units = total - platform_fee - royalties.iter().map(|(_, u)| u).sum::<i64>();
+
+        let mut payouts = vec![payout(PayoutKind::Seller, seller, seller_units)];
+        if platform_fee > 0 {
+            payouts.push(payout(
+                PayoutKind::PlatformFee,
+                &self.treasury_address,
+                platform_fee,
+            ));
+        }
+        payouts.extend(
+            royalties
+                .into_iter()
+                .filter(|(_, units)| *units > 0)
+                .map(|(recipient, units)| payout(PayoutKind::Royalty, recipient, units)),
+        );
+        Ok(payouts)
+    }
+}
+
+fn parse_royalty(entry: &str) -> Result<(String, Royalty), ArkError> {
+    let invalid = || {
+        ArkError::ConfigError(format!(
+            "royalty entry '{}' is not collection:recipient:bps",
+            entry
+        ))
+    };
+    let mut parts = entry.split(':');
+    let (Some(collection), Some(recipient), Some(bps), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let bps: u32 = bps.parse().map_err(|_| invalid())?;
+    if collection.is_empty() || bps >= BPS_DENOMINATOR {
+        return Err(invalid());
+    }
+    validate_address(recipient).map_err(|e| ArkError::ConfigError(e.to_string()))?;
+    Ok((
+        collection.to_lowercase(),
+        Royalty {
+            recipient: recipient.to_string(),
+            bps,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::USDC;
+
+    const CREATOR: &str = "0x00000000000000000000000000000000000c4ea7";
+
+    #[test]
+    fn test_split_pays_fee_and_royalties_and_sums_to_price() {
+        let mut policy = SettlementPolicy::from_config(&SettlementConfig::default()).unwrap();
+        let (collection, royalty) = parse_royalty(&format!("BAYC:{}:500", CREATOR)).unwrap();
+        policy.royalties.insert(collection, royalty);
+
+        let nfts: Vec<NftRef> = vec!["BAYC#1".parse().unwrap(), "PUNKS#2".parse().unwrap()];
+        let payouts = policy
+            .split(&TokenAmount::new(USDC, 1000.0), "0xseller", &nfts)
+            .unwrap();
+
+        // 2.5% to the treasury, 5% royalty on the BAYC half of the bundle
+        assert_eq!(payouts.len(), 3);
+        assert_eq!(payouts[0].kind, PayoutKind::Seller);
+        assert_eq!(payouts[0].amount, TokenAmount::new(USDC, 950.0));
+        assert_eq!(payouts[1].recipient, policy.treasury_address);
+        assert_eq!(payouts[1].amount.amount, 25.0);
+        assert_eq!(payouts[2].kind, PayoutKind::Royalty);
+        assert_eq!(payouts[2].recipient, CREATOR);
+        assert_eq!(payouts[2].amount.amount, 25.0);
+
+        // Rounding dust stays with the seller
+        let payouts = policy
+            .split(&TokenAmount::new(USDC, 0.000001), "0xseller", &nfts)
+            .unwrap();
+        assert_eq!(payouts.len(), 1);
+        assert_eq!(payouts[0].amount.amount, 0.000001);
+    }
+
+    #[test]
+    fn test_policy_keeps_a_share_for_the_seller() {
+        let mut policy = SettlementPolicy::from_config(&SettlementConfig::default()).unwrap();
+        assert!(policy.validate().is_ok());
+
+        policy.royalties.insert(
+            "bayc".to_string(),
+            Royalty {
+                recipient: CREATOR.to_string(),
+                bps: 9_750,
+            },
+        );
+        assert!(policy.validate().is_err());
+
+        assert!(parse_royalty("BAYC:0x1234:500").is_err());
+        assert!(parse_royalty(&format!("BAYC:{}:10000", CREATOR)).is_err());
+    }
+}
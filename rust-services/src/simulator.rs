@@ -0,0 +1,1026 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::ark_client::{ArkError, BlockLog, TransactionLog, TxStatus};
+use crate::chains::{ChainConfig, ARK_TESTNET_CHAIN_ID};
+use crate::ledger::{from_units, to_units};
+use crate::nft::{NftRef, TokenStandard};
+use crate::tokens::{totals, TokenAmount};
+use crate::wallet::{ContractCall, SignedTransaction};
+
+/// Base fee of the simulated testnet until changed
+pub const DEFAULT_BASE_FEE_GWEI: u64 = 10;
+
+/// Intrinsic gas of any transaction
+const GAS_BASE: u64 = 21000;
+/// Gas for entering the escrow contract
+const GAS_ESCROW_CONTRACT: u64 = 30000;
+/// Gas per NFT transfer
+const GAS_NFT_TRANSFER: u64 = 55000;
+/// Gas per fungible token transfer
+const GAS_TOKEN_TRANSFER: u64 = 45000;
+
+/// Holdings the simulation has not seen move are assumed to exist, like a mint:
+/// every address owns each ERC-721 token and a stack of each ERC-1155 edition.
+/// Once a holding of an ERC-721 token is recorded its owner is known, and no other
+/// address holds it.
+const UNSEEN_ERC721_BALANCE: u64 = 1;
+const UNSEEN_ERC1155_BALANCE: u64 = 10;
+/// Likewise every address is assumed to hold this many of each token until the
+/// simulation sees it pay or receive some
+const UNSEEN_TOKEN_BALANCE: f64 = 10000.0;
+
+/// A replacement must raise the max fee by at least this percentage
+const REPLACEMENT_MIN_BUMP_PERCENT: u64 = 10;
+
+/// A mined transaction as the simulated chain remembers it
+#[derive(Debug, Clone)]
+pub struct SimulatedTransaction {
+    pub tx_hash: String,
+    pub block_number: u64,
+    pub status: TxStatus,
+    pub gas_used: u64,
+    pub effective_gas_price_gwei: u64,
+    pub value: Vec<TokenAmount>,
+    pub logs: Vec<TransactionLog>,
+    pub revert_reason: Option<String>,
+}
+
+#[derive(Default)]
+struct ChainState {
+    transactions: HashMap<String, SimulatedTransaction>,
+    mempool: Vec<SignedTransaction>,
+    /// Next nonce accepted into the mempool, per sender
+    nonces: HashMap<String, u64>,
+    /// Next nonce eligible for mining, per sender
+    mined_nonces: HashMap<String, u64>,
+    /// Hashes of transactions superseded by a replacement with the same nonce
+    replaced: HashSet<String>,
+    /// Hashes of transactions orphaned by a reorg and not re-included
+    dropped: HashSet<String>,
+    /// Hashes of blocks on the canonical chain, assigned when first asked for
+    block_hashes: HashMap<u64, String>,
+    /// NFT units held, by (token key, lowercase owner)
+    nft_balances: HashMap<(String, String), u64>,
+    /// Every NFT with a recorded holding, by token key
+    nfts: HashMap<String, NftRef>,
+    /// Ledger units of a token held, by (uppercase symbol, lowercase owner)
+    token_balances: HashMap<(String, String), i64>,
+    base_fee_gwei: u64,
+}
+
+/// In-process stand-in for a chain's RPC backend (ARK testnet unless created for
+/// another configured chain).
+///
+/// While the RPC backend is simulated, this keeps the chain-side view consistent
+/// across requests: the head advances one block per `block_time`, submitted
+/// transactions wait in a mempool until their max fee covers the base fee,
+/// become queryable once their block is mined, and reorgs can orphan recent blocks.
+pub struct SimulatedChain {
+    chain_id: u64,
+    started: Instant,
+    started_at: i64,
+    genesis_height: u64,
+    block_time: Duration,
+    state: Mutex<ChainState>,
+}
+
+impl SimulatedChain {
+    pub fn new(block_time: Duration) -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            chain_id: ARK_TESTNET_CHAIN_ID,
+            started: Instant::now(),
+            started_at: chrono::Utc::now().timestamp_millis(),
+            genesis_height: rand::Rng::gen_range(&mut rng, 1000000..2000000),
+            block_time,
+            state: Mutex::new(ChainState {
+                base_fee_gwei: DEFAULT_BASE_FEE_GWEI,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Process-wide simulation of a configured chain, shared by every `ArkClient`
+    /// routed to it
+    pub fn shared(config: &ChainConfig) -> Arc<SimulatedChain> {
+        static CHAINS: OnceLock<Mutex<HashMap<String, Arc<SimulatedChain>>>> = OnceLock::new();
+        CHAINS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(config.name.clone())
+            .or_insert_with(|| {
+                Arc::new(SimulatedChain {
+                    chain_id: config.chain_id,
+                    ..SimulatedChain::new(config.block_time)
+                })
+            })
+            .clone()
+    }
+
+    #[cfg(test)]
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    pub fn block_time(&self) -> Duration {
+        self.block_time
+    }
+
+    /// Current head block height
+    pub fn head_height(&self) -> u64 {
+        let elapsed = self.started.elapsed().as_millis() / self.block_time.as_millis().max(1);
+        self.genesis_height + elapsed as u64
+    }
+
+    /// Hash of the canonical block at `height`; changes if the block is reorged out
+    pub fn block_hash(&self, height: u64) -> String {
+        self.state
+            .lock()
+            .unwrap()
+            .block_hashes
+            .entry(height)
+            .or_insert_with(|| {
+                let seed = format!("{}:{}:{}", self.started_at, height, rand::random::<u64>());
+                format!("0x{}", hex::encode(Sha256::digest(seed.as_bytes())))
+            })
+            .clone()
+    }
+
+    /// Unix timestamp (seconds) of the block at `height`
+    pub fn block_timestamp(&self, height: u64) -> i64 {
+        let since_genesis = height.saturating_sub(self.genesis_height) as u128;
+        let millis = since_genesis * self.block_time.as_millis();
+        (self.started_at + millis as i64) / 1000
+    }
+
+    pub fn base_fee(&self) -> u64 {
+        self.state.lock().unwrap().base_fee_gwei
+    }
+
+    pub fn set_base_fee(&self, base_fee_gwei: u64) {
+        log::warn!("Simulated base fee set to {} gwei", base_fee_gwei);
+        self.state.lock().unwrap().base_fee_gwei = base_fee_gwei;
+    }
+
+    /// Gas the call consumes when executed
+    pub fn estimate_gas(&self, call: &ContractCall) -> u64 {
+        match call {
+            ContractCall::Escrow(escrow) => {
+                GAS_BASE
+                    + GAS_ESCROW_CONTRACT
+                    + GAS_NFT_TRANSFER * escrow.nfts.len() as u64
+                    + GAS_TOKEN_TRANSFER * escrow.payouts.len() as u64
+            }
+            ContractCall::Swap(swap) => {
+                let sides = [&swap.maker, &swap.taker];
+                let nfts: usize = sides.iter().map(|side| side.nfts.len()).sum();
+                let tokens: usize = sides.iter().map(|side| side.tokens.len()).sum();
+                GAS_BASE
+                    + GAS_ESCROW_CONTRACT
+                    + GAS_NFT_TRANSFER * nfts as u64
+                    + GAS_TOKEN_TRANSFER * tokens as u64
+            }
+            ContractCall::Cancel => GAS_BASE,
+        }
+    }
+
+    /// Units of `nft`'s token id held by `owner`
+    pub fn nft_balance(&self, nft: &NftRef, owner: &str) -> u64 {
+        state_nft_balance(&self.state.lock().unwrap(), nft, owner)
+    }
+
+    /// Overwrite the units of `nft`'s token id held by `owner`
+    pub fn set_nft_balance(&self, nft: &NftRef, owner: &str, balance: u64) {
+        log::warn!("Simulated balance of {} for {} set to {}", nft.token_key(), owner, balance);
+        set_holding(&mut self.state.lock().unwrap(), nft, owner, balance);
+    }
+
+    /// Whole tokens of `token` held by `owner`
+    pub fn token_balance(&self, token: &str, owner: &str) -> f64 {
+        from_units(state_token_balance(&self.state.lock().unwrap(), token, owner))
+    }
+
+    /// Overwrite the whole tokens of `token` held by `owner`
+    pub fn set_token_balance(&self, token: &str, owner: &str, balance: f64) -> Result<(), ArkError> {
+        if balance < 0.0 {
+            return Err(ArkError::InvalidToken(format!(
+                "balance must not be negative, got {}",
+                balance
+            )));
+        }
+        log::warn!("Simulated {} balance of {} set to {}", token, owner, balance);
+        let units = to_units(balance)?;
+        self.state
+            .lock()
+            .unwrap()
+            .token_balances
+            .insert(token_holding_key(token, owner), units);
+        Ok(())
+    }
+
+    /// Tokens of a collection `owner` holds, with the units of each.
+    ///
+    /// Only recorded holdings are listed, not the ones assumed for NFTs the
+    /// simulation has never seen.
+    pub fn nft_holdings(&self, collection: &str, owner: &str) -> Vec<(NftRef, u64)> {
+        let state = self.state.lock().unwrap();
+        let owner = owner.to_lowercase();
+        let mut holdings: Vec<(NftRef, u64)> = state
+            .nft_balances
+            .iter()
+            .filter(|((_, holder), balance)| *holder == owner && **balance > 0)
+            .filter_map(|((token_key, _), balance)| {
+                let nft = state.nfts.get(token_key)?;
+                nft.collection
+                    .eq_ignore_ascii_case(collection)
+                    .then(|| (nft.clone(), *balance))
+            })
+            .collect();
+        // Numeric token ids in numeric order
+        holdings.sort_by(|(a, _), (b, _)| {
+            (a.token_id.len(), &a.token_id).cmp(&(b.token_id.len(), &b.token_id))
+        });
+        holdings
+    }
+
+    /// Next nonce expected from `address`, counting transactions still in the mempool
+    pub fn transaction_count(&self, address: &str) -> u64 {
+        self.state
+            .lock()
+            .unwrap()
+            .nonces
+            .get(address)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Accept a signed transaction into the mempool.
+    ///
+    /// Like a real node, this rejects bad signatures and any nonce other than the
+    /// sender's next one, so duplicated or skipped nonces surface as errors. A
+    /// transaction reusing the nonce of a pending one replaces it if it pays enough
+    /// more.
+    pub fn submit(&self, signed: &SignedTransaction) -> Result<(), ArkError> {
+        signed.verify()?;
+        if signed.tx.chain_id != self.chain_id {
+            return Err(ArkError::TransactionFailed(format!(
+                "Transaction is for chain id {}, this chain is {}",
+                signed.tx.chain_id, self.chain_id
+            )));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let from = &signed.tx.from;
+
+        if let Some(index) = state
+            .mempool
+            .iter()
+            .position(|p| &p.tx.from == from && p.tx.nonce == signed.tx.nonce)
+        {
+            let current = state.mempool[index].tx.max_fee_per_gas;
+            let required = current + current * REPLACEMENT_MIN_BUMP_PERCENT / 100;
+            if signed.tx.max_fee_per_gas < required {
+                return Err(ArkError::TransactionFailed(format!(
+                    "Replacement underpriced: max fee {} gwei, needs at least {} gwei",
+                    signed.tx.max_fee_per_gas, required
+                )));
+            }
+            let replaced = state.mempool.remove(index);
+            log::debug!("{} replaced by {}", replaced.tx_hash, signed.tx_hash);
+            state.replaced.insert(replaced.tx_hash);
+            state.mempool.push(signed.clone());
+            return Ok(());
+        }
+
+        let expected = state.nonces.get(from).copied().unwrap_or(0);
+        if signed.tx.nonce != expected {
+            return Err(ArkError::TransactionFailed(format!(
+                "Invalid nonce for {}: expected {}, got {}",
+                from, expected, signed.tx.nonce
+            )));
+        }
+        state.nonces.insert(from.clone(), expected + 1);
+        state.mempool.push(signed.clone());
+
+        Ok(())
+    }
+
+    /// Move affordable mempool transactions into the next block, in nonce order per sender
+    fn mine_pending(&self, state: &mut ChainState) {
+        let next_block = self.head_height() + 1;
+
+        loop {
+            let base_fee = state.base_fee_gwei;
+            let mined_nonces = &state.mined_nonces;
+            let Some(index) = state.mempool.iter().position(|p| {
+                p.tx.max_fee_per_gas >= base_fee
+                    && p.tx.nonce == mined_nonces.get(&p.tx.from).copied().unwrap_or(0)
+            }) else {
+                break;
+            };
+
+            let signed = state.mempool.remove(index);
+            let gas_needed = self.estimate_gas(&signed.tx.call);
+            let effective_gas_price_gwei = signed
+                .tx
+                .max_fee_per_gas
+                .min(base_fee + signed.tx.max_priority_fee_per_gas);
+
+            state
+                .mined_nonces
+                .insert(signed.tx.from.clone(), signed.tx.nonce + 1);
+
+            // Running out of gas reverts the call but still consumes the whole limit
+            let revert_reason = if signed.tx.gas_limit < gas_needed {
+                Some("out of gas")
+            } else if !parties_hold_nfts(state, &signed.tx.call) {
+                Some("sender holds too few units of an NFT it transfers")
+            } else if !parties_hold_tokens(state, &signed.tx.call) {
+                Some("sender holds too few of a token it pays")
+            } else if !payouts_add_up(&signed.tx.call) {
+                Some("payouts do not add up to the price")
+            } else {
+                None
+            };
+            let logs = match revert_reason {
+                Some(_) => Vec::new(),
+                None => call_effects(&signed.tx.call),
+            };
+            apply_transfers(state, &logs, false);
+
+            state.transactions.insert(
+                signed.tx_hash.clone(),
+                SimulatedTransaction {
+                    tx_hash: signed.tx_hash.clone(),
+                    block_number: next_block,
+                    status: if revert_reason.is_some() {
+                        TxStatus::Reverted
+                    } else {
+                        TxStatus::Success
+                    },
+                    gas_used: gas_needed.min(signed.tx.gas_limit),
+                    effective_gas_price_gwei,
+                    value: transferred_value(&logs),
+                    logs,
+                    revert_reason: revert_reason.map(str::to_string),
+                },
+            );
+        }
+    }
+
+    /// Insert an already-mined transaction directly, bypassing the mempool
+    #[cfg(test)]
+    pub fn record_transaction(&self, tx: SimulatedTransaction) {
+        self.state
+            .lock()
+            .unwrap()
+            .transactions
+            .insert(tx.tx_hash.clone(), tx);
+    }
+
+    /// Look up a mined transaction; transactions whose block is not mined yet are not visible
+    pub fn transaction(&self, tx_hash: &str) -> Option<SimulatedTransaction> {
+        let mut state = self.state.lock().unwrap();
+        self.mine_pending(&mut state);
+
+        let head = self.head_height();
+        state
+            .transactions
+            .get(tx_hash)
+            .filter(|tx| tx.block_number <= head)
+            .cloned()
+    }
+
+    /// Event logs of the transactions mined in blocks `from..=to`, in block order;
+    /// blocks past the head have none yet
+    pub fn logs(&self, from: u64, to: u64) -> Vec<BlockLog> {
+        let mut state = self.state.lock().unwrap();
+        self.mine_pending(&mut state);
+
+        let to = to.min(self.head_height());
+        let mut mined: Vec<&SimulatedTransaction> = state
+            .transactions
+            .values()
+            .filter(|tx| tx.block_number >= from && tx.block_number <= to)
+            .collect();
+        mined.sort_by(|a, b| (a.block_number, &a.tx_hash).cmp(&(b.block_number, &b.tx_hash)));
+        mined
+            .into_iter()
+            .flat_map(|tx| {
+                tx.logs.iter().map(|log| BlockLog {
+                    block_number: tx.block_number,
+                    tx_hash: tx.tx_hash.clone(),
+                    log: log.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Lifecycle status of a transaction, or `None` if the chain never saw it
+    pub fn status(&self, tx_hash: &str) -> Option<TxStatus> {
+        let mut state = self.state.lock().unwrap();
+        self.mine_pending(&mut state);
+
+        let head = self.head_height();
+        if let Some(tx) = state.transactions.get(tx_hash) {
+            return Some(if tx.block_number > head {
+                TxStatus::Pending
+            } else {
+                tx.status
+            });
+        }
+        if state.mempool.iter().any(|p| p.tx_hash == tx_hash) {
+            Some(TxStatus::Pending)
+        } else if state.replaced.contains(tx_hash) {
+            Some(TxStatus::Replaced)
+        } else if state.dropped.contains(tx_hash) {
+            Some(TxStatus::Dropped)
+        } else {
+            None
+        }
+    }
+
+    /// Orphan the last `depth` blocks.
+    ///
+    /// Transactions in orphaned blocks are either dropped or, with `reinclude`,
+    /// mined again in the next block of the new canonical chain. Returns the
+    /// hashes of affected transactions.
+    pub fn reorg(&self, depth: u64, reinclude: bool) -> Vec<String> {
+        let head = self.head_height();
+        let fork_point = head.saturating_sub(depth);
+        let mut state = self.state.lock().unwrap();
+
+        let orphaned: Vec<String> = state
+            .transactions
+            .values()
+            .filter(|tx| tx.block_number > fork_point && tx.block_number <= head)
+            .map(|tx| tx.tx_hash.clone())
+            .collect();
+
+        for tx_hash in &orphaned {
+            if reinclude {
+                if let Some(tx) = state.transactions.get_mut(tx_hash) {
+                    tx.block_number = head + 1;
+                }
+            } else if let Some(tx) = state.transactions.remove(tx_hash) {
+                // The dropped transaction's transfers never happened on the new chain
+                apply_transfers(&mut state, &tx.logs, true);
+                state.dropped.insert(tx_hash.clone());
+            }
+        }
+        state.block_hashes.retain(|height, _| *height <= fork_point);
+
+        log::warn!(
+            "Simulated reorg of depth {} at height {}: {} transactions {}",
+            depth,
+            head,
+            orphaned.len(),
+            if reinclude { "re-included" } else { "dropped" }
+        );
+
+        orphaned
+    }
+}
+
+fn holding_key(nft: &NftRef, owner: &str) -> (String, String) {
+    (nft.token_key(), owner.to_lowercase())
+}
+
+/// Record the units of `nft`'s token id held by `owner`
+fn set_holding(state: &mut ChainState, nft: &NftRef, owner: &str, balance: u64) {
+    state.nft_balances.insert(holding_key(nft, owner), balance);
+    state.nfts.entry(nft.token_key()).or_insert_with(|| NftRef {
+        amount: 1,
+        ..nft.clone()
+    });
+}
+
+fn state_nft_balance(state: &ChainState, nft: &NftRef, owner: &str) -> u64 {
+    if let Some(balance) = state.nft_balances.get(&holding_key(nft, owner)) {
+        return *balance;
+    }
+    match nft.standard {
+        TokenStandard::Erc721 if state.nfts.contains_key(&nft.token_key()) => 0,
+        TokenStandard::Erc721 => UNSEEN_ERC721_BALANCE,
+        TokenStandard::Erc1155 => UNSEEN_ERC1155_BALANCE,
+    }
+}
+
+/// Whether every party of a call holds all NFT units it hands over
+fn parties_hold_nfts(state: &ChainState, call: &ContractCall) -> bool {
+    let holds = |owner: &str, nfts: &[NftRef]| {
+        nfts.iter()
+            .all(|nft| state_nft_balance(state, nft, owner) >= nft.amount)
+    };
+    match call {
+        ContractCall::Escrow(escrow) => holds(&escrow.seller_address, &escrow.nfts),
+        ContractCall::Swap(swap) => {
+            holds(&swap.maker.address, &swap.maker.nfts)
+                && holds(&swap.taker.address, &swap.taker.nfts)
+        }
+        ContractCall::Cancel => true,
+    }
+}
+
+fn token_holding_key(token: &str, owner: &str) -> (String, String) {
+    (token.to_uppercase(), owner.to_lowercase())
+}
+
+fn state_token_balance(state: &ChainState, token: &str, owner: &str) -> i64 {
+    state
+        .token_balances
+        .get(&token_holding_key(token, owner))
+        .copied()
+        .unwrap_or_else(|| to_units(UNSEEN_TOKEN_BALANCE).unwrap_or(0))
+}
+
+/// Whether every party of a call holds all the tokens it pays
+fn parties_hold_tokens(state: &ChainState, call: &ContractCall) -> bool {
+    let holds = |owner: &str, amounts: &[TokenAmount]| {
+        totals(amounts).iter().all(|amount| {
+            to_units(amount.amount)
+                .is_ok_and(|units| state_token_balance(state, &amount.token, owner) >= units)
+        })
+    };
+    match call {
+        ContractCall::Escrow(escrow) => {
+            holds(&escrow.buyer_address, std::slice::from_ref(&escrow.price))
+        }
+        ContractCall::Swap(swap) => {
+            holds(&swap.maker.address, &swap.maker.tokens)
+                && holds(&swap.taker.address, &swap.taker.tokens)
+        }
+        ContractCall::Cancel => true,
+    }
+}
+
+/// Whether an escrow pays out exactly its price, in the price's token
+fn payouts_add_up(call: &ContractCall) -> bool {
+    let ContractCall::Escrow(escrow) = call else {
+        return true;
+    };
+    let units = |amount: &TokenAmount| to_units(amount.amount).unwrap_or(-1);
+    escrow.payouts.iter().all(|p| p.amount.token == escrow.price.token)
+        && escrow.payouts.iter().map(|p| units(&p.amount)).sum::<i64>() == units(&escrow.price)
+}
+
+/// Move NFT units and tokens as the transfer logs say, or back again with `undo`
+fn apply_transfers(state: &mut ChainState, logs: &[TransactionLog], undo: bool) {
+    for log in logs {
+        match log {
+            TransactionLog::NftTransfer { nft, from, to } => {
+                let (from, to) = if undo { (to, from) } else { (from, to) };
+                let (from_balance, to_balance) = match nft.standard {
+                    // A unique token changes owner; an assumed holding by the recipient
+                    // is replaced by the real one
+                    TokenStandard::Erc721 => (0, 1),
+                    TokenStandard::Erc1155 => (
+                        state_nft_balance(state, nft, from).saturating_sub(nft.amount),
+                        state_nft_balance(state, nft, to) + nft.amount,
+                    ),
+                };
+                set_holding(state, nft, from, from_balance);
+                set_holding(state, nft, to, to_balance);
+            }
+            TransactionLog::TokenTransfer {
+                token,
+                from,
+                to,
+                amount,
+            } => {
+                let (from, to) = if undo { (to, from) } else { (from, to) };
+                let units = to_units(*amount).unwrap_or(0);
+                let from_balance = state_token_balance(state, token, from) - units;
+                let to_balance = state_token_balance(state, token, to) + units;
+                state
+                    .token_balances
+                    .insert(token_holding_key(token, from), from_balance);
+                state
+                    .token_balances
+                    .insert(token_holding_key(token, to), to_balance);
+            }
+        }
+    }
+}
+
+/// Decoded event logs of a successful call
+fn call_effects(call: &ContractCall) -> Vec<TransactionLog> {
+    match call {
+        ContractCall::Escrow(escrow) => {
+            let mut logs: Vec<TransactionLog> = escrow
+                .nfts
+                .iter()
+                .map(|nft| TransactionLog::NftTransfer {
+                    nft: nft.clone(),
+                    from: escrow.seller_address.clone(),
+                    to: escrow.buyer_address.clone(),
+                })
+                .collect();
+            logs.extend(escrow.payouts.iter().map(|payout| TransactionLog::TokenTransfer {
+                token: payout.amount.token.clone(),
+                from: escrow.buyer_address.clone(),
+                to: payout.recipient.clone(),
+                amount: payout.amount.amount,
+            }));
+            logs
+        }
+        ContractCall::Swap(swap) => {
+            let mut logs = Vec::new();
+            for (from, to) in [(&swap.maker, &swap.taker), (&swap.taker, &swap.maker)] {
+                logs.extend(from.nfts.iter().map(|nft| TransactionLog::NftTransfer {
+                    nft: nft.clone(),
+                    from: from.address.clone(),
+                    to: to.address.clone(),
+                }));
+                logs.extend(from.tokens.iter().map(|t| TransactionLog::TokenTransfer {
+                    token: t.token.clone(),
+                    from: from.address.clone(),
+                    to: to.address.clone(),
+                    amount: t.amount,
+                }));
+            }
+            logs
+        }
+        ContractCall::Cancel => Vec::new(),
+    }
+}
+
+/// Tokens moved by the transfer logs, per token
+fn transferred_value(logs: &[TransactionLog]) -> Vec<TokenAmount> {
+    let transfers: Vec<TokenAmount> = logs
+        .iter()
+        .filter_map(|log| match log {
+            TransactionLog::TokenTransfer { token, amount, .. } => {
+                Some(TokenAmount::new(token, *amount))
+            }
+            TransactionLog::NftTransfer { .. } => None,
+        })
+        .collect();
+    totals(&transfers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ark_client::{EscrowTransaction, SwapSide, SwapTransaction};
+    use crate::chains::ChainRegistry;
+    use crate::settlement::{Payout, PayoutKind};
+    use crate::tokens::USDC;
+    use crate::wallet::{HotWallet, UnsignedTransaction};
+
+    fn tx(tx_hash: &str, block_number: u64) -> SimulatedTransaction {
+        SimulatedTransaction {
+            tx_hash: tx_hash.to_string(),
+            block_number,
+            status: TxStatus::Success,
+            gas_used: 21000,
+            effective_gas_price_gwei: 10,
+            value: vec![TokenAmount::new(USDC, 1.0)],
+            logs: Vec::new(),
+            revert_reason: None,
+        }
+    }
+
+    fn signed(wallet: &HotWallet, nonce: u64, max_fee_per_gas: u64) -> SignedTransaction {
+        signed_with_gas(wallet, nonce, max_fee_per_gas, 200000)
+    }
+
+    fn signed_with_gas(
+        wallet: &HotWallet,
+        nonce: u64,
+        max_fee_per_gas: u64,
+        gas_limit: u64,
+    ) -> SignedTransaction {
+        signed_escrow(wallet, nonce, max_fee_per_gas, gas_limit, vec!["BAYC#1".parse().unwrap()])
+    }
+
+    fn signed_escrow(
+        wallet: &HotWallet,
+        nonce: u64,
+        max_fee_per_gas: u64,
+        gas_limit: u64,
+        nfts: Vec<NftRef>,
+    ) -> SignedTransaction {
+        signed_sale(wallet, nonce, max_fee_per_gas, gas_limit, "0xseller", "0xbuyer", nfts)
+    }
+
+    fn signed_sale(
+        wallet: &HotWallet,
+        nonce: u64,
+        max_fee_per_gas: u64,
+        gas_limit: u64,
+        seller: &str,
+        buyer: &str,
+        nfts: Vec<NftRef>,
+    ) -> SignedTransaction {
+        wallet.sign(UnsignedTransaction {
+            chain_id: ARK_TESTNET_CHAIN_ID,
+            from: wallet.address().to_string(),
+            to: "0xescrow".to_string(),
+            nonce,
+            gas_limit,
+            max_fee_per_gas,
+            max_priority_fee_per_gas: 1,
+            call: ContractCall::Escrow(EscrowTransaction {
+                buyer_address: buyer.to_string(),
+                seller_address: seller.to_string(),
+                nfts,
+                price: TokenAmount::new(USDC, 10.0),
+                payouts: vec![Payout {
+                    kind: PayoutKind::Seller,
+                    recipient: seller.to_string(),
+                    amount: TokenAmount::new(USDC, 10.0),
+                }],
+            }),
+        })
+    }
+
+    #[test]
+    fn test_pending_transaction_not_visible() {
+        let chain = SimulatedChain::new(Duration::from_secs(60));
+        let head = chain.head_height();
+        chain.record_transaction(tx("0xpending", head + 1));
+        chain.record_transaction(tx("0xmined", head));
+
+        assert!(chain.transaction("0xpending").is_none());
+        assert_eq!(chain.status("0xpending"), Some(TxStatus::Pending));
+        assert!(chain.transaction("0xmined").is_some());
+        assert_eq!(chain.status("0xmined"), Some(TxStatus::Success));
+        assert_eq!(chain.status("0xunknown"), None);
+    }
+
+    #[test]
+    fn test_shared_chains_are_separate_and_reject_foreign_transactions() {
+        let registry = ChainRegistry::default();
+        let ark = SimulatedChain::shared(registry.get("ark").unwrap());
+        let sepolia = SimulatedChain::shared(registry.get("sepolia").unwrap());
+        assert!(Arc::ptr_eq(&ark, &SimulatedChain::shared(registry.get("ark").unwrap())));
+        assert_eq!(sepolia.block_time(), Duration::from_secs(12));
+
+        let wallet = HotWallet::generate();
+        let signed = signed(&wallet, 0, 20);
+        assert!(sepolia.submit(&signed).is_err());
+        assert!(ark.submit(&signed).is_ok());
+        assert_eq!(sepolia.status(&signed.tx_hash), None);
+    }
+
+    #[test]
+    fn test_reorg_drops_or_reincludes() {
+        let chain = SimulatedChain::new(Duration::from_secs(60));
+        let head = chain.head_height();
+        chain.record_transaction(tx("0xold", head - 10));
+        chain.record_transaction(tx("0xrecent", head - 1));
+        chain.record_transaction(tx("0xtip", head));
+
+        let tip_hash = chain.block_hash(head);
+        let orphaned = chain.reorg(1, true);
+        assert_eq!(orphaned, vec!["0xtip".to_string()]);
+        assert!(chain.transaction("0xtip").is_none());
+        assert_ne!(chain.block_hash(head), tip_hash);
+
+        chain.reorg(2, false);
+        assert!(chain.transaction("0xrecent").is_none());
+        assert_eq!(chain.status("0xrecent"), Some(TxStatus::Dropped));
+        assert!(chain.transaction("0xold").is_some());
+    }
+
+    #[test]
+    fn test_underpriced_transaction_waits_for_base_fee() {
+        let chain = SimulatedChain::new(Duration::from_secs(60));
+        let wallet = HotWallet::generate();
+        chain.set_base_fee(50);
+
+        let cheap = signed(&wallet, 0, 20);
+        chain.submit(&cheap).unwrap();
+        assert_eq!(chain.status(&cheap.tx_hash), Some(TxStatus::Pending));
+
+        // Too small a bump is refused, a sufficient one replaces the original
+        assert!(chain.submit(&signed(&wallet, 0, 21)).is_err());
+        let bumped = signed(&wallet, 0, 60);
+        chain.submit(&bumped).unwrap();
+        assert_eq!(chain.status(&cheap.tx_hash), Some(TxStatus::Replaced));
+        assert_eq!(chain.status(&bumped.tx_hash), Some(TxStatus::Pending));
+        assert_eq!(chain.transaction_count(wallet.address()), 1);
+    }
+
+    #[test]
+    fn test_mined_escrow_emits_transfer_logs_or_reverts() {
+        let chain = SimulatedChain::new(Duration::from_millis(200));
+        let wallet = HotWallet::generate();
+
+        let ok = signed(&wallet, 0, 20);
+        let out_of_gas = signed_with_gas(&wallet, 1, 20, 50000);
+        chain.submit(&ok).unwrap();
+        chain.submit(&out_of_gas).unwrap();
+        // Mined into the next block, visible once the head reaches it
+        assert_eq!(chain.status(&ok.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+
+        let mined = chain.transaction(&ok.tx_hash).unwrap();
+        assert_eq!(mined.status, TxStatus::Success);
+        assert_eq!(mined.revert_reason, None);
+        assert!(matches!(
+            &mined.logs[..],
+            [TransactionLog::NftTransfer { .. }, TransactionLog::TokenTransfer { amount, .. }]
+                if *amount == 10.0
+        ));
+
+        let reverted = chain.transaction(&out_of_gas.tx_hash).unwrap();
+        assert_eq!(reverted.status, TxStatus::Reverted);
+        assert_eq!(reverted.gas_used, 50000);
+        assert!(reverted.value.is_empty());
+        assert!(reverted.logs.is_empty());
+        assert_eq!(reverted.revert_reason.as_deref(), Some("out of gas"));
+    }
+
+    #[test]
+    fn test_a_sold_token_cannot_be_sold_by_anyone_but_its_new_owner() {
+        let chain = SimulatedChain::new(Duration::from_millis(200));
+        let wallet = HotWallet::generate();
+        let nft: NftRef = "BAYC#7".parse().unwrap();
+        assert_eq!(chain.nft_balance(&nft, "0xcarol"), 1);
+
+        let sale = signed_sale(&wallet, 0, 20, 200000, "0xalice", "0xbob", vec![nft.clone()]);
+        chain.submit(&sale).unwrap();
+        assert_eq!(chain.status(&sale.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+        assert_eq!(chain.status(&sale.tx_hash), Some(TxStatus::Success));
+        assert_eq!(chain.nft_balance(&nft, "0xalice"), 0);
+        assert_eq!(chain.nft_balance(&nft, "0xbob"), 1);
+        // Every other address is known not to hold the token now
+        assert_eq!(chain.nft_balance(&nft, "0xcarol"), 0);
+
+        let resale = signed_sale(&wallet, 1, 20, 200000, "0xcarol", "0xdave", vec![nft.clone()]);
+        chain.submit(&resale).unwrap();
+        assert_eq!(chain.status(&resale.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+        let reverted = chain.transaction(&resale.tx_hash).unwrap();
+        assert_eq!(reverted.status, TxStatus::Reverted);
+        assert_eq!(
+            reverted.revert_reason.as_deref(),
+            Some("sender holds too few units of an NFT it transfers")
+        );
+        assert_eq!(chain.nft_balance(&nft, "0xbob"), 1);
+    }
+
+    #[test]
+    fn test_edition_escrow_moves_units_and_reorg_restores_them() {
+        let chain = SimulatedChain::new(Duration::from_millis(200));
+        let wallet = HotWallet::generate();
+        let edition: NftRef = "COOLCATS#42".parse().unwrap();
+        let five = edition.clone().with_erc1155_amount(5).unwrap();
+        chain.set_nft_balance(&edition, "0xseller", 7);
+        chain.set_nft_balance(&edition, "0xbuyer", 0);
+
+        let sold = signed_escrow(&wallet, 0, 20, 200000, vec![five.clone()]);
+        let oversold = signed_escrow(&wallet, 1, 20, 200000, vec![five]);
+        chain.submit(&sold).unwrap();
+        chain.submit(&oversold).unwrap();
+        assert_eq!(chain.status(&sold.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+
+        assert_eq!(chain.status(&sold.tx_hash), Some(TxStatus::Success));
+        let reverted = chain.transaction(&oversold.tx_hash).unwrap();
+        assert_eq!(reverted.status, TxStatus::Reverted);
+        assert_eq!(
+            reverted.revert_reason.as_deref(),
+            Some("sender holds too few units of an NFT it transfers")
+        );
+        assert_eq!(chain.nft_balance(&edition, "0xseller"), 2);
+        assert_eq!(chain.nft_balance(&edition, "0xbuyer"), 5);
+        assert_eq!(chain.token_balance(USDC, "0xseller"), UNSEEN_TOKEN_BALANCE + 10.0);
+        assert_eq!(chain.token_balance(USDC, "0xbuyer"), UNSEEN_TOKEN_BALANCE - 10.0);
+
+        chain.reorg(1000, false);
+        assert_eq!(chain.nft_balance(&edition, "0xseller"), 7);
+        assert_eq!(chain.nft_balance(&edition, "0xbuyer"), 0);
+        assert_eq!(chain.token_balance(USDC, "0xbuyer"), UNSEEN_TOKEN_BALANCE);
+    }
+
+    #[test]
+    fn test_escrow_the_buyer_cannot_pay_reverts() {
+        let chain = SimulatedChain::new(Duration::from_millis(200));
+        let wallet = HotWallet::generate();
+        chain.set_token_balance("usdc", "0xBuyer", 15.0).unwrap();
+
+        let paid = signed_escrow(&wallet, 0, 20, 200000, vec!["BAYC#1".parse().unwrap()]);
+        let unpaid = signed_escrow(&wallet, 1, 20, 200000, vec!["BAYC#2".parse().unwrap()]);
+        chain.submit(&paid).unwrap();
+        chain.submit(&unpaid).unwrap();
+        assert_eq!(chain.status(&paid.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+
+        assert_eq!(chain.status(&paid.tx_hash), Some(TxStatus::Success));
+        let reverted = chain.transaction(&unpaid.tx_hash).unwrap();
+        assert_eq!(
+            reverted.revert_reason.as_deref(),
+            Some("sender holds too few of a token it pays")
+        );
+        assert_eq!(chain.token_balance(USDC, "0xbuyer"), 5.0);
+    }
+
+    #[test]
+    fn test_holdings_list_recorded_tokens_of_a_collection() {
+        let chain = SimulatedChain::new(Duration::from_secs(1));
+        let edition: NftRef = "COOLCATS#42".parse().unwrap();
+        chain.set_nft_balance(&edition.with_erc1155_amount(3).unwrap(), "0xSeller", 7);
+        for token in ["BAYC#100", "BAYC#9", "bayc#20"] {
+            chain.set_nft_balance(&token.parse().unwrap(), "0xseller", 1);
+        }
+        chain.set_nft_balance(&"BAYC#20".parse().unwrap(), "0xseller", 0);
+        chain.set_nft_balance(&"BAYC#1".parse().unwrap(), "0xbuyer", 1);
+
+        let bayc = chain.nft_holdings("bayc", "0xseller");
+        let ids: Vec<&str> = bayc.iter().map(|(nft, _)| nft.token_id.as_str()).collect();
+        assert_eq!(ids, ["9", "100"]);
+
+        let coolcats = chain.nft_holdings("COOLCATS", "0xseller");
+        assert_eq!(coolcats.len(), 1);
+        assert_eq!(coolcats[0].0.standard, TokenStandard::Erc1155);
+        assert_eq!((coolcats[0].0.amount, coolcats[0].1), (1, 7));
+    }
+
+    #[test]
+    fn test_bundle_escrow_is_atomic() {
+        let chain = SimulatedChain::new(Duration::from_millis(200));
+        let wallet = HotWallet::generate();
+        let bundle: Vec<NftRef> = ["BAYC#1", "BAYC#2", "COOLCATS#3"]
+            .iter()
+            .map(|id| id.parse().unwrap())
+            .collect();
+        chain.set_nft_balance(&bundle[2], "0xseller", 0);
+
+        let missing_item = signed_escrow(&wallet, 0, 20, 400000, bundle.clone());
+        chain.submit(&missing_item).unwrap();
+        assert_eq!(chain.status(&missing_item.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+
+        let reverted = chain.transaction(&missing_item.tx_hash).unwrap();
+        assert_eq!(reverted.status, TxStatus::Reverted);
+        assert_eq!(chain.nft_balance(&bundle[0], "0xseller"), 1);
+
+        chain.set_nft_balance(&bundle[2], "0xseller", 1);
+        let complete = signed_escrow(&wallet, 1, 20, 400000, bundle.clone());
+        chain.submit(&complete).unwrap();
+        assert_eq!(chain.status(&complete.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+
+        let mined = chain.transaction(&complete.tx_hash).unwrap();
+        assert_eq!(mined.status, TxStatus::Success);
+        assert_eq!(mined.logs.len(), 4);
+        for nft in &bundle {
+            assert_eq!(chain.nft_balance(nft, "0xseller"), 0);
+            assert_eq!(chain.nft_balance(nft, "0xbuyer"), 1);
+        }
+    }
+
+    #[test]
+    fn test_swap_moves_both_sides() {
+        let chain = SimulatedChain::new(Duration::from_millis(200));
+        let wallet = HotWallet::generate();
+        let ape: NftRef = "BAYC#1".parse().unwrap();
+        let cat: NftRef = "COOLCATS#3".parse().unwrap();
+        let swap = ContractCall::Swap(SwapTransaction {
+            maker: SwapSide {
+                address: "0xmaker".to_string(),
+                nfts: vec![ape.clone()],
+                tokens: Vec::new(),
+            },
+            taker: SwapSide {
+                address: "0xtaker".to_string(),
+                nfts: vec![cat.clone()],
+                tokens: vec![TokenAmount::new(USDC, 25.0), TokenAmount::new("DAI", 5.0)],
+            },
+        });
+
+        let signed_swap = wallet.sign(UnsignedTransaction {
+            chain_id: chain.chain_id(),
+            from: wallet.address().to_string(),
+            to: "0xescrow".to_string(),
+            nonce: 0,
+            gas_limit: chain.estimate_gas(&swap),
+            max_fee_per_gas: 20,
+            max_priority_fee_per_gas: 1,
+            call: swap,
+        });
+        chain.submit(&signed_swap).unwrap();
+        assert_eq!(chain.status(&signed_swap.tx_hash), Some(TxStatus::Pending));
+        std::thread::sleep(Duration::from_millis(450));
+
+        let mined = chain.transaction(&signed_swap.tx_hash).unwrap();
+        assert_eq!(mined.status, TxStatus::Success);
+        assert_eq!(
+            mined.value,
+            vec![TokenAmount::new(USDC, 25.0), TokenAmount::new("DAI", 5.0)]
+        );
+        assert_eq!(mined.logs.len(), 4);
+        assert_eq!(chain.nft_balance(&ape, "0xtaker"), 1);
+        assert_eq!(chain.nft_balance(&ape, "0xmaker"), 0);
+        assert_eq!(chain.nft_balance(&cat, "0xmaker"), 1);
+        assert_eq!(chain.nft_balance(&cat, "0xtaker"), 0);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::ark_client::{ArkClient, ArkError};
+use crate::cache::QueryCache;
+use crate::chains::ChainRegistry;
+use crate::config::Config;
+use crate::deals::ActiveDeals;
+use crate::holds::FundHolds;
+use crate::indexer::ChainIndexer;
+use crate::ledger::Ledger;
+use crate::locks::NftLocks;
+use crate::mandates::Mandates;
+use crate::policy::SpendingPolicies;
+use crate::reconciliation::ReconciliationReports;
+use crate::records::{DealTimelines, EscrowStore};
+use crate::settlement::SettlementPolicy;
+use crate::wallet::HotWallet;
+
+/// Everything handlers and background jobs share for the lifetime of the process.
+///
+/// Built once at startup and registered as `web::Data`, so configuration is read
+/// and validated once and every chain keeps one client.
+pub struct AppState {
+    pub config: Config,
+    chains: ChainRegistry,
+    /// One client per registered chain, in registry order
+    clients: Vec<ArkClient>,
+    pub settlement: SettlementPolicy,
+    /// Operator key store signing every escrow and swap
+    pub wallet: HotWallet,
+    pub ledger: Ledger,
+    pub escrows: EscrowStore,
+    pub timelines: DealTimelines,
+    /// Deals whose escrow or swap is being executed
+    pub active_deals: ActiveDeals,
+    pub reconciliation_reports: ReconciliationReports,
+    pub spending_policies: SpendingPolicies,
+    pub mandates: Mandates,
+    pub holds: FundHolds,
+    pub nft_locks: NftLocks,
+    pub query_cache: QueryCache,
+    pub indexer: ChainIndexer,
+}
+
+impl AppState {
+    /// Connect a client to every chain of a validated configuration
+    pub fn new(config: Config) -> Result<Self, ArkError> {
+        let chains = ChainRegistry::load(&config.chains)?;
+        let clients = chains
+            .all()
+            .iter()
+            .map(|chain| ArkClient::connect(chain.clone(), &config))
+            .collect::<Result<_, _>>()?;
+
+        let query_cache = QueryCache::new(config.cache.ttl());
+        let settlement = SettlementPolicy::from_config(&config.settlement)?;
+        let wallet = HotWallet::from_config(&config.wallet)?;
+        let mandates_path = config.storage.data_dir.join("mandates.json");
+        let mandates = Mandates::from_config(&config.mandates, &mandates_path)?;
+        let holds = FundHolds::from_config(&config.holds);
+        let nft_locks = NftLocks::from_config(&config.locks);
+        let spending_policies =
+            SpendingPolicies::load(&config.storage.data_dir.join("policies.json"))?;
+        let ledger = Ledger::load(&config.storage.data_dir.join("ledger.jsonl"))?;
+        let escrows = EscrowStore::load(&config.storage.data_dir.join("escrows.json"))?;
+        let timelines = DealTimelines::load(&config.storage.data_dir.join("timelines.json"))?;
+        let indexer = ChainIndexer::load(
+            chains.all(),
+            &config.indexer,
+            &config.storage.data_dir.join("index"),
+        );
+        Ok(Self {
+            config,
+            chains,
+            clients,
+            settlement,
+            wallet,
+            ledger,
+            escrows,
+            timelines,
+            active_deals: ActiveDeals::new(),
+            reconciliation_reports: ReconciliationReports::new(),
+            spending_policies,
+            mandates,
+            holds,
+            nft_locks,
+            query_cache,
+            indexer,
+        })
+    }
+
+    pub fn chains(&self) -> &ChainRegistry {
+        &self.chains
+    }
+
+    /// Client of a chain of the registry, by name
+    pub fn client(&self, chain: &str) -> Result<&ArkClient, ArkError> {
+        self.clients
+            .iter()
+            .find(|client| client.chain_config().name == chain)
+            .ok_or_else(|| ArkError::UnknownChain(chain.to_string()))
+    }
+
+    pub fn clients(&self) -> &[ArkClient] {
+        &self.clients
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::DEFAULT_CHAIN;
+
+    #[test]
+    fn test_routes_to_the_client_of_each_registered_chain() {
+        let mut config = Config::default();
+        config.storage.data_dir =
+            std::env::temp_dir().join(format!("ark-state-{:016x}", rand::random::<u64>()));
+        let state = AppState::new(config).unwrap();
+        assert_eq!(state.clients().len(), state.chains().all().len());
+        assert_eq!(
+            state.client(DEFAULT_CHAIN).unwrap().chain_config().name,
+            DEFAULT_CHAIN
+        );
+        assert_eq!(
+            state.client("sepolia").unwrap().chain_config().chain_id,
+            11_155_111
+        );
+        assert!(matches!(
+            state.client("solana"),
+            Err(ArkError::UnknownChain(_))
+        ));
+        std::fs::remove_dir_all(&state.config.storage.data_dir).ok();
+    }
+}
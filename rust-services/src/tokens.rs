@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::ark_client::ArkError;
+use crate::wallet::validate_address;
+
+/// Token prices are paid in unless a request names another
+pub const USDC: &str = "USDC";
+
+/// Decimal places the ledger keeps for every token. Tokens with more decimals are
+/// traded in amounts no finer than this, so every amount is recorded exactly.
+pub const LEDGER_DECIMALS: u32 = 6;
+
+/// Amount of a fungible token, in whole tokens
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenAmount {
+    pub token: String,
+    pub amount: f64,
+}
+
+impl TokenAmount {
+    pub fn new(token: &str, amount: f64) -> Self {
+        Self {
+            token: token.to_string(),
+            amount,
+        }
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount, self.token)
+    }
+}
+
+/// Sum amounts per token, in order of first appearance
+pub fn totals<'a>(amounts: impl IntoIterator<Item = &'a TokenAmount>) -> Vec<TokenAmount> {
+    let mut totals: Vec<TokenAmount> = Vec::new();
+    for amount in amounts {
+        match totals.iter_mut().find(|t| t.token == amount.token) {
+            Some(total) => total.amount += amount.amount,
+            None => totals.push(amount.clone()),
+        }
+    }
+    totals
+}
+
+/// An ERC-20 token deals can be settled in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub symbol: String,
+    pub address: String,
+    pub decimals: u32,
+}
+
+impl TokenInfo {
+    pub fn new(symbol: &str, address: &str, decimals: u32) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            address: address.to_string(),
+            decimals,
+        }
+    }
+
+    /// `amount` in the token's smallest unit, at its own decimals; wide enough for an
+    /// 18-decimal token
+    pub fn base_units(&self, amount: f64) -> Result<u128, ArkError> {
+        if !amount.is_finite() || amount < 0.0 {
+            return Err(ArkError::InvalidToken(format!(
+                "{} {} is not a valid amount",
+                amount, self.symbol
+            )));
+        }
+        Ok((amount * 10f64.powi(self.decimals as i32)).round() as u128)
+    }
+
+    /// Whole tokens of `units` base units
+    pub fn amount(&self, units: u128) -> f64 {
+        units as f64 / 10f64.powi(self.decimals as i32)
+    }
+
+    /// Decimal places a deal amount of the token may have: its own decimals, at most
+    /// the ledger's
+    pub fn precision(&self) -> u32 {
+        self.decimals.min(LEDGER_DECIMALS)
+    }
+
+    /// Check that `amount` is positive and no finer than both the token and the ledger
+    /// can represent
+    pub fn validate_amount(&self, amount: f64) -> Result<(), ArkError> {
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(ArkError::InvalidToken(format!(
+                "{} {} must be a positive amount",
+                amount, self.symbol
+            )));
+        }
+        let scaled = amount * 10f64.powi(self.precision() as i32);
+        if (scaled - scaled.round()).abs() > 1e-9 * scaled.max(1.0) {
+            return Err(ArkError::InvalidToken(format!(
+                "{} {} has more than {} decimal places",
+                amount,
+                self.symbol,
+                self.precision()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Tokens the escrow contract accepts, by symbol
+#[derive(Debug, Clone)]
+pub struct TokenRegistry {
+    tokens: Vec<TokenInfo>,
+}
+
+impl Default for TokenRegistry {
+    fn default() -> Self {
+        Self {
+            tokens: vec![
+                TokenInfo::new(USDC, "0x0000000000000000000000000000000000c0ffee", 6),
+                TokenInfo::new("USDT", "0x00000000000000000000000000000000000dec0d", 6),
+            ],
+        }
+    }
+}
+
+impl TokenRegistry {
+    pub fn new(tokens: Vec<TokenInfo>) -> Self {
+        Self { tokens }
+    }
+
+    /// Register comma-separated `SYMBOL:address:decimals` entries; a listed symbol
+    /// replaces the existing one
+    pub fn with_list(mut self, list: &str) -> Result<Self, ArkError> {
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            self.register(parse_token(entry)?);
+        }
+        Ok(self)
+    }
+
+    /// Add a token, replacing one with the same symbol
+    pub fn register(&mut self, token: TokenInfo) {
+        self.tokens
+            .retain(|t| !t.symbol.eq_ignore_ascii_case(&token.symbol));
+        self.tokens.push(token);
+    }
+
+    pub fn all(&self) -> &[TokenInfo] {
+        &self.tokens
+    }
+
+    /// Look a token up by symbol, ignoring case
+    pub fn get(&self, symbol: &str) -> Result<&TokenInfo, ArkError> {
+        self.tokens
+            .iter()
+            .find(|t| t.symbol.eq_ignore_ascii_case(symbol))
+            .ok_or_else(|| ArkError::InvalidToken(format!("{} is not a supported token", symbol)))
+    }
+
+    /// Validate an amount and normalize its symbol to the registered spelling
+    pub fn amount(&self, amount: &TokenAmount) -> Result<TokenAmount, ArkError> {
+        let token = self.get(&amount.token)?;
+        token.validate_amount(amount.amount)?;
+        Ok(TokenAmount::new(&token.symbol, amount.amount))
+    }
+}
+
+fn parse_token(entry: &str) -> Result<TokenInfo, ArkError> {
+    let invalid = || {
+        ArkError::ConfigError(format!(
+            "token entry '{}' is not SYMBOL:address:decimals",
+            entry
+        ))
+    };
+    let mut parts = entry.split(':');
+    let (Some(symbol), Some(address), Some(decimals), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let decimals: u32 = decimals.parse().map_err(|_| invalid())?;
+    if symbol.is_empty() || !symbol.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(invalid());
+    }
+    validate_address(address).map_err(|e| ArkError::ConfigError(e.to_string()))?;
+    Ok(TokenInfo::new(&symbol.to_uppercase(), address, decimals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_lookup_and_amount_precision() {
+        let mut registry = TokenRegistry::default();
+        registry
+            .register(parse_token("EURC:0x1abaea1f7c830bd89acc67ec4af516284b1bc33c:6").unwrap());
+
+        assert_eq!(registry.get("usdc").unwrap().decimals, 6);
+        assert_eq!(registry.get("EURC").unwrap().decimals, 6);
+        assert!(matches!(
+            registry.get("WETH"),
+            Err(ArkError::InvalidToken(_))
+        ));
+
+        let amount = registry.amount(&TokenAmount::new("usdt", 12.5)).unwrap();
+        assert_eq!(amount, TokenAmount::new("USDT", 12.5));
+        assert!(registry.amount(&TokenAmount::new(USDC, 0.0000001)).is_err());
+        assert!(registry.amount(&TokenAmount::new(USDC, -1.0)).is_err());
+
+        assert!(parse_token("EURC:0x1234:6").is_err());
+        assert!(parse_token("EURC:0x1abaea1f7c830bd89acc67ec4af516284b1bc33c").is_err());
+    }
+
+    #[test]
+    fn test_tokens_with_more_decimals_than_the_ledger() {
+        let dai = parse_token("DAI:0x00000000000000000000000000000000000da1da:18").unwrap();
+        assert_eq!(dai.decimals, 18);
+        assert_eq!(dai.precision(), LEDGER_DECIMALS);
+
+        // Balances far beyond what 64 bits hold at 18 decimals
+        let units = dai.base_units(1_000_000.5).unwrap();
+        assert!(units > u128::from(u64::MAX));
+        assert_eq!(dai.amount(units), 1_000_000.5);
+
+        // Deal amounts are kept to the ledger's decimals
+        assert!(dai.validate_amount(12.000001).is_ok());
+        assert!(dai.validate_amount(12.0000001).is_err());
+    }
+
+    #[test]
+    fn test_totals_per_token() {
+        let amounts = [
+            TokenAmount::new(USDC, 10.0),
+            TokenAmount::new("DAI", 5.0),
+            TokenAmount::new(USDC, 2.5),
+        ];
+        assert_eq!(
+            totals(&amounts),
+            vec![TokenAmount::new(USDC, 12.5), TokenAmount::new("DAI", 5.0)]
+        );
+    }
+}
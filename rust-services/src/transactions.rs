@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ark_client::{ArkClient, ArkError};
+use crate::state::AppState;
+use crate::wallet::{ContractCall, HotWallet, SignedTransaction};
+
+/// What to do with a transaction still unconfirmed after the stuck deadline
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StuckTxAction {
+    /// Replace with the same call at a higher fee
+    SpeedUp,
+    /// Replace with a no-op self-transaction at a higher fee
+    Cancel,
+    /// Only log it; the operator speeds it up or cancels it through the API
+    Alert,
+}
+
+#[derive(Debug, Clone)]
+pub struct StuckTxPolicy {
+    pub deadline: Duration,
+    pub action: StuckTxAction,
+}
+
+impl FromStr for StuckTxAction {
+    type Err = ArkError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "speed_up" => Ok(Self::SpeedUp),
+            "cancel" => Ok(Self::Cancel),
+            "alert" => Ok(Self::Alert),
+            other => Err(ArkError::ConfigError(format!(
+                "Stuck transaction action must be speed_up, cancel or alert, got {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// A nonce of the operator account whose transaction is not mined yet
+#[derive(Debug, Clone)]
+pub struct PendingTransaction {
+    pub nonce: u64,
+    /// Every version submitted with this nonce, oldest first; the last one is current
+    pub versions: Vec<SignedTransaction>,
+    pub submitted_at: i64,
+    /// When the stuck-transaction policy was last applied to the nonce, or when it was
+    /// first submitted
+    pub acted_on_at: i64,
+}
+
+impl PendingTransaction {
+    pub fn current(&self) -> &SignedTransaction {
+        self.versions
+            .last()
+            .expect("pending transaction has a version")
+    }
+
+    pub fn original_tx_hash(&self) -> &str {
+        &self.versions[0].tx_hash
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.current().tx.call, ContractCall::Cancel)
+    }
+
+    pub fn contains(&self, tx_hash: &str) -> bool {
+        self.versions.iter().any(|v| v.tx_hash == tx_hash)
+    }
+
+    pub fn age_secs(&self) -> i64 {
+        chrono::Utc::now().timestamp() - self.submitted_at
+    }
+
+    /// Seconds since the nonce was submitted or last acted on, whichever is later
+    pub fn idle_secs(&self) -> i64 {
+        chrono::Utc::now().timestamp() - self.acted_on_at
+    }
+}
+
+/// Registry of the operator account's unmined transactions, by nonce
+pub struct PendingTransactions {
+    by_nonce: Mutex<BTreeMap<u64, PendingTransaction>>,
+}
+
+impl PendingTransactions {
+    pub fn new() -> Self {
+        Self {
+            by_nonce: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn track(&self, signed: &SignedTransaction) {
+        let now = chrono::Utc::now().timestamp();
+        self.by_nonce.lock().unwrap().insert(
+            signed.tx.nonce,
+            PendingTransaction {
+                nonce: signed.tx.nonce,
+                versions: vec![signed.clone()],
+                submitted_at: now,
+                acted_on_at: now,
+            },
+        );
+    }
+
+    /// Record that the transaction for the same nonce was replaced by `signed`
+    pub fn replace(&self, signed: &SignedTransaction) {
+        if let Some(entry) = self.by_nonce.lock().unwrap().get_mut(&signed.tx.nonce) {
+            entry.versions.push(signed.clone());
+            entry.acted_on_at = chrono::Utc::now().timestamp();
+        }
+    }
+
+    /// Restart the stuck deadline of a nonce that was acted on without replacing it
+    pub fn touch(&self, nonce: u64) {
+        if let Some(entry) = self.by_nonce.lock().unwrap().get_mut(&nonce) {
+            entry.acted_on_at = chrono::Utc::now().timestamp();
+        }
+    }
+
+    pub fn get(&self, nonce: u64) -> Option<PendingTransaction> {
+        self.by_nonce.lock().unwrap().get(&nonce).cloned()
+    }
+
+    /// Find the pending nonce a hash belongs to, including replaced versions
+    pub fn find(&self, tx_hash: &str) -> Option<PendingTransaction> {
+        self.by_nonce
+            .lock()
+            .unwrap()
+            .values()
+            .find(|p| p.contains(tx_hash))
+            .cloned()
+    }
+
+    pub fn remove(&self, nonce: u64) -> Option<PendingTransaction> {
+        self.by_nonce.lock().unwrap().remove(&nonce)
+    }
+
+    pub fn all(&self) -> Vec<PendingTransaction> {
+        self.by_nonce.lock().unwrap().values().cloned().collect()
+    }
+}
+
+impl Default for PendingTransactions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Apply the stuck-transaction policy to every pending transaction past its deadline.
+/// Returns the replacement transactions submitted.
+///
+/// The deadline runs from the latest submission or alert, so a transaction is acted
+/// on again only if it stays stuck for another full deadline.
+pub async fn recover_stuck_transactions(
+    client: &ArkClient,
+    wallet: &HotWallet,
+    policy: &StuckTxPolicy,
+) -> Vec<SignedTransaction> {
+    let mut replacements = Vec::new();
+
+    let account = client.account(wallet);
+    for pending in account.pending().all() {
+        if pending.idle_secs() < policy.deadline.as_secs() as i64 {
+            continue;
+        }
+        // Mined in the meantime: nothing is stuck
+        if client
+            .get_transaction_receipt(&pending.current().tx_hash)
+            .await
+            .is_ok()
+        {
+            continue;
+        }
+
+        // A cancellation is only ever sped up, never turned back into the original call
+        let action = match policy.action {
+            StuckTxAction::Cancel if pending.is_cancelled() => StuckTxAction::SpeedUp,
+            action => action,
+        };
+        log::warn!(
+            "Transaction {} on {} (nonce {}) unconfirmed after {}s, applying {:?}",
+            pending.current().tx_hash,
+            client.chain_config().name,
+            pending.nonce,
+            pending.age_secs(),
+            action
+        );
+
+        let replaced = match action {
+            StuckTxAction::SpeedUp => client.speed_up_transaction(wallet, pending.nonce).await,
+            StuckTxAction::Cancel => client.cancel_transaction(wallet, pending.nonce).await,
+            StuckTxAction::Alert => {
+                account.pending().touch(pending.nonce);
+                continue;
+            }
+        };
+        match replaced {
+            Ok(signed) => replacements.push(signed),
+            Err(e) => log::error!(
+                "Failed to recover stuck transaction {}: {}",
+                pending.current().tx_hash,
+                e
+            ),
+        }
+    }
+
+    replacements
+}
+
+/// Background job applying the stuck-transaction policy on every chain every `interval`
+pub async fn run_stuck_transaction_monitor(
+    state: actix_web::web::Data<AppState>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        for client in state.clients() {
+            recover_stuck_transactions(client, &state.wallet, client.stuck_tx_policy()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ark_client::EscrowTransaction;
+    use crate::chains::{ChainRegistry, DEFAULT_CHAIN};
+    use crate::config::Config;
+    use crate::settlement::{Payout, PayoutKind};
+    use crate::simulator::SimulatedChain;
+    use crate::tokens::{TokenAmount, USDC};
+    use crate::wallet::UnsignedTransaction;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_stuck_transactions_are_replaced_per_policy() {
+        let chain = Arc::new(SimulatedChain::new(Duration::from_millis(20)));
+        let config = ChainRegistry::default().get(DEFAULT_CHAIN).unwrap().clone();
+        let client = ArkClient::with_simulator(config, chain, &Config::default()).unwrap();
+        let wallet = HotWallet::generate();
+
+        for nonce in 0..2 {
+            let signed = wallet.sign(UnsignedTransaction {
+                chain_id: client.chain_config().chain_id,
+                from: wallet.address().to_string(),
+                to: "0xescrow".to_string(),
+                nonce,
+                gas_limit: 200000,
+                max_fee_per_gas: 5,
+                max_priority_fee_per_gas: 1,
+                call: ContractCall::Escrow(EscrowTransaction {
+                    buyer_address: "0xbuyer".to_string(),
+                    seller_address: "0xseller".to_string(),
+                    nfts: vec![format!("BAYC#{}", nonce).parse().unwrap()],
+                    price: TokenAmount::new(USDC, 100.0),
+                    payouts: vec![Payout {
+                        kind: PayoutKind::Seller,
+                        recipient: "0xseller".to_string(),
+                        amount: TokenAmount::new(USDC, 100.0),
+                    }],
+                }),
+            });
+            client.send_raw_transaction(&signed).await.unwrap();
+            client.account(&wallet).pending().track(&signed);
+        }
+
+        // Alerting leaves the transactions as they are
+        let policy = StuckTxPolicy {
+            deadline: Duration::ZERO,
+            action: StuckTxAction::Alert,
+        };
+        assert!(recover_stuck_transactions(&client, &wallet, &policy).await.is_empty());
+        assert!(client.account(&wallet).pending().all().iter().all(|p| p.versions.len() == 1));
+
+        let policy = StuckTxPolicy {
+            deadline: Duration::ZERO,
+            action: StuckTxAction::Cancel,
+        };
+        let replacements = recover_stuck_transactions(&client, &wallet, &policy).await;
+        assert_eq!(replacements.len(), 2);
+        assert!(client.account(&wallet).pending().all().iter().all(|p| p.is_cancelled()));
+
+        // Cancellations are sped up rather than turned back into escrows
+        let policy = StuckTxPolicy {
+            deadline: Duration::ZERO,
+            action: StuckTxAction::SpeedUp,
+        };
+        recover_stuck_transactions(&client, &wallet, &policy).await;
+        for pending in client.account(&wallet).pending().all() {
+            assert_eq!(pending.versions.len(), 3);
+            assert!(pending.is_cancelled());
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::ark_client::{ArkError, EscrowTransaction, SwapTransaction};
+use crate::config::WalletConfig;
+use crate::transactions::PendingTransactions;
+
+/// Contract call carried by a transaction
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ContractCall {
+    /// Atomic NFT-for-USDC swap through the escrow contract
+    Escrow(EscrowTransaction),
+    /// Atomic exchange of NFTs and tokens between two parties
+    Swap(SwapTransaction),
+    /// No-op used to replace (cancel) a pending transaction
+    Cancel,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnsignedTransaction {
+    /// Chain the transaction is valid on
+    pub chain_id: u64,
+    pub from: String,
+    pub to: String,
+    pub nonce: u64,
+    pub gas_limit: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+    pub call: ContractCall,
+}
+
+impl UnsignedTransaction {
+    /// Hash of the canonical encoding; this is what gets signed and the transaction id
+    pub fn hash(&self) -> [u8; 32] {
+        let encoded = serde_json::to_vec(self).expect("transaction encoding cannot fail");
+        Sha256::digest(&encoded).into()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedTransaction {
+    pub tx: UnsignedTransaction,
+    pub tx_hash: String,
+    pub signature: String,
+    pub public_key: String,
+}
+
+impl SignedTransaction {
+    /// Check the hash, the signature and that the signer owns the `from` address
+    pub fn verify(&self) -> Result<(), ArkError> {
+        let hash = self.tx.hash();
+        if self.tx_hash != format!("0x{}", hex::encode(hash)) {
+            return Err(ArkError::TransactionFailed(
+                "Transaction hash does not match contents".to_string(),
+            ));
+        }
+
+        let public_key: [u8; 32] = hex::decode(&self.public_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| ArkError::TransactionFailed("Malformed public key".to_string()))?;
+        let signature: [u8; 64] = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| ArkError::TransactionFailed("Malformed signature".to_string()))?;
+
+        let verifying_key = VerifyingKey::from_bytes(&public_key)
+            .map_err(|e| ArkError::TransactionFailed(format!("Invalid public key: {}", e)))?;
+        if address_from_public_key(&verifying_key) != self.tx.from {
+            return Err(ArkError::TransactionFailed(
+                "Signer does not match sender address".to_string(),
+            ));
+        }
+
+        verifying_key
+            .verify(&hash, &Signature::from_bytes(&signature))
+            .map_err(|_| ArkError::TransactionFailed("Invalid transaction signature".to_string()))
+    }
+}
+
+/// Check that `address` is a 20-byte hex account address (`0x` + 40 hex digits)
+pub fn validate_address(address: &str) -> Result<(), ArkError> {
+    let valid = address.len() == 42
+        && address.starts_with("0x")
+        && address[2..].chars().all(|c| c.is_ascii_hexdigit());
+    if valid {
+        Ok(())
+    } else {
+        Err(ArkError::InvalidAddress(address.to_string()))
+    }
+}
+
+/// Parse a hex-encoded 32-byte Ed25519 seed, with or without a `0x` prefix
+pub fn parse_secret_key(secret: &str) -> Result<SigningKey, ArkError> {
+    let secret = secret.trim().trim_start_matches("0x");
+    let bytes: [u8; 32] = hex::decode(secret)
+        .map_err(|e| ArkError::ConfigError(format!("Operator key is not valid hex: {}", e)))?
+        .try_into()
+        .map_err(|_| ArkError::ConfigError("Operator key must be 32 bytes".to_string()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Derive an account address from an Ed25519 public key (first 20 bytes of its SHA-256)
+pub fn address_from_public_key(key: &VerifyingKey) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    format!("0x{}", hex::encode(&digest[..20]))
+}
+
+/// Operator hot wallet that signs escrow transactions locally.
+///
+/// The same key and address are used on every chain, but each chain has its own
+/// nonce sequence and pending transactions, kept in a per-chain `ChainAccount`.
+pub struct HotWallet {
+    signing_key: SigningKey,
+    address: String,
+    accounts: std::sync::Mutex<HashMap<String, Arc<ChainAccount>>>,
+}
+
+/// The operator account on one chain.
+///
+/// Tracks the account's next nonce. Submissions hold the nonce lock from signing
+/// until the node accepts the transaction, so concurrent escrows get consecutive
+/// nonces with no gaps and no duplicates. Accepted but unmined transactions are
+/// kept in its pending registry.
+#[derive(Default)]
+pub struct ChainAccount {
+    next_nonce: Mutex<Option<u64>>,
+    pending: PendingTransactions,
+}
+
+impl ChainAccount {
+    /// Unmined transactions sent from this account
+    pub fn pending(&self) -> &PendingTransactions {
+        &self.pending
+    }
+
+    /// Take exclusive use of the account nonce until the guard is dropped
+    pub async fn lock_nonce(&self) -> NonceGuard<'_> {
+        NonceGuard {
+            slot: self.next_nonce.lock().await,
+        }
+    }
+}
+
+impl HotWallet {
+    /// Load the operator key from `ARK_PRIVATE_KEY` (hex-encoded 32-byte Ed25519 seed).
+    ///
+    /// Without a configured key an ephemeral one is generated, which is only
+    /// useful against the simulated testnet.
+    pub fn from_config(config: &WalletConfig) -> Result<Self, ArkError> {
+        match &config.private_key {
+            Some(secret) => Self::from_secret_hex(secret),
+            None => {
+                let wallet = Self::generate();
+                log::warn!(
+                    "ARK_PRIVATE_KEY not set, using ephemeral operator wallet {}",
+                    wallet.address
+                );
+                Ok(wallet)
+            }
+        }
+    }
+
+    pub fn from_secret_hex(secret: &str) -> Result<Self, ArkError> {
+        Ok(Self::from_signing_key(parse_secret_key(secret)?))
+    }
+
+    pub fn generate() -> Self {
+        Self::from_signing_key(SigningKey::from_bytes(&rand::random::<[u8; 32]>()))
+    }
+
+    fn from_signing_key(signing_key: SigningKey) -> Self {
+        let address = address_from_public_key(&signing_key.verifying_key());
+        log::info!("Operator wallet loaded: {}", address);
+        Self {
+            signing_key,
+            address,
+            accounts: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The operator account on `chain`
+    pub fn account(&self, chain: &str) -> Arc<ChainAccount> {
+        self.accounts
+            .lock()
+            .unwrap()
+            .entry(chain.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn sign(&self, tx: UnsignedTransaction) -> SignedTransaction {
+        let hash = tx.hash();
+        let signature = self.signing_key.sign(&hash);
+        SignedTransaction {
+            tx_hash: format!("0x{}", hex::encode(hash)),
+            signature: hex::encode(signature.to_bytes()),
+            public_key: hex::encode(self.signing_key.verifying_key().as_bytes()),
+            tx,
+        }
+    }
+}
+
+/// Exclusive access to the operator account's next nonce
+pub struct NonceGuard<'a> {
+    slot: MutexGuard<'a, Option<u64>>,
+}
+
+impl NonceGuard<'_> {
+    /// The next nonce to use, or `None` if it must be fetched from the chain first
+    pub fn next(&self) -> Option<u64> {
+        *self.slot
+    }
+
+    /// Record that `nonce` was accepted by the node
+    pub fn consume(&mut self, nonce: u64) {
+        *self.slot = Some(nonce + 1);
+    }
+
+    /// Forget the cached nonce so the next submission re-syncs from the chain
+    pub fn reset(&mut self) {
+        *self.slot = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::ARK_TESTNET_CHAIN_ID;
+    use crate::tokens::{TokenAmount, USDC};
+
+    fn unsigned(from: &str, nonce: u64) -> UnsignedTransaction {
+        UnsignedTransaction {
+            chain_id: ARK_TESTNET_CHAIN_ID,
+            from: from.to_string(),
+            to: "0xescrow".to_string(),
+            nonce,
+            gas_limit: 250000,
+            max_fee_per_gas: 22,
+            max_priority_fee_per_gas: 2,
+            call: ContractCall::Escrow(EscrowTransaction {
+                buyer_address: "0xbuyer".to_string(),
+                seller_address: "0xseller".to_string(),
+                nfts: vec!["BAYC#1234".parse().unwrap()],
+                price: TokenAmount::new(USDC, 100.0),
+                payouts: Vec::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_signed_transaction_verifies() {
+        let wallet = HotWallet::generate();
+        let signed = wallet.sign(unsigned(wallet.address(), 0));
+        assert!(signed.verify().is_ok());
+    }
+
+    #[test]
+    fn test_tampered_transaction_rejected() {
+        let wallet = HotWallet::generate();
+        let mut signed = wallet.sign(unsigned(wallet.address(), 0));
+        signed.tx.nonce = 1;
+        assert!(signed.verify().is_err());
+
+        // Replaying on another chain changes what was signed
+        let mut replayed = wallet.sign(unsigned(wallet.address(), 0));
+        replayed.tx.chain_id = 11_155_111;
+        assert!(replayed.verify().is_err());
+
+        let other = HotWallet::generate();
+        let forged = other.sign(unsigned(wallet.address(), 0));
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn test_address_validation() {
+        let wallet = HotWallet::generate();
+        assert!(validate_address(wallet.address()).is_ok());
+        assert!(validate_address(&wallet.address().to_uppercase().replace("0X", "0x")).is_ok());
+        let too_long = format!("{}00", wallet.address());
+        for bad in ["", "0x123", "0xbuyer", &wallet.address()[2..], &too_long] {
+            assert!(matches!(validate_address(bad), Err(ArkError::InvalidAddress(_))));
+        }
+    }
+
+    #[test]
+    fn test_invalid_operator_key_is_config_error() {
+        assert!(matches!(
+            HotWallet::from_secret_hex("not-hex"),
+            Err(ArkError::ConfigError(_))
+        ));
+        assert!(matches!(
+            HotWallet::from_secret_hex("abcd"),
+            Err(ArkError::ConfigError(_))
+        ));
+    }
+}